@@ -0,0 +1,374 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    path::Path,
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+use symphonia::{
+    core::{codecs::CodecParameters, io::MediaSourceStream},
+    default::{get_codecs, get_probe},
+};
+
+use crate::{
+    cue::{CueTrack, find_cue},
+    tags::{Tags, read_tags},
+    template::Field,
+};
+
+/// Track numbers above this are taken for garbage, such as a year, rather than gaps
+const MAX_TRACK_NUMBER: u32 = 999;
+/// Tracks after the highest numbered one a track total may report missing
+const MAX_MISSING_AFTER_LAST: u32 = 20;
+
+/// Tags every track of an album should agree on
+const SHARED_TAGS: &[Field] = &[Field::AlbumArtist, Field::Album, Field::Date];
+
+/// A song as part of an album
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Track<'a> {
+    /// Id of the track in the [`LibraryIndex`](crate::index::LibraryIndex) it comes from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub path: &'a Path,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disc: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub codec: &'static str,
+    /// Duration in seconds, if the container knows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// Start in seconds within `path`, for the virtual tracks of a CUE image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<f64>,
+    #[serde(skip)]
+    pub tags: Tags,
+}
+
+impl<'a> Track<'a> {
+    pub fn new(path: &'a Path, tags: Tags, codec: &'static str, duration: Option<f64>) -> Self {
+        Self {
+            id: None,
+            path,
+            disc: Field::Disc.value(&tags).and_then(|d| d.parse().ok()),
+            number: Field::Track.value(&tags).and_then(|t| t.parse().ok()),
+            title: Field::Title.value(&tags),
+            codec,
+            duration,
+            offset: None,
+            tags,
+        }
+    }
+
+    /// Probe the song at `path`
+    pub fn read(path: &'a Path) -> crate::Result<Self> {
+        let mut probed = get_probe().format(
+            &Default::default(),
+            MediaSourceStream::new(Box::new(File::open(path)?), Default::default()),
+            &Default::default(),
+            &Default::default(),
+        )?;
+        let tags = read_tags(&mut probed, true);
+        let params = probed.format.default_track().map(|t| &t.codec_params);
+        Ok(Self::new(
+            path,
+            tags,
+            codec_name(params),
+            params.and_then(duration),
+        ))
+    }
+
+    /// The virtual tracks of the CUE sheet beside the song, or just the song without one
+    pub fn split_cue(self) -> Vec<Self> {
+        let Some((_, sheet)) = find_cue(self.path) else {
+            return vec![self];
+        };
+        let file = sheet
+            .file_for(self.path)
+            .expect("find_cue matched the file");
+        let starts: Vec<Option<f64>> = file.tracks.iter().map(CueTrack::start_seconds).collect();
+        sheet
+            .track_tags(file, &self.tags)
+            .into_iter()
+            .enumerate()
+            .map(|(i, tags)| {
+                let mut track = Self::new(self.path, tags, self.codec, None);
+                track.offset = starts[i];
+                let end = match starts.get(i + 1) {
+                    Some(end) => *end,
+                    None => self.duration,
+                };
+                track.duration = end.zip(track.offset).map(|(end, start)| end - start);
+                track
+            })
+            .collect()
+    }
+
+    pub fn field(&self, field: Field) -> Option<String> {
+        field.value(&self.tags)
+    }
+
+    /// The number of tracks on the track's disc, from `TrackTotal` or `3/12` style numbering
+    pub fn total(&self) -> Option<u32> {
+        let total = self
+            .tags
+            .get("TrackTotal")
+            .and_then(|t| t.trim().parse().ok());
+        total.or_else(|| {
+            let (_, total) = self.tags.get("TrackNumber")?.split_once('/')?;
+            total.trim().parse().ok()
+        })
+    }
+}
+
+pub(crate) fn codec_name(params: Option<&CodecParameters>) -> &'static str {
+    params
+        .and_then(|p| get_codecs().get_codec(p.codec))
+        .map_or("unknown", |c| c.short_name)
+}
+
+/// Duration of a stream in seconds
+pub(crate) fn duration(params: &CodecParameters) -> Option<f64> {
+    let frames = params.n_frames?;
+    match params.time_base {
+        Some(tb) => {
+            let time = tb.calc_time(frames);
+            Some(time.seconds as f64 + time.frac)
+        }
+        None => Some(frames as f64 / params.sample_rate? as f64),
+    }
+}
+
+/// Probe every song in parallel, splitting CUE images into their virtual tracks
+pub fn read_tracks<'a>(songs: &'a [impl AsRef<Path> + Sync]) -> crate::Result<Vec<Track<'a>>> {
+    let tracks: Vec<Vec<Track>> = songs
+        .par_iter()
+        .map(|s| Ok(Track::read(s.as_ref())?.split_cue()))
+        .collect::<crate::Result<_>>()?;
+    Ok(tracks.into_iter().flatten().collect())
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Album<'a> {
+    pub name: String,
+    /// The album artist, or the artist every track shares, or `Various Artists`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<String>,
+    /// The directory holding the album, above any disc directories
+    pub dir: &'a Path,
+    /// Total duration in seconds
+    pub duration: f64,
+    /// Track numbers missing up to the track total or last track, as `disc:track` on multi disc albums
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<String>,
+    /// Fields the tracks disagree on
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub inconsistent: Vec<&'static str>,
+    pub tracks: Vec<Track<'a>>,
+}
+
+impl<'a> Album<'a> {
+    fn new(dir: &'a Path, mut tracks: Vec<Track<'a>>) -> Self {
+        tracks.sort_by(|a, b| {
+            (a.disc.unwrap_or(1), a.number, a.path).cmp(&(b.disc.unwrap_or(1), b.number, b.path))
+        });
+        let common = |field: Field| most_common(tracks.iter().filter_map(|t| t.field(field)));
+        let artists: BTreeSet<_> = tracks
+            .iter()
+            .filter_map(|t| t.field(Field::Artist))
+            .collect();
+        let artist = common(Field::AlbumArtist).or_else(|| match artists.len() {
+            0 => None,
+            1 => artists.first().cloned(),
+            _ => Some("Various Artists".to_string()),
+        });
+        let mut album = Self {
+            name: common(Field::Album).unwrap_or_default(),
+            artist,
+            year: common(Field::Year),
+            dir,
+            duration: tracks.iter().filter_map(|t| t.duration).sum(),
+            missing: Vec::new(),
+            inconsistent: Vec::new(),
+            tracks,
+        };
+        album.missing = album.missing_tracks();
+        album.inconsistent = album.inconsistent_fields();
+        album
+    }
+
+    fn missing_tracks(&self) -> Vec<String> {
+        let mut discs: BTreeMap<u32, (BTreeSet<u32>, u32)> = BTreeMap::new();
+        for track in &self.tracks {
+            let Some(number) = track.number.filter(|n| *n <= MAX_TRACK_NUMBER) else {
+                continue;
+            };
+            let (numbers, total) = discs.entry(track.disc.unwrap_or(1)).or_default();
+            numbers.insert(number);
+            *total = (*total).max(track.total().unwrap_or(0));
+        }
+        let several = discs.len() > 1;
+        discs
+            .into_iter()
+            .flat_map(|(disc, (numbers, total))| {
+                let highest = numbers.last().copied().unwrap_or_default();
+                let last = total.min(highest + MAX_MISSING_AFTER_LAST).max(highest);
+                (1..=last)
+                    .filter(move |n| !numbers.contains(n))
+                    .map(move |n| match several {
+                        true => format!("{disc}:{n}"),
+                        false => n.to_string(),
+                    })
+            })
+            .collect()
+    }
+
+    fn inconsistent_fields(&self) -> Vec<&'static str> {
+        let mut fields: Vec<&'static str> = [Field::AlbumArtist, Field::Year]
+            .into_iter()
+            .filter(|field| {
+                let values: BTreeSet<_> = self.tracks.iter().map(|t| t.field(*field)).collect();
+                values.len() > 1
+            })
+            .map(Field::name)
+            .collect();
+        let codecs: BTreeSet<_> = self.tracks.iter().map(|t| t.codec).collect();
+        if codecs.len() > 1 {
+            fields.push("codec");
+        }
+        fields
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty() && self.tracks.iter().all(|t| t.number.is_some())
+    }
+
+    /// `Artist - Album`, or just the album without an artist
+    pub fn title(&self) -> String {
+        match &self.artist {
+            Some(artist) => format!("{artist} - {}", self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// The most common value of each tag the album's tracks should share
+    pub fn shared_tags(&self) -> Tags {
+        SHARED_TAGS
+            .iter()
+            .filter_map(|field| {
+                let value =
+                    most_common(self.tracks.iter().filter_map(|t| t.tags.get(field.tag())))?;
+                Some((field.tag().to_string(), value.clone()))
+            })
+            .collect()
+    }
+}
+
+/// A library's songs grouped into albums
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Library<'a> {
+    pub albums: Vec<Album<'a>>,
+    /// Songs without an album tag
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub loose: Vec<Track<'a>>,
+}
+
+impl<'a> Library<'a> {
+    pub fn new(tracks: Vec<Track<'a>>) -> Self {
+        let (albums, loose) = group_albums(tracks);
+        Self { albums, loose }
+    }
+}
+
+/// Group tracks by album tag and directory, with disc directories such as `CD1` counting as
+/// their parent. Tracks without an album tag are returned separately.
+pub fn group_albums<'a>(tracks: Vec<Track<'a>>) -> (Vec<Album<'a>>, Vec<Track<'a>>) {
+    let mut groups: BTreeMap<(&Path, String), Vec<Track>> = BTreeMap::new();
+    let mut loose = Vec::new();
+    for track in tracks {
+        match (album_dir(track.path), track.field(Field::Album)) {
+            (Some(dir), Some(album)) => groups
+                .entry((dir, album.to_lowercase()))
+                .or_default()
+                .push(track),
+            _ => loose.push(track),
+        }
+    }
+    let albums = groups
+        .into_iter()
+        .map(|((dir, _), tracks)| Album::new(dir, tracks))
+        .collect();
+    (albums, loose)
+}
+
+fn album_dir(path: &Path) -> Option<&Path> {
+    let dir = path.parent()?;
+    let name = dir.file_name()?.to_string_lossy().to_lowercase();
+    let rest = ["cd", "disc", "disk"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix));
+    match rest {
+        Some(n) if n.trim().chars().all(|c| c.is_ascii_digit()) && !n.trim().is_empty() => {
+            dir.parent().or(Some(dir))
+        }
+        _ => Some(dir),
+    }
+}
+
+/// The most common value, preferring the first seen on ties
+pub(crate) fn most_common<T: PartialEq>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts: Vec<(T, usize)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map(|(v, _)| v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(number: &str, total: Option<&str>) -> Track<'static> {
+        let mut tags = Tags::from([("TrackNumber".to_string(), number.to_string())]);
+        if let Some(total) = total {
+            tags.insert("TrackTotal".to_string(), total.to_string());
+        }
+        Track::new(Path::new("album/song.flac"), tags, "flac", None)
+    }
+
+    #[test]
+    fn gaps_and_missing_last_tracks() {
+        let album = Album::new(
+            Path::new("album"),
+            vec![track("1", Some("5")), track("3", None)],
+        );
+        assert_eq!(album.missing, ["2", "4", "5"]);
+    }
+
+    #[test]
+    fn garbage_numbers_and_totals_are_bounded() {
+        let tracks = vec![
+            track("1", Some(&u32::MAX.to_string())),
+            track("2024", None),
+            track(&u32::MAX.to_string(), None),
+        ];
+        let album = Album::new(Path::new("album"), tracks);
+        assert_eq!(album.missing.len(), MAX_MISSING_AFTER_LAST as usize);
+    }
+}
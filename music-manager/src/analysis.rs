@@ -0,0 +1,368 @@
+use std::path::{Path, PathBuf};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    decode::decode_samples,
+    lossless::{LosslessCheck, Spectrum},
+};
+
+/// Samples quieter than -60 dBFS count as silence
+const SILENCE_LEVEL: f32 = 0.001;
+/// Samples at least this loud are at full scale
+const CLIP_LEVEL: f32 = 0.9999;
+/// Consecutive full scale samples in a channel needed to count as clipping
+const CLIP_RUN: u32 = 3;
+/// Largest difference between channels of a mono song stored as stereo, about 2 LSB at 16 bit
+const FAKE_STEREO_LEVEL: f32 = 2.0 / 32768.0;
+/// Length of the blocks the dynamic range is measured over
+const BLOCK_SECONDS: u32 = 3;
+/// Share of the loudest blocks the dynamic range is measured against
+const LOUDEST_BLOCKS: f64 = 0.2;
+
+const MAX_PADDING_SECONDS: f64 = 3.0;
+const MAX_DC_OFFSET: f64 = 0.01;
+const MIN_DYNAMIC_RANGE: f64 = 6.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Problem {
+    /// Runs of full scale samples
+    Clipped,
+    /// More than a few seconds of silence at either end
+    Padded,
+    /// A channel whose average is noticeably off zero
+    DcOffset,
+    /// Identical channels
+    FakeStereo,
+    /// Little difference between the peak and the loudest passages
+    Compressed,
+    /// A lossless file whose spectrum is cut off like a lossy encoder's
+    FakeLossless,
+}
+
+impl Problem {
+    pub const ALL: &[Problem] = &[
+        Self::Clipped,
+        Self::Padded,
+        Self::DcOffset,
+        Self::FakeStereo,
+        Self::Compressed,
+        Self::FakeLossless,
+    ];
+}
+
+impl std::str::FromStr for Problem {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|p| p.to_string() == s.to_ascii_lowercase())
+            .copied()
+            .ok_or_else(|| format!("Unknown problem '{s}'"))
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Clipped => write!(f, "clipped"),
+            Self::Padded => write!(f, "padded"),
+            Self::DcOffset => write!(f, "dc-offset"),
+            Self::FakeStereo => write!(f, "fake-stereo"),
+            Self::Compressed => write!(f, "compressed"),
+            Self::FakeLossless => write!(f, "fake-lossless"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Analysis {
+    /// Duration in seconds
+    pub duration: f64,
+    /// Silence before the first audible sample in seconds
+    pub leading_silence: f64,
+    /// Silence after the last audible sample in seconds
+    pub trailing_silence: f64,
+    /// Samples in runs of full scale samples
+    pub clipped_samples: u64,
+    /// Largest average of a channel, relative to full scale
+    pub dc_offset: f64,
+    /// Peak to loudest passages ratio in dB, similar to the DR meter
+    pub dynamic_range: f64,
+    /// Mono audio stored as stereo
+    pub fake_stereo: bool,
+    /// Spectral check of lossless songs, if asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lossless: Option<LosslessCheck>,
+}
+
+impl Analysis {
+    pub fn problems(&self) -> Vec<Problem> {
+        Problem::ALL
+            .iter()
+            .copied()
+            .filter(|p| match p {
+                Problem::Clipped => self.clipped_samples > 0,
+                Problem::Padded => {
+                    self.leading_silence.max(self.trailing_silence) > MAX_PADDING_SECONDS
+                }
+                Problem::DcOffset => self.dc_offset > MAX_DC_OFFSET,
+                Problem::FakeStereo => self.fake_stereo,
+                Problem::Compressed => self.dynamic_range < MIN_DYNAMIC_RANGE,
+                Problem::FakeLossless => self.lossless.is_some_and(|l| l.is_suspicious()),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SongAnalysis<'a> {
+    pub path: &'a Path,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<Problem>,
+    pub analysis: Analysis,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AnalysisReport<'a> {
+    pub songs: Vec<SongAnalysis<'a>>,
+}
+
+/// Analyze every song in parallel, skipping songs without audio.
+/// With `lossless_check` the spectrum of lossless songs is checked for lossy origins.
+pub fn analyze_songs(songs: &[PathBuf], lossless_check: bool) -> crate::Result<AnalysisReport<'_>> {
+    let songs = songs
+        .par_iter()
+        .map(|s| -> crate::Result<_> {
+            let Some(analysis) = analyze(s, lossless_check)? else {
+                warn!("Skipping {}: no audio", s.display());
+                return Ok(None);
+            };
+            Ok(Some(SongAnalysis {
+                path: s,
+                problems: analysis.problems(),
+                analysis,
+            }))
+        })
+        .filter_map(Result::transpose)
+        .collect::<crate::Result<_>>()?;
+    Ok(AnalysisReport { songs })
+}
+
+/// Decode a song and measure its silence, clipping, DC offset and dynamic range
+pub fn analyze(path: &Path, lossless_check: bool) -> crate::Result<Option<Analysis>> {
+    let mut frames = 0u64;
+    let mut first_audible = None;
+    let mut last_audible = 0u64;
+    let mut clipped = 0u64;
+    let mut peak = 0f32;
+    let mut sums: Vec<f64> = Vec::new();
+    let mut runs: Vec<u32> = Vec::new();
+    let mut fake_stereo = true;
+    let mut blocks: Vec<f64> = Vec::new();
+    let mut block_squares = 0f64;
+    let mut block_frames = 0u64;
+    let mut spectrum = None;
+
+    let info = decode_samples(path, |info, samples| {
+        let channels = info.channels;
+        let block_len = (info.sample_rate * BLOCK_SECONDS) as u64;
+        sums.resize(channels, 0.0);
+        runs.resize(channels, 0);
+        if lossless_check && info.lossless {
+            spectrum
+                .get_or_insert_with(|| Spectrum::new(info.sample_rate))
+                .push(samples, channels);
+        }
+        for frame in samples.chunks_exact(channels) {
+            if frame.iter().any(|s| s.abs() > SILENCE_LEVEL) {
+                first_audible.get_or_insert(frames);
+                last_audible = frames;
+            }
+            for (channel, sample) in frame.iter().enumerate() {
+                let level = sample.abs();
+                peak = peak.max(level);
+                sums[channel] += *sample as f64;
+                block_squares += (*sample as f64).powi(2);
+                if level >= CLIP_LEVEL {
+                    runs[channel] += 1;
+                    clipped += match runs[channel] {
+                        CLIP_RUN => CLIP_RUN as u64,
+                        run if run > CLIP_RUN => 1,
+                        _ => 0,
+                    };
+                } else {
+                    runs[channel] = 0;
+                }
+            }
+            if channels != 2 || (frame[0] - frame[1]).abs() > FAKE_STEREO_LEVEL {
+                fake_stereo = false;
+            }
+            frames += 1;
+            block_frames += 1;
+            if block_frames == block_len {
+                blocks.push((block_squares / (block_len * channels as u64) as f64).sqrt());
+                (block_squares, block_frames) = (0.0, 0);
+            }
+        }
+        Ok(())
+    })?;
+    let Some(info) = info else {
+        return Ok(None);
+    };
+    if block_frames > 0 {
+        blocks.push((block_squares / (block_frames * info.channels as u64) as f64).sqrt());
+    }
+
+    let rate = info.sample_rate as f64;
+    let (leading, trailing) = match first_audible {
+        Some(first) => (first, frames - last_audible - 1),
+        None => (frames, 0),
+    };
+    Ok(Some(Analysis {
+        duration: frames as f64 / rate,
+        leading_silence: leading as f64 / rate,
+        trailing_silence: trailing as f64 / rate,
+        clipped_samples: clipped,
+        dc_offset: sums
+            .iter()
+            .map(|sum| (sum / frames.max(1) as f64).abs())
+            .fold(0.0, f64::max),
+        dynamic_range: dynamic_range(&mut blocks, peak as f64),
+        fake_stereo: fake_stereo && frames > 0,
+        lossless: spectrum.and_then(Spectrum::check),
+    }))
+}
+
+/// Ratio in dB of the peak to the RMS of the loudest blocks
+fn dynamic_range(blocks: &mut [f64], peak: f64) -> f64 {
+    blocks.sort_unstable_by(|a, b| b.total_cmp(a));
+    let loudest = &blocks[..((blocks.len() as f64 * LOUDEST_BLOCKS).ceil() as usize)];
+    let rms = (loudest.iter().map(|b| b * b).sum::<f64>() / loudest.len().max(1) as f64).sqrt();
+    if rms == 0.0 || peak == 0.0 {
+        return 0.0;
+    }
+    20.0 * (peak / rms).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use super::*;
+    use crate::cue::write_flac;
+
+    const RATE: u32 = 8000;
+
+    /// `seconds` of a 440 Hz sine at `amplitude`, relative to full scale
+    fn sine(seconds: f64, amplitude: f64) -> Vec<f64> {
+        (0..(seconds * RATE as f64) as usize)
+            .map(|i| (i as f64 * 440.0 * TAU / RATE as f64).sin() * amplitude)
+            .collect()
+    }
+
+    /// Analyze interleaved `samples` written as a 16 bit FLAC song
+    fn analyze_pcm(samples: &[f64], channels: usize) -> Analysis {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+        let samples: Vec<i32> = samples
+            .iter()
+            .map(|s| (s * 32768.0).round().clamp(-32768.0, 32767.0) as i32)
+            .collect();
+        write_flac(&path, &samples, channels, 16, RATE).unwrap();
+        analyze(&path, false).unwrap().unwrap()
+    }
+
+    #[test]
+    fn clipped_sines_are_clipped() {
+        let clean = analyze_pcm(&sine(1.0, 0.5), 1);
+        assert_eq!(clean.clipped_samples, 0);
+        assert!(!clean.problems().contains(&Problem::Clipped));
+
+        let clipped = analyze_pcm(&sine(1.0, 1.5), 1);
+        assert!(clipped.clipped_samples > 0);
+        assert!(clipped.problems().contains(&Problem::Clipped));
+    }
+
+    #[test]
+    fn silence_at_either_end_is_padding() {
+        let samples = [
+            vec![0.0; 4 * RATE as usize],
+            sine(1.0, 0.5),
+            vec![0.0; RATE as usize],
+        ]
+        .concat();
+        let analysis = analyze_pcm(&samples, 1);
+        assert!(
+            (analysis.duration - 6.0).abs() < 1e-9,
+            "{}",
+            analysis.duration
+        );
+        assert!(
+            (analysis.leading_silence - 4.0).abs() < 0.01,
+            "{}",
+            analysis.leading_silence
+        );
+        assert!(
+            (analysis.trailing_silence - 1.0).abs() < 0.01,
+            "{}",
+            analysis.trailing_silence
+        );
+        assert!(analysis.problems().contains(&Problem::Padded));
+
+        let trimmed = analyze_pcm(&sine(1.0, 0.5), 1);
+        assert!(trimmed.leading_silence < 0.01 && trimmed.trailing_silence < 0.01);
+        assert!(!trimmed.problems().contains(&Problem::Padded));
+    }
+
+    #[test]
+    fn biased_signals_have_a_dc_offset() {
+        let biased: Vec<f64> = sine(1.0, 0.5).iter().map(|s| s + 0.05).collect();
+        let analysis = analyze_pcm(&biased, 1);
+        assert!(
+            (analysis.dc_offset - 0.05).abs() < 0.001,
+            "{}",
+            analysis.dc_offset
+        );
+        assert!(analysis.problems().contains(&Problem::DcOffset));
+
+        let centered = analyze_pcm(&sine(1.0, 0.5), 1);
+        assert!(centered.dc_offset < 0.001, "{}", centered.dc_offset);
+        assert!(!centered.problems().contains(&Problem::DcOffset));
+    }
+
+    #[test]
+    fn steady_signals_have_little_dynamic_range() {
+        // a sine peaks 3 dB above its RMS
+        let steady = analyze_pcm(&sine(9.0, 0.5), 1);
+        assert!(
+            (steady.dynamic_range - 3.01).abs() < 0.1,
+            "{}",
+            steady.dynamic_range
+        );
+        assert!(steady.problems().contains(&Problem::Compressed));
+
+        let mut dynamic = sine(9.0, 0.05);
+        dynamic[RATE as usize] = 0.9;
+        let dynamic = analyze_pcm(&dynamic, 1);
+        assert!(dynamic.dynamic_range > 20.0, "{}", dynamic.dynamic_range);
+        assert!(!dynamic.problems().contains(&Problem::Compressed));
+    }
+
+    #[test]
+    fn identical_channels_are_fake_stereo() {
+        let mono = sine(1.0, 0.5);
+        let doubled: Vec<f64> = mono.iter().flat_map(|s| [*s, *s]).collect();
+        assert!(analyze_pcm(&doubled, 2).fake_stereo);
+        let panned: Vec<f64> = mono.iter().flat_map(|s| [*s, s * 0.5]).collect();
+        assert!(!analyze_pcm(&panned, 2).fake_stereo);
+        assert!(!analyze_pcm(&mono, 1).fake_stereo);
+    }
+}
@@ -0,0 +1,392 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error, WalkOptions,
+    lint::{LintConfig, RequiredTags},
+    sanitize::Profile,
+    template::{DEFAULT_TEMPLATE, Template},
+};
+
+/// Per library settings, read from this file in the library root
+pub const CONFIG_FILE: &str = ".songman.toml";
+
+/// System wide settings, overridden by the user's and the library's
+pub const SYSTEM_CONFIG: &str = "/etc/songman/config.toml";
+
+/// Table holding the named profiles of a config file
+const PROFILES: &str = "profiles";
+
+/// Key naming the profile used when none is asked for
+const PROFILE: &str = "profile";
+
+/// Origin of the values nothing configures
+pub const DEFAULT_ORIGIN: &str = "default";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LibraryConfig {
+    /// Library root used when a command is given none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<PathBuf>,
+    /// Library roots commands can be given by name, such as `main` or `incoming`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub roots: BTreeMap<String, PathBuf>,
+    pub log_level: String,
+    /// Path template songs are sorted by
+    pub template: Template,
+    /// Filename sanitization profile
+    pub sanitize: Profile,
+    /// Maximum length of a path component in bytes
+    pub max_length: usize,
+    pub required_tags: RequiredTags,
+    pub lint: LintConfig,
+    pub walk: WalkOptions,
+    pub duplicates: DuplicatePolicy,
+    pub serve: ServeConfig,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            root: None,
+            roots: BTreeMap::new(),
+            log_level: "info".to_string(),
+            template: DEFAULT_TEMPLATE
+                .parse()
+                .expect("The default template is valid"),
+            sanitize: Profile::default(),
+            max_length: 255,
+            required_tags: RequiredTags::default(),
+            lint: LintConfig::default(),
+            walk: WalkOptions::default(),
+            duplicates: DuplicatePolicy::default(),
+            serve: ServeConfig::default(),
+        }
+    }
+}
+
+/// The kinds of duplicates looked for when a command names none
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DuplicatePolicy {
+    pub metadata: bool,
+    pub filename: bool,
+    pub stream: bool,
+}
+
+impl DuplicatePolicy {
+    pub fn is_empty(&self) -> bool {
+        !(self.metadata || self.filename || self.stream)
+    }
+}
+
+/// Accounts of `serve`, needed by its Subsonic API and by its JSON API on addresses other
+/// than loopback
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServeConfig {
+    /// Passwords by user name, kept in the clear as token authentication needs them
+    pub users: BTreeMap<String, String>,
+}
+
+impl LibraryConfig {
+    /// Read the [`CONFIG_FILE`] of the library at `root`, the defaults if there is none
+    pub fn load(root: &Path) -> crate::Result<Self> {
+        let path = root.join(CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        toml::from_str(&std::fs::read_to_string(&path)?)
+            .map_err(|source| Error::Config { path, source })
+    }
+}
+
+/// A [`LibraryConfig`] merged from every config file, remembering where each value came from
+#[derive(Debug, Clone)]
+pub struct LayeredConfig {
+    pub config: LibraryConfig,
+    /// The profile applied on top of the files, if any
+    pub profile: Option<String>,
+    merged: toml::Table,
+    /// Dotted key to the file, or [`DEFAULT_ORIGIN`], setting it
+    origins: BTreeMap<String, String>,
+}
+
+/// A value of the effective configuration
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Setting {
+    pub key: String,
+    #[cfg_attr(feature = "schema", schemars(with = "serde_json::Value"))]
+    pub value: toml::Value,
+    pub origin: String,
+}
+
+/// The system, user and, once `root` is known, library config files, lowest precedence first
+pub fn config_files(root: Option<&Path>) -> Vec<PathBuf> {
+    let user = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")));
+    [
+        Some(PathBuf::from(SYSTEM_CONFIG)),
+        user.map(|u| u.join("songman").join("config.toml")),
+        root.map(|r| r.join(CONFIG_FILE)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+impl LayeredConfig {
+    /// Merge the [`config_files`] that exist. The files are applied in order, then the
+    /// `[profiles.<name>]` tables of `profile`, or of the `profile` key when it is `None`.
+    /// Without `root`, the library file of the configured root is read.
+    pub fn load(root: Option<&Path>, profile: Option<&str>) -> crate::Result<Self> {
+        let mut layers = read_layers(&config_files(root))?;
+        let chosen = |layers: &[(PathBuf, toml::Table)]| -> Option<String> {
+            profile.map(str::to_string).or_else(|| {
+                layers
+                    .iter()
+                    .rev()
+                    .find_map(|(_, t)| t.get(PROFILE)?.as_str().map(str::to_string))
+            })
+        };
+        if root.is_none() {
+            let config = Self::merge(&layers, chosen(&layers).as_deref())?;
+            if let Some(root) = &config.config.root {
+                layers.extend(read_layers(&[root.join(CONFIG_FILE)])?);
+            }
+        }
+        Self::merge(&layers, chosen(&layers).as_deref())
+    }
+
+    fn merge(layers: &[(PathBuf, toml::Table)], profile: Option<&str>) -> crate::Result<Self> {
+        let defaults =
+            toml::Table::try_from(LibraryConfig::default()).expect("The config serializes");
+        let (mut merged, mut origins) = (toml::Table::new(), BTreeMap::new());
+        merge_table(&mut merged, &mut origins, "", &defaults, DEFAULT_ORIGIN);
+        for (path, table) in layers {
+            let mut base = table.clone();
+            base.remove(PROFILES);
+            base.remove(PROFILE);
+            merge_table(
+                &mut merged,
+                &mut origins,
+                "",
+                &base,
+                &path.to_string_lossy(),
+            );
+        }
+        if let Some(name) = profile {
+            let mut found = false;
+            for (path, table) in layers {
+                let section = table
+                    .get(PROFILES)
+                    .and_then(|p| p.get(name))
+                    .and_then(toml::Value::as_table);
+                if let Some(section) = section {
+                    found = true;
+                    let origin = format!("{} [{PROFILES}.{name}]", path.to_string_lossy());
+                    merge_table(&mut merged, &mut origins, "", section, &origin);
+                }
+            }
+            if !found {
+                return Err(Error::UnknownProfile(name.to_string()));
+            }
+        }
+        let config = merged.clone().try_into().map_err(|source| Error::Config {
+            path: layers.last().map(|(p, _)| p.clone()).unwrap_or_default(),
+            source,
+        })?;
+        Ok(Self {
+            config,
+            profile: profile.map(str::to_string),
+            merged,
+            origins,
+        })
+    }
+
+    /// Where `key`, such as `walk.exclude`, was set, or the table holding it when
+    /// the table had no default
+    pub fn origin(&self, key: &str) -> &str {
+        let mut key = key;
+        loop {
+            if let Some(origin) = self.origins.get(key) {
+                return origin;
+            }
+            match key.rsplit_once('.') {
+                Some((parent, _)) => key = parent,
+                None => return DEFAULT_ORIGIN,
+            }
+        }
+    }
+
+    /// Every value of the effective configuration with its origin, sorted by key
+    pub fn settings(&self) -> Vec<Setting> {
+        let mut settings = Vec::new();
+        flatten(&self.merged, "", &mut settings);
+        for setting in &mut settings {
+            setting.origin = self.origin(&setting.key).to_string();
+            if setting.key.starts_with("serve.users.") {
+                setting.value = toml::Value::String("<hidden>".to_string());
+            }
+        }
+        settings
+    }
+}
+
+/// The tables of the files that exist, each checked on its own so errors name the file
+fn read_layers(paths: &[PathBuf]) -> crate::Result<Vec<(PathBuf, toml::Table)>> {
+    let mut layers = Vec::new();
+    for path in paths.iter().filter(|p| p.is_file()) {
+        let config_error = |source| Error::Config {
+            path: path.clone(),
+            source,
+        };
+        let table: toml::Table =
+            toml::from_str(&std::fs::read_to_string(path)?).map_err(config_error)?;
+        let mut base = table.clone();
+        let profiles = base.remove(PROFILES);
+        base.remove(PROFILE);
+        let sections = profiles
+            .as_ref()
+            .and_then(toml::Value::as_table)
+            .into_iter()
+            .flat_map(|p| p.values())
+            .filter_map(toml::Value::as_table);
+        for section in std::iter::once(&base).chain(sections) {
+            section
+                .clone()
+                .try_into::<LibraryConfig>()
+                .map_err(config_error)?;
+        }
+        layers.push((path.clone(), table));
+    }
+    Ok(layers)
+}
+
+/// Overlay `table` onto `merged` key by key, nested tables merging and anything else replacing
+fn merge_table(
+    merged: &mut toml::Table,
+    origins: &mut BTreeMap<String, String>,
+    prefix: &str,
+    table: &toml::Table,
+    origin: &str,
+) {
+    for (key, value) in table {
+        let path = match prefix {
+            "" => key.clone(),
+            _ => format!("{prefix}.{key}"),
+        };
+        match (merged.get_mut(key), value) {
+            (Some(toml::Value::Table(inner)), toml::Value::Table(value)) => {
+                merge_table(inner, origins, &path, value, origin)
+            }
+            _ => {
+                merged.insert(key.clone(), value.clone());
+                origins.insert(path, origin.to_string());
+            }
+        }
+    }
+}
+
+fn flatten(table: &toml::Table, prefix: &str, out: &mut Vec<Setting>) {
+    for (key, value) in table {
+        let key = match prefix {
+            "" => key.clone(),
+            _ => format!("{prefix}.{key}"),
+        };
+        match value {
+            toml::Value::Table(table) => flatten(table, &key, out),
+            value => out.push(Setting {
+                key,
+                value: value.clone(),
+                origin: String::new(),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for Setting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {} # {}", self.key, self.value, self.origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layers(files: &[(&str, &str)]) -> Vec<(PathBuf, toml::Table)> {
+        files
+            .iter()
+            .map(|(path, text)| (PathBuf::from(path), toml::from_str(text).unwrap()))
+            .collect()
+    }
+
+    const SYSTEM: &str = r#"
+        log_level = "warn"
+        profile = "usb"
+        [walk]
+        exclude = ["*.tmp"]
+        [profiles.usb]
+        sanitize = "windows"
+    "#;
+    const LIBRARY: &str = r#"
+        max_length = 100
+        [walk]
+        hidden = false
+        [profiles.usb]
+        max_length = 64
+        [serve.users]
+        alice = "sesame"
+    "#;
+
+    #[test]
+    fn later_files_and_profiles_override_key_by_key() {
+        let layers = layers(&[("system.toml", SYSTEM), ("library.toml", LIBRARY)]);
+        let merged = LayeredConfig::merge(&layers, None).unwrap();
+        assert_eq!(merged.config.log_level, "warn");
+        assert_eq!(merged.config.max_length, 100);
+        assert_eq!(merged.config.sanitize, Profile::Posix);
+        // nested tables merge rather than replace each other
+        assert_eq!(merged.config.walk.exclude, ["*.tmp"]);
+        assert!(!merged.config.walk.hidden);
+        assert!(merged.config.walk.follow_symlinks);
+
+        let usb = LayeredConfig::merge(&layers, Some("usb")).unwrap();
+        assert_eq!(usb.config.sanitize, Profile::Windows);
+        assert_eq!(usb.config.max_length, 64);
+        assert_eq!(usb.origin("max_length"), "library.toml [profiles.usb]");
+        assert_eq!(usb.origin("walk.exclude"), "system.toml");
+        assert_eq!(usb.origin("template"), DEFAULT_ORIGIN);
+        assert_eq!(usb.origin("serve.users.alice"), "library.toml");
+    }
+
+    #[test]
+    fn unknown_profiles_are_rejected() {
+        let layers = layers(&[("system.toml", SYSTEM)]);
+        assert!(matches!(
+            LayeredConfig::merge(&layers, Some("car")),
+            Err(Error::UnknownProfile(name)) if name == "car"
+        ));
+    }
+
+    #[test]
+    fn settings_hide_passwords() {
+        let layers = layers(&[("library.toml", LIBRARY)]);
+        let settings = LayeredConfig::merge(&layers, None).unwrap().settings();
+        let alice = settings
+            .iter()
+            .find(|s| s.key == "serve.users.alice")
+            .unwrap();
+        assert_eq!(alice.value, toml::Value::String("<hidden>".to_string()));
+        assert!(settings.is_sorted_by(|a, b| a.key <= b.key));
+    }
+}
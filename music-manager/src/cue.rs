@@ -0,0 +1,642 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use flacenc::{
+    bitsink::ByteSink,
+    component::{BitRepr, MetadataBlockData, Stream, StreamInfo},
+    error::Verify,
+    source::{Context, Fill, FrameBuf},
+};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+    Error,
+    decode::decode_samples,
+    sanitize::Sanitizer,
+    tags::{TagChanges, Tags, write_tags},
+    template::Template,
+};
+
+/// CD frames per second, the unit of `INDEX` times
+const FRAMES_PER_SECOND: u64 = 75;
+
+/// Padding reserved in split tracks so tags are written in place
+const PADDING: usize = 8192;
+/// Offsets of the block sizes in a FLAC file, in the STREAMINFO block after `fLaC` and its header
+const MIN_BLOCK_SIZE: usize = 8;
+const MAX_BLOCK_SIZE: usize = 10;
+
+/// The file names split tracks get by default
+pub const DEFAULT_SPLIT_TEMPLATE: &str = "{track:2} - {title}";
+
+/// A CUE sheet describing the tracks of one or more audio files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// `REM` comments such as `DATE` and `GENRE`
+    pub rem: BTreeMap<String, String>,
+    pub files: Vec<CueFile>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CueFile {
+    /// The file name as written in the sheet, relative to the sheet
+    pub name: String,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub isrc: Option<String>,
+    /// Index number to position in CD frames
+    pub indexes: BTreeMap<u32, u64>,
+}
+
+impl CueTrack {
+    /// Where the track starts in CD frames, `INDEX 01` or the pregap when it is missing
+    pub fn start(&self) -> Option<u64> {
+        self.indexes
+            .get(&1)
+            .or_else(|| self.indexes.get(&0))
+            .copied()
+    }
+
+    pub fn start_seconds(&self) -> Option<f64> {
+        self.start()
+            .map(|frames| frames as f64 / FRAMES_PER_SECOND as f64)
+    }
+}
+
+impl CueSheet {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut sheet = Self::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            let error = |message: &str| format!("line {}: {message}", n + 1);
+            let track = sheet.files.last_mut().and_then(|f| f.tracks.last_mut());
+            match command.to_ascii_uppercase().as_str() {
+                "REM" => {
+                    let (key, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    sheet
+                        .rem
+                        .insert(key.to_ascii_uppercase(), unquote(value.trim()));
+                }
+                "FILE" => {
+                    // the file type follows the name
+                    let name = match rest.strip_prefix('"') {
+                        Some(quoted) => quoted.split_once('"').map(|(name, _)| name),
+                        None => rest.rsplit_once(char::is_whitespace).map(|(name, _)| name),
+                    };
+                    let name = name.ok_or_else(|| error("FILE without a type"))?;
+                    sheet.files.push(CueFile {
+                        name: name.to_string(),
+                        tracks: Vec::new(),
+                    });
+                }
+                "TRACK" => {
+                    let file = sheet
+                        .files
+                        .last_mut()
+                        .ok_or_else(|| error("TRACK before FILE"))?;
+                    let number = rest
+                        .split_whitespace()
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or_else(|| error("TRACK without a number"))?;
+                    file.tracks.push(CueTrack {
+                        number,
+                        ..Default::default()
+                    });
+                }
+                "INDEX" => {
+                    let track = track.ok_or_else(|| error("INDEX before TRACK"))?;
+                    let (number, time) = rest
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(|| error("INDEX without a time"))?;
+                    let number = number
+                        .parse()
+                        .map_err(|_| error(&format!("Invalid index number '{number}'")))?;
+                    let time = parse_time(time.trim())
+                        .ok_or_else(|| error(&format!("Invalid time '{}'", time.trim())))?;
+                    track.indexes.insert(number, time);
+                }
+                "TITLE" => match track {
+                    Some(track) => track.title = Some(unquote(rest)),
+                    None => sheet.title = Some(unquote(rest)),
+                },
+                "PERFORMER" => match track {
+                    Some(track) => track.performer = Some(unquote(rest)),
+                    None => sheet.performer = Some(unquote(rest)),
+                },
+                "ISRC" => {
+                    if let Some(track) = track {
+                        track.isrc = Some(unquote(rest));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if sheet.files.iter().all(|f| f.tracks.is_empty()) {
+            return Err("No tracks".to_string());
+        }
+        Ok(sheet)
+    }
+
+    /// The entry of the sheet describing `song`, matched by file name or, as rippers
+    /// often name the image before compressing it, by stem when the sheet lists one file
+    pub fn file_for(&self, song: &Path) -> Option<&CueFile> {
+        let name = song.file_name()?.to_string_lossy();
+        let stem = song.file_stem()?.to_string_lossy();
+        let file_name = |f: &CueFile| {
+            Path::new(&f.name.replace('\\', "/"))
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+        };
+        self.files
+            .iter()
+            .find(|f| file_name(f).is_some_and(|n| n.eq_ignore_ascii_case(&name)))
+            .or_else(|| {
+                let [file] = self.files.as_slice() else {
+                    return None;
+                };
+                let cue_stem = Path::new(&file_name(file)?)
+                    .file_stem()?
+                    .to_string_lossy()
+                    .into_owned();
+                cue_stem.eq_ignore_ascii_case(&stem).then_some(file)
+            })
+    }
+
+    /// The tags of every track of `file` in an image tagged with `image_tags`
+    pub fn track_tags(&self, file: &CueFile, image_tags: &Tags) -> Vec<Tags> {
+        file.tracks
+            .iter()
+            .map(|track| {
+                let mut tags = image_tags.clone();
+                let mut set = |key: &str, value: Option<&String>| match value {
+                    Some(value) => tags.insert(key.to_string(), value.clone()),
+                    None => tags.remove(key),
+                };
+                set("TrackTitle", track.title.as_ref());
+                set(
+                    "Artist",
+                    track.performer.as_ref().or(self.performer.as_ref()),
+                );
+                set("TrackNumber", Some(&track.number.to_string()));
+                set("TrackTotal", Some(&file.tracks.len().to_string()));
+                set("IdentIsrc", track.isrc.as_ref());
+                let mut overlay = |key: &str, value: Option<&String>| {
+                    if let Some(value) = value {
+                        tags.insert(key.to_string(), value.clone());
+                    }
+                };
+                overlay("Album", self.title.as_ref());
+                overlay("AlbumArtist", self.performer.as_ref());
+                overlay("Date", self.rem.get("DATE"));
+                overlay("Genre", self.rem.get("GENRE"));
+                overlay("DiscNumber", self.rem.get("DISCNUMBER"));
+                overlay("DiscTotal", self.rem.get("TOTALDISCS"));
+                tags
+            })
+            .collect()
+    }
+}
+
+/// Parse `MM:SS:FF` into CD frames
+fn parse_time(time: &str) -> Option<u64> {
+    let mut parts = time.split(':').map(|p| p.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    (parts.next().is_none() && seconds < 60 && frames < FRAMES_PER_SECOND)
+        .then(|| (minutes * 60 + seconds) * FRAMES_PER_SECOND + frames)
+}
+
+fn unquote(s: &str) -> String {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+        .to_string()
+}
+
+/// Read the sheet at `path`, as UTF-8 or, failing that, Latin-1
+pub fn read_cue(path: &Path) -> crate::Result<CueSheet> {
+    let text = read_text(path)?;
+    CueSheet::parse(&text).map_err(|message| Error::InvalidCue {
+        path: path.to_path_buf(),
+        message,
+    })
+}
+
+/// The text of the sheet at `path`, as UTF-8 or, failing that, Latin-1
+fn read_text(path: &Path) -> std::io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => e.into_bytes().iter().map(|&b| b as char).collect(),
+    };
+    Ok(text.trim_start_matches('\u{feff}').to_string())
+}
+
+/// The sheet at `cue` with its `FILE` entries naming `old` naming `new` instead, every
+/// other line left as it is
+pub fn rename_file(cue: &Path, old: &str, new: &str) -> crate::Result<String> {
+    Ok(rename_file_entry(&read_text(cue)?, old, new))
+}
+
+fn rename_file_entry(text: &str, old: &str, new: &str) -> String {
+    let newline = match text.contains("\r\n") {
+        true => "\r\n",
+        false => "\n",
+    };
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| {
+            let trimmed = line.trim_start();
+            let Some(rest) = trimmed
+                .get(..4)
+                .filter(|c| c.eq_ignore_ascii_case("FILE"))
+                .map(|_| trimmed[4..].trim())
+            else {
+                return line.to_string();
+            };
+            let (name, file_type) = match rest.strip_prefix('"') {
+                Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+                None => rest.rsplit_once(char::is_whitespace).unwrap_or((rest, "")),
+            };
+            match name == old {
+                true => {
+                    let indent = &line[..line.len() - trimmed.len()];
+                    format!("{indent}FILE \"{new}\" {}", file_type.trim())
+                }
+                false => line.to_string(),
+            }
+        })
+        .collect();
+    let mut text = lines.join(newline);
+    text.push_str(newline);
+    text
+}
+
+/// The sheet beside `song` splitting it into several tracks, if there is one
+pub fn find_cue(song: &Path) -> Option<(PathBuf, CueSheet)> {
+    let dir = song.parent()?;
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+    dir.read_dir()
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("cue")))
+        .find_map(|path| {
+            let sheet = read_cue(&path).inspect_err(|e| warn!("{e}")).ok()?;
+            let splits = sheet.file_for(song)?.tracks.len() > 1;
+            splits.then_some((path, sheet))
+        })
+}
+
+/// How a virtual track of `image` is named where a path is expected, such as `image.flac#03`
+pub fn virtual_path(image: &Path, number: u32) -> PathBuf {
+    let mut path = image.as_os_str().to_os_string();
+    path.push(format!("#{number:02}"));
+    PathBuf::from(path)
+}
+
+/// The image a sheet describes, the first of its files that exists beside it
+pub fn find_image(cue: &Path, sheet: &CueSheet) -> Option<PathBuf> {
+    let dir = cue.parent().unwrap_or(Path::new(""));
+    sheet.files.iter().find_map(|file| {
+        let path = dir.join(file.name.replace('\\', "/"));
+        if path.is_file() {
+            return Some(path);
+        }
+        // the sheet may name the uncompressed image
+        let stem = path.file_stem()?.to_os_string();
+        dir.read_dir().ok()?.find_map(|entry| {
+            let entry = entry.ok()?.path();
+            (entry.file_stem() == Some(&stem) && entry != cue && entry.is_file()).then_some(entry)
+        })
+    })
+}
+
+/// One track of an image and the file it is split into
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SplitTrack<'a> {
+    pub image: &'a Path,
+    pub dest: PathBuf,
+    /// Start in CD frames
+    pub start: u64,
+    /// End in CD frames, the end of the image when missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<u64>,
+    pub tags: Tags,
+}
+
+impl std::fmt::Display for SplitTrack<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = |frames: u64| {
+            let seconds = frames / FRAMES_PER_SECOND;
+            format!("{}:{:02}", seconds / 60, seconds % 60)
+        };
+        let end = self.end.map(time).unwrap_or_default();
+        write!(
+            f,
+            "Split {} [{}-{end}] to {}",
+            self.image.to_string_lossy(),
+            time(self.start),
+            self.dest.to_string_lossy()
+        )
+    }
+}
+
+/// Where each track of `image` is split to below `dest`.
+/// Tracks run from their `INDEX 01` to the next track's, so pregaps stay with the track before.
+pub fn split_tracks<'a>(
+    image: &'a Path,
+    sheet: &CueSheet,
+    image_tags: &Tags,
+    dest: &Path,
+    template: &Template,
+    sanitizer: &Sanitizer,
+) -> crate::Result<Vec<SplitTrack<'a>>> {
+    let file = sheet.file_for(image).ok_or_else(|| Error::InvalidCue {
+        path: image.to_path_buf(),
+        message: "The sheet does not describe the image".to_string(),
+    })?;
+    let tags = sheet.track_tags(file, image_tags);
+    file.tracks
+        .iter()
+        .zip(tags)
+        .enumerate()
+        .map(|(i, (track, tags))| {
+            let missing_index = || Error::InvalidCue {
+                path: image.to_path_buf(),
+                message: format!("Track {} has no index", track.number),
+            };
+            let start = track.start().ok_or_else(missing_index)?;
+            let end = file.tracks.get(i + 1).and_then(CueTrack::start);
+            let dest = template
+                .render(dest, &tags, "flac", sanitizer)
+                .ok_or(Error::MissingMetadata)?;
+            if dest.symlink_metadata().is_ok() {
+                let message = format!("{} already exists", dest.to_string_lossy());
+                return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, message).into());
+            }
+            Ok(SplitTrack {
+                image,
+                dest,
+                start,
+                end,
+                tags,
+            })
+        })
+        .collect()
+}
+
+/// Decode the image once, encoding every track to FLAC as soon as its samples are in.
+/// Only lossless images are split, so the tracks hold the image's exact samples.
+pub fn split(tracks: &[SplitTrack]) -> crate::Result<()> {
+    let Some(first) = tracks.first() else {
+        return Ok(());
+    };
+    let image = first.image;
+    let mut current = 0;
+    let mut position: u64 = 0;
+    let mut samples: Vec<i32> = Vec::new();
+    let info = decode_samples(image, |info, chunk| {
+        let bits = match info.bits_per_sample {
+            Some(bits @ 4..=24) if info.lossless => bits,
+            _ => return Err(Error::NotLossless(image.to_path_buf())),
+        };
+        let to_sample = |frames: u64| frames * info.sample_rate as u64 / FRAMES_PER_SECOND;
+        let scale = (1u32 << (bits - 1)) as f32;
+        for frame in chunk.chunks(info.channels) {
+            while let Some(track) = tracks.get(current)
+                && track.end.is_some_and(|end| position >= to_sample(end))
+            {
+                write_track(track, &samples, info.channels, bits, info.sample_rate)?;
+                samples.clear();
+                current += 1;
+            }
+            let Some(track) = tracks.get(current) else {
+                break;
+            };
+            if position >= to_sample(track.start) {
+                samples.extend(frame.iter().map(|s| (s * scale).round() as i32));
+            }
+            position += 1;
+        }
+        Ok(())
+    })?;
+    let info = info.ok_or(Error::NoAudio)?;
+    let bits = info.bits_per_sample.unwrap_or(16);
+    for track in &tracks[current.min(tracks.len())..] {
+        write_track(track, &samples, info.channels, bits, info.sample_rate)?;
+        samples.clear();
+    }
+    Ok(())
+}
+
+/// Encode interleaved `samples` as a FLAC file at `dest`, with room for tags
+pub(crate) fn write_flac(
+    dest: &Path,
+    samples: &[i32],
+    channels: usize,
+    bits: u32,
+    sample_rate: u32,
+) -> crate::Result<()> {
+    let encoder_error = |message: String| Error::Encoder {
+        program: "flacenc".to_string(),
+        message,
+    };
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, e)| encoder_error(e.to_string()))?;
+    let mut info = StreamInfo::new(sample_rate as usize, channels, bits as usize)
+        .map_err(|e| encoder_error(e.to_string()))?;
+    // a single block for the whole song, flacenc hashes the padding of a partial block
+    let mut context = Context::new(bits as usize, channels, samples.len() / channels);
+    context
+        .fill_interleaved(samples)
+        .map_err(|e| encoder_error(e.to_string()))?;
+    info.set_md5_digest(&context.md5_digest());
+    let mut stream = Stream::with_stream_info(info);
+    // encoded frame by frame, flacenc would pad the last frame to a whole block
+    let mut frame = FrameBuf::with_size(channels, config.block_size)
+        .map_err(|e| encoder_error(e.to_string()))?;
+    for (number, block) in samples.chunks(config.block_size * channels).enumerate() {
+        frame.resize(block.len() / channels);
+        frame
+            .fill_interleaved(block)
+            .map_err(|e| encoder_error(e.to_string()))?;
+        let encoded =
+            flacenc::encode_fixed_size_frame(&config, &frame, number, stream.stream_info())
+                .map_err(|e| encoder_error(format!("{e:?}")))?;
+        stream.add_frame(encoded);
+    }
+    let padding = MetadataBlockData::new_unknown(1, &[0; PADDING])
+        .map_err(|e| encoder_error(e.to_string()))?;
+    stream.add_metadata_block(padding);
+    let mut sink = ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|e| encoder_error(e.to_string()))?;
+    // the minimum block size leaves out the last block, as decoders take a stream whose
+    // minimum and maximum differ to be variable and refuse its numbered frames
+    let mut bytes = sink.into_inner();
+    bytes.copy_within(MAX_BLOCK_SIZE..MAX_BLOCK_SIZE + 2, MIN_BLOCK_SIZE);
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(dest, bytes)?;
+    Ok(())
+}
+
+fn write_track(
+    track: &SplitTrack,
+    samples: &[i32],
+    channels: usize,
+    bits: u32,
+    sample_rate: u32,
+) -> crate::Result<()> {
+    write_flac(&track.dest, samples, channels, bits, sample_rate)?;
+    let tags: TagChanges = track
+        .tags
+        .iter()
+        .map(|(key, value)| (key.clone(), Some(value.clone())))
+        .collect();
+    write_tags(&track.dest, &tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "\u{feff}REM GENRE \"Post-Rock\"
+REM DATE 1999
+PERFORMER \"Sigur Rós\"
+TITLE \"Ágætis byrjun\"
+FILE \"Ágætis byrjun.wav\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"Intro\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE \"Svefn-g-englar\"
+    PERFORMER \"Jónsi\"
+    ISRC ISXXX9900001
+    INDEX 00 01:35:70
+    INDEX 01 01:37:00
+";
+
+    #[test]
+    fn sheets_parse_into_files_and_tracks() {
+        let sheet = CueSheet::parse(SHEET.trim_start_matches('\u{feff}')).unwrap();
+        assert_eq!(sheet.title.as_deref(), Some("Ágætis byrjun"));
+        assert_eq!(sheet.performer.as_deref(), Some("Sigur Rós"));
+        assert_eq!(sheet.rem["GENRE"], "Post-Rock");
+        assert_eq!(sheet.rem["DATE"], "1999");
+        let [file] = &sheet.files[..] else {
+            panic!("{:?}", sheet.files);
+        };
+        assert_eq!(file.name, "Ágætis byrjun.wav");
+        let [intro, svefn] = &file.tracks[..] else {
+            panic!("{:?}", file.tracks);
+        };
+        assert_eq!((intro.number, intro.start()), (1, Some(0)));
+        assert_eq!(intro.performer, None);
+        assert_eq!(svefn.performer.as_deref(), Some("Jónsi"));
+        assert_eq!(svefn.isrc.as_deref(), Some("ISXXX9900001"));
+        assert_eq!(svefn.start(), Some(97 * FRAMES_PER_SECOND));
+        assert_eq!(svefn.start_seconds(), Some(97.0));
+        assert_eq!(svefn.indexes[&0], 95 * FRAMES_PER_SECOND + 70);
+    }
+
+    #[test]
+    fn invalid_sheets_name_the_line() {
+        for (sheet, message) in [
+            ("TRACK 01 AUDIO", "line 1: TRACK before FILE"),
+            (
+                "FILE a.wav WAVE\nINDEX 01 00:00:00",
+                "line 2: INDEX before TRACK",
+            ),
+            (
+                "FILE a.wav WAVE\nTRACK x AUDIO",
+                "line 2: TRACK without a number",
+            ),
+            ("FILE a.wav", "line 1: FILE without a type"),
+            (
+                "FILE a.wav WAVE\nTRACK 1\nINDEX 01 00:60:00",
+                "line 3: Invalid time '00:60:00'",
+            ),
+            ("FILE a.wav WAVE", "No tracks"),
+        ] {
+            assert_eq!(CueSheet::parse(sheet), Err(message.to_string()));
+        }
+    }
+
+    #[test]
+    fn times_are_minutes_seconds_and_frames() {
+        assert_eq!(parse_time("00:00:00"), Some(0));
+        assert_eq!(parse_time("01:02:03"), Some(62 * FRAMES_PER_SECOND + 3));
+        assert_eq!(parse_time("120:00:74"), Some(7200 * FRAMES_PER_SECOND + 74));
+        for time in [
+            "00:00:75",
+            "00:60:00",
+            "00:00",
+            "00:00:00:00",
+            "a:00:00",
+            "",
+        ] {
+            assert_eq!(parse_time(time), None, "{time}");
+        }
+    }
+
+    #[test]
+    fn files_match_by_name_or_lone_stem() {
+        let sheet = CueSheet::parse(SHEET).unwrap();
+        assert!(sheet.file_for(Path::new("x/Ágætis BYRJUN.WAV")).is_some());
+        assert!(sheet.file_for(Path::new("x/Ágætis byrjun.wav")).is_some());
+        assert!(sheet.file_for(Path::new("x/Ágætis byrjun.flac")).is_some());
+        assert!(sheet.file_for(Path::new("x/Other.flac")).is_none());
+    }
+
+    #[test]
+    fn track_tags_overlay_the_image_tags() {
+        let sheet = CueSheet::parse(SHEET).unwrap();
+        let image = Tags::from([
+            ("Artist".to_string(), "Image".to_string()),
+            ("Label".to_string(), "Fat Cat".to_string()),
+        ]);
+        let tracks = sheet.track_tags(&sheet.files[0], &image);
+        assert_eq!(tracks[0]["Artist"], "Sigur Rós");
+        assert_eq!(tracks[1]["Artist"], "Jónsi");
+        assert_eq!(tracks[1]["AlbumArtist"], "Sigur Rós");
+        assert_eq!(tracks[1]["TrackNumber"], "2");
+        assert_eq!(tracks[1]["TrackTotal"], "2");
+        assert_eq!(tracks[1]["Date"], "1999");
+        assert_eq!(tracks[1]["Label"], "Fat Cat");
+        assert!(!tracks[0].contains_key("IdentIsrc"));
+    }
+
+    #[test]
+    fn renaming_a_file_entry_keeps_the_other_lines() {
+        let text = "REM DATE 1999\r\n  FILE \"a.wav\" WAVE\r\nFILE b.wav WAVE\r\n";
+        assert_eq!(
+            rename_file_entry(text, "a.wav", "c.flac"),
+            "REM DATE 1999\r\n  FILE \"c.flac\" WAVE\r\nFILE b.wav WAVE\r\n"
+        );
+        assert_eq!(
+            rename_file_entry("FILE b.wav WAVE", "b.wav", "c.flac"),
+            "FILE \"c.flac\" WAVE\n"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use std::{io::ErrorKind, path::Path};
+
+use symphonia::{
+    core::{
+        audio::SampleBuffer,
+        codecs::{
+            CODEC_TYPE_ALAC, CODEC_TYPE_FLAC, CODEC_TYPE_PCM_F32LE, CODEC_TYPE_PCM_F64LE,
+            CODEC_TYPE_PCM_S16BE, CODEC_TYPE_PCM_S16LE, CODEC_TYPE_PCM_S24BE, CODEC_TYPE_PCM_S24LE,
+            CODEC_TYPE_PCM_S32BE, CODEC_TYPE_PCM_S32LE, CODEC_TYPE_WAVPACK, CodecType,
+            DecoderOptions,
+        },
+        errors::Error as SymphoniaError,
+        formats::FormatOptions,
+        io::{MediaSourceStream, MediaSourceStreamOptions},
+        meta::MetadataOptions,
+        probe::Hint,
+    },
+    default::{get_codecs, get_probe},
+};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Frames in the stream, if the container knows
+    pub frames: Option<u64>,
+    /// Bits per sample of integer streams, if the codec knows
+    pub bits_per_sample: Option<u32>,
+    /// Whether the stream is stored losslessly
+    pub lossless: bool,
+}
+
+const LOSSLESS_CODECS: &[CodecType] = &[
+    CODEC_TYPE_FLAC,
+    CODEC_TYPE_ALAC,
+    CODEC_TYPE_WAVPACK,
+    CODEC_TYPE_PCM_S16LE,
+    CODEC_TYPE_PCM_S16BE,
+    CODEC_TYPE_PCM_S24LE,
+    CODEC_TYPE_PCM_S24BE,
+    CODEC_TYPE_PCM_S32LE,
+    CODEC_TYPE_PCM_S32BE,
+    CODEC_TYPE_PCM_F32LE,
+    CODEC_TYPE_PCM_F64LE,
+];
+
+/// Decode the default track of `path`, handing interleaved samples to `sink` as they come
+pub fn decode_samples(
+    path: &Path,
+    mut sink: impl FnMut(&StreamInfo, &[f32]) -> crate::Result<()>,
+) -> crate::Result<Option<StreamInfo>> {
+    let song = std::fs::File::open(path)?;
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension() {
+        hint.with_extension(&extension.to_string_lossy());
+    }
+    let probed = get_probe().format(
+        &hint,
+        MediaSourceStream::new(Box::new(song), MediaSourceStreamOptions::default()),
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut reader = probed.format;
+    let Some(track) = reader.default_track() else {
+        return Ok(None);
+    };
+    let track_id = track.id;
+    let frames = track.codec_params.n_frames;
+    let bits_per_sample = track.codec_params.bits_per_sample;
+    let lossless = LOSSLESS_CODECS.contains(&track.codec_params.codec);
+    let mut decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut info = None;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => Err(err)?,
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(err)) => {
+                warn!("Skipping undecodable packet in {}: {err}", path.display());
+                continue;
+            }
+            Err(err) => Err(err)?,
+        };
+        let spec = *decoded.spec();
+        let info = info.get_or_insert(StreamInfo {
+            sample_rate: spec.rate,
+            channels: spec.channels.count(),
+            frames,
+            bits_per_sample,
+            lossless,
+        });
+        let buffer = match &mut buffer {
+            Some(buffer) if buffer.capacity() >= decoded.capacity() * info.channels => buffer,
+            _ => buffer.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buffer.copy_interleaved_ref(decoded);
+        sink(info, buffer.samples())?;
+    }
+    Ok(info)
+}
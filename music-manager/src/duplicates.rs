@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
     path::{Path, PathBuf},
 };
@@ -11,27 +12,85 @@ use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use serde::Serialize;
 pub use stream::hash_stream;
 
+use crate::roots::{LibraryRoot, RootedPath};
+
 #[derive(Clone, Debug, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Duplicates<'a> {
+    /// Virtual tracks of CUE images are listed as `image.flac#03`
     #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub metadata: Vec<Vec<&'a Path>>,
+    pub metadata: Vec<Vec<Cow<'a, Path>>>,
+    /// CUE images are compared as a whole
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub filename: Vec<Vec<&'a Path>>,
+    /// CUE images are compared as a whole
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub stream: Vec<Vec<&'a Path>>,
 }
 
+/// [`Duplicates`] with each song tagged with the root holding it
+#[derive(Clone, Debug, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RootedDuplicates<'a> {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub metadata: Vec<Vec<RootedPath<'a>>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub filename: Vec<Vec<RootedPath<'a>>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stream: Vec<Vec<RootedPath<'a>>>,
+}
+
+impl<'a> Duplicates<'a> {
+    /// Only keep the groups spanning more than one of `roots`
+    pub fn retain_across(&mut self, roots: &[LibraryRoot]) {
+        let across = |group: &[&Path]| {
+            let mut names: Vec<_> = group
+                .iter()
+                .map(|p| crate::roots::root_of(roots, p).map(|r| &r.name))
+                .collect();
+            names.sort_unstable();
+            names.dedup();
+            names.len() > 1
+        };
+        self.metadata
+            .retain(|g| across(&g.iter().map(Cow::as_ref).collect::<Vec<_>>()));
+        self.filename.retain(|g| across(g));
+        self.stream.retain(|g| across(g));
+    }
+
+    /// Tag every song with the root holding it
+    pub fn rooted<'b>(&'b self, roots: &'b [LibraryRoot]) -> RootedDuplicates<'b> {
+        let rooted = |group: Vec<&'b Path>| -> Vec<RootedPath<'b>> {
+            let mut group: Vec<_> = group
+                .into_iter()
+                .map(|p| RootedPath::new(roots, p))
+                .collect();
+            group.sort();
+            group
+        };
+        RootedDuplicates {
+            metadata: self
+                .metadata
+                .iter()
+                .map(|g| rooted(g.iter().map(Cow::as_ref).collect()))
+                .collect(),
+            filename: self.filename.iter().map(|g| rooted(g.clone())).collect(),
+            stream: self.stream.iter().map(|g| rooted(g.clone())).collect(),
+        }
+    }
+}
+
 pub fn detect_duplicates(
     songs: &Vec<PathBuf>,
     metadata: bool,
     filename: bool,
     stream: bool,
-) -> crate::Result<Duplicates> {
+) -> crate::Result<Duplicates<'_>> {
     let mut duplicates = Duplicates::default();
     if metadata {
         duplicates
             .metadata
-            .append(&mut find_duplicates(songs, metadata::hash_metadata)?);
+            .append(&mut metadata::find_title_duplicates(songs)?);
     }
     if filename {
         duplicates
@@ -58,8 +117,13 @@ where
             Some(hash.map(|h| (p, h)))
         })
         .collect::<Result<Vec<_>, _>>()?;
+    Ok(group_by_hash(song_hashes))
+}
+
+/// The groups of songs sharing a hash
+fn group_by_hash<T>(song_hashes: Vec<(T, blake3::Hash)>) -> Vec<Vec<T>> {
     let song_hashes = song_hashes.into_iter().fold(
-        HashMap::<blake3::Hash, Vec<&Path>>::new(),
+        HashMap::<blake3::Hash, Vec<T>>::new(),
         |mut map, (song, hash)| {
             if let Some(paths) = map.get_mut(&hash) {
                 paths.push(song);
@@ -69,9 +133,8 @@ where
             map
         },
     );
-    let duplicates = song_hashes
+    song_hashes
         .into_values()
         .filter(|paths| paths.len() > 1)
-        .collect();
-    Ok(duplicates)
+        .collect()
 }
@@ -1,4 +1,9 @@
-use std::path::Path;
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
 use symphonia::{
     core::{
@@ -10,6 +15,35 @@ use symphonia::{
     default::get_probe,
 };
 
+use crate::cue::{find_cue, virtual_path};
+
+/// Songs sharing a title, the virtual tracks of CUE images each counting as a song
+pub fn find_title_duplicates(songs: &[PathBuf]) -> crate::Result<Vec<Vec<Cow<'_, Path>>>> {
+    let titles: Vec<Vec<(Cow<Path>, blake3::Hash)>> = songs
+        .par_iter()
+        .map(|song| -> crate::Result<_> {
+            let Some((_, sheet)) = find_cue(song) else {
+                let hash = hash_metadata(song)?;
+                return Ok(hash
+                    .map(|h| (Cow::Borrowed(song.as_path()), h))
+                    .into_iter()
+                    .collect());
+            };
+            let file = sheet.file_for(song).expect("find_cue matched the file");
+            Ok(file
+                .tracks
+                .iter()
+                .filter_map(|track| {
+                    let title = track.title.as_ref()?;
+                    let path = virtual_path(song, track.number);
+                    Some((Cow::Owned(path), blake3::hash(title.as_bytes())))
+                })
+                .collect())
+        })
+        .collect::<crate::Result<_>>()?;
+    Ok(super::group_by_hash(titles.into_iter().flatten().collect()))
+}
+
 pub fn hash_metadata(path: &Path) -> Result<Option<blake3::Hash>, crate::Error> {
     let song = std::fs::File::open(path)?;
     let mut probed = get_probe().format(
@@ -0,0 +1,107 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Error,
+    sort::Transaction,
+    tags::{TagChanges, Tags, read_file_tags},
+};
+
+/// Explains the document to whoever edits it, like the instructions of `git rebase -i`
+const HELP: &str = "\
+# Edit the tags below, then save and quit to apply the changes.
+# Removing a line removes the tag, removing a song's table leaves it untouched.
+# Saving an empty document aborts.
+";
+
+/// Keys holding `N` or `N/TOTAL` numbers
+const NUMBER_KEYS: &[&str] = &["TrackNumber", "TrackTotal", "DiscNumber", "DiscTotal"];
+
+/// The tags of a selection of songs as an editable TOML document, a table per song
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagDocument {
+    songs: BTreeMap<PathBuf, Tags>,
+}
+
+impl TagDocument {
+    pub fn read(songs: &[impl AsRef<Path>]) -> crate::Result<Self> {
+        let songs = songs
+            .iter()
+            .map(|s| Ok((s.as_ref().to_path_buf(), read_file_tags(s.as_ref(), true)?)))
+            .collect::<crate::Result<_>>()?;
+        Ok(Self { songs })
+    }
+
+    pub fn to_toml(&self) -> String {
+        let tables: BTreeMap<String, &Tags> = self
+            .songs
+            .iter()
+            .map(|(path, tags)| (path.to_string_lossy().into_owned(), tags))
+            .collect();
+        format!(
+            "{HELP}\n{}",
+            toml::to_string_pretty(&tables).expect("Tags serialize to TOML")
+        )
+    }
+
+    /// The tag writes turning the songs' tags into those of `edited`, `None` if
+    /// `edited` is empty
+    pub fn changes(&self, edited: &str) -> crate::Result<Option<Vec<Transaction>>> {
+        let is_blank = edited
+            .lines()
+            .all(|l| l.trim().is_empty() || l.trim_start().starts_with('#'));
+        if is_blank {
+            return Ok(None);
+        }
+        let edited: BTreeMap<PathBuf, Tags> =
+            toml::from_str(edited).map_err(|e| Error::InvalidDocument(e.to_string()))?;
+        let mut transactions = Vec::new();
+        for (path, tags) in edited {
+            let Some(original) = self.songs.get(&path) else {
+                return Err(Error::InvalidDocument(format!(
+                    "'{}' is not one of the edited songs",
+                    path.to_string_lossy()
+                )));
+            };
+            validate(&path, &tags)?;
+            let changes: TagChanges = original
+                .keys()
+                .chain(tags.keys())
+                .filter_map(|key| {
+                    let new = tags.get(key).filter(|v| !v.trim().is_empty());
+                    (original.get(key) != new).then(|| (key.clone(), new.cloned()))
+                })
+                .collect();
+            if !changes.is_empty() {
+                transactions.push(Transaction::WriteTags {
+                    path,
+                    tags: changes,
+                });
+            }
+        }
+        Ok(Some(transactions))
+    }
+}
+
+fn validate(path: &Path, tags: &Tags) -> crate::Result<()> {
+    for key in NUMBER_KEYS {
+        let Some(value) = tags.get(*key).map(|v| v.trim()) else {
+            continue;
+        };
+        let is_number = |n: &str| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit());
+        let valid = value.is_empty()
+            || match value.split_once('/') {
+                Some((n, total)) => is_number(n.trim()) && is_number(total.trim()),
+                None => is_number(value),
+            };
+        if !valid {
+            return Err(Error::InvalidDocument(format!(
+                "{key} of '{}' is not a number: \"{value}\"",
+                path.to_string_lossy()
+            )));
+        }
+    }
+    Ok(())
+}
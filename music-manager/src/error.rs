@@ -8,8 +8,49 @@ pub enum Error {
     Symphonia(#[from] symphonia::core::errors::Error),
     #[error(transparent)]
     IO(#[from] io::Error),
+    #[error(transparent)]
+    Lofty(#[from] lofty::error::LoftyError),
+    #[error(transparent)]
+    Loudness(#[from] ebur128::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid config {}", .path.to_string_lossy())]
+    Config {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("Unknown config profile '{0}'")]
+    UnknownProfile(String),
     #[error("Missing Metadata")]
     MissingMetadata,
-    #[error("Unable to move {} to {}: File already exists", .src.to_string_lossy(), .dest.to_string_lossy())]
+    #[error("Unable to sort {} to {}: File already exists", .src.to_string_lossy(), .dest.to_string_lossy())]
     AlreadyExists { src: PathBuf, dest: PathBuf },
+    #[error("Unable to sort {} to {}: {} is sorted there too", .src.to_string_lossy(), .dest.to_string_lossy(), .other.to_string_lossy())]
+    SameDestination {
+        src: PathBuf,
+        other: PathBuf,
+        dest: PathBuf,
+    },
+    #[error("Invalid template: {0}")]
+    InvalidTemplate(String),
+    #[error("Invalid filter: {0}")]
+    InvalidFilter(String),
+    #[error("Invalid tag sheet: {0}")]
+    InvalidSheet(String),
+    #[error("Invalid tag document: {0}")]
+    InvalidDocument(String),
+    #[error("Several tag diffs for {}", .0.to_string_lossy())]
+    ConflictingDiffs(PathBuf),
+    #[error("Invalid CUE sheet {}: {message}", .path.to_string_lossy())]
+    InvalidCue { path: PathBuf, message: String },
+    #[error("{} is not lossless", .0.to_string_lossy())]
+    NotLossless(PathBuf),
+    #[error("Encoder {program} failed: {message}")]
+    Encoder { program: String, message: String },
+    #[error("No audio track")]
+    NoAudio,
+    #[error("Too quiet to measure its loudness")]
+    Silent,
 }
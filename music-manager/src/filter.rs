@@ -0,0 +1,317 @@
+use std::{cmp::Ordering, path::Path, str::FromStr};
+
+use crate::{Error, tags::Tags, template::Field};
+
+/// A predicate over songs such as `genre~rock and not (year<1990 or artist="Sigur Rós")`.
+///
+/// Conditions compare a field with `=`, `!=`, `~` (contains), `!~`, `<`, `<=`, `>` or `>=`,
+/// ignoring case and comparing numerically when both sides are numbers. A bare field
+/// matches songs that have it. Fields are template fields (`artist`, `year`, ...), `path`,
+/// `filename`, `ext` or any tag key as shown by `info`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Filter {
+    #[default]
+    All,
+    Exists(String),
+    Compare {
+        field: String,
+        op: Op,
+        value: String,
+    },
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Contains,
+    NotContains,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Longest operators first so `!=` is not read as `!` followed by `=`
+const OPS: &[(&str, Op)] = &[
+    ("!=", Op::Ne),
+    ("!~", Op::NotContains),
+    ("<=", Op::Le),
+    (">=", Op::Ge),
+    ("=", Op::Eq),
+    ("~", Op::Contains),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+];
+
+impl Filter {
+    pub fn matches(&self, song: &Path, tags: &Tags) -> bool {
+        match self {
+            Self::All => true,
+            Self::Exists(field) => field_value(field, song, tags).is_some(),
+            Self::Compare { field, op, value } => {
+                let Some(actual) = field_value(field, song, tags) else {
+                    return matches!(op, Op::Ne | Op::NotContains);
+                };
+                compare(&actual, *op, value)
+            }
+            Self::Not(f) => !f.matches(song, tags),
+            Self::And(a, b) => a.matches(song, tags) && b.matches(song, tags),
+            Self::Or(a, b) => a.matches(song, tags) || b.matches(song, tags),
+        }
+    }
+}
+
+/// The value of a filter field, see [`Filter`]
+pub(crate) fn field_value(field: &str, song: &Path, tags: &Tags) -> Option<String> {
+    match field.to_ascii_lowercase().as_str() {
+        "path" => return Some(song.to_string_lossy().to_string()),
+        "filename" => return song.file_name().map(|f| f.to_string_lossy().to_string()),
+        "ext" | "extension" => return song.extension().map(|e| e.to_string_lossy().to_string()),
+        _ => {}
+    }
+    if let Ok(field) = Field::from_str(field) {
+        return field.value(tags);
+    }
+    tags.iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(field))
+        .map(|(_, v)| v.clone())
+}
+
+fn compare(actual: &str, op: Op, expected: &str) -> bool {
+    let (actual_lower, expected_lower) = (actual.to_lowercase(), expected.to_lowercase());
+    let ordering = match (actual.trim().parse::<f64>(), expected.trim().parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => actual_lower.cmp(&expected_lower),
+    };
+    match op {
+        Op::Eq => ordering == Ordering::Equal,
+        Op::Ne => ordering != Ordering::Equal,
+        Op::Contains => actual_lower.contains(&expected_lower),
+        Op::NotContains => !actual_lower.contains(&expected_lower),
+        Op::Lt => ordering == Ordering::Less,
+        Op::Le => ordering != Ordering::Greater,
+        Op::Gt => ordering == Ordering::Greater,
+        Op::Ge => ordering != Ordering::Less,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word(String),
+}
+
+fn tokenize(s: &str) -> crate::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(&c) = chars.peek() {
+                    match c {
+                        '"' => quoted = !quoted,
+                        c if !quoted && (c.is_whitespace() || c == '(' || c == ')') => break,
+                        c => word.push(c),
+                    }
+                    chars.next();
+                }
+                if quoted {
+                    return Err(Error::InvalidFilter(format!("Unclosed '\"' in '{s}'")));
+                }
+                tokens.push(match word.to_ascii_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> crate::Result<Filter> {
+        let mut filter = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> crate::Result<Filter> {
+        let mut filter = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                }
+                // adjacent conditions are joined with an implicit `and`
+                Some(Token::Word(_) | Token::Not | Token::Open) => {}
+                _ => return Ok(filter),
+            }
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> crate::Result<Filter> {
+        match self.next() {
+            Some(Token::Not) => Ok(Filter::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let filter = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(filter),
+                    _ => Err(Error::InvalidFilter("Expected ')'".to_string())),
+                }
+            }
+            Some(Token::Word(word)) => condition(&word),
+            Some(token) => Err(Error::InvalidFilter(format!("Unexpected {token:?}"))),
+            None => Err(Error::InvalidFilter("Unexpected end of filter".to_string())),
+        }
+    }
+}
+
+fn condition(word: &str) -> crate::Result<Filter> {
+    let Some(start) = word.find(['=', '!', '~', '<', '>']) else {
+        return Ok(Filter::Exists(word.to_string()));
+    };
+    let rest = &word[start..];
+    let (symbol, op) = OPS
+        .iter()
+        .find(|(symbol, _)| rest.starts_with(symbol))
+        .copied()
+        .ok_or_else(|| Error::InvalidFilter(format!("Unknown operator in '{word}'")))?;
+    if start == 0 {
+        return Err(Error::InvalidFilter(format!("Missing field in '{word}'")));
+    }
+    Ok(Filter::Compare {
+        field: word[..start].to_string(),
+        op,
+        value: rest[symbol.len()..].to_string(),
+    })
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Ok(Self::All);
+        }
+        let filter = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(Error::InvalidFilter(format!("Unexpected {token:?}")));
+        }
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(filter: &str, tags: &[(&str, &str)]) -> bool {
+        let tags: Tags = tags
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let filter: Filter = filter.parse().unwrap();
+        filter.matches(Path::new("lib/Artist/Song.flac"), &tags)
+    }
+
+    #[test]
+    fn precedence_and_implicit_and() {
+        let filter: Filter = "a or b c".parse().unwrap();
+        let exists = |f: &str| Box::new(Filter::Exists(f.to_string()));
+        assert_eq!(
+            filter,
+            Filter::Or(exists("a"), Box::new(Filter::And(exists("b"), exists("c"))))
+        );
+        assert_eq!("".parse::<Filter>().unwrap(), Filter::All);
+    }
+
+    #[test]
+    fn conditions_compare_fields_and_tags() {
+        let song = [
+            ("Genre", "Post-Rock"),
+            ("Date", "1999-06-12"),
+            ("Artist", "Sigur Rós"),
+            ("Mood", "calm"),
+        ];
+        assert!(matches("genre~rock and not year<1990", &song));
+        assert!(matches(r#"artist="sigur rós""#, &song));
+        assert!(matches("year>=1999 year<=1999 year!=2000", &song));
+        assert!(matches(
+            "mood=CALM ext=flac filename~song path~Artist/",
+            &song
+        ));
+        assert!(!matches("genre!~rock or (album and year>1990)", &song));
+        // numbers compare numerically, not as text
+        assert!(matches("track<10", &[("TrackNumber", "9")]));
+        assert!(!matches("track>10", &[("TrackNumber", "9")]));
+    }
+
+    #[test]
+    fn missing_fields_only_match_negations() {
+        assert!(matches("album!=x album!~x", &[]));
+        assert!(!matches("album=x or album~x or album<x or album", &[]));
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        for filter in [
+            "(genre",
+            "genre)",
+            "=rock",
+            "genre=\"rock",
+            "genre!rock",
+            "and",
+            "not",
+        ] {
+            assert!(
+                matches!(filter.parse::<Filter>(), Err(Error::InvalidFilter(_))),
+                "{filter}"
+            );
+        }
+    }
+}
@@ -0,0 +1,530 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    Error,
+    duplicates::hash_stream,
+    lint::RequiredTags,
+    musicbrainz::simplify,
+    sanitize::Sanitizer,
+    sort::{Mode, Transaction, destinations, place_song},
+    tags::{Tags, read_file_tags},
+    template::{Field, Template},
+};
+
+/// What happens to incoming songs already in the library
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateAction {
+    /// Leave them in the incoming folder
+    #[default]
+    Skip,
+    /// Move them into the quarantine folder
+    Quarantine,
+    /// Import them anyway, noting what they duplicate
+    Flag,
+}
+
+impl std::str::FromStr for DuplicateAction {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(Self::Skip),
+            "quarantine" => Ok(Self::Quarantine),
+            "flag" => Ok(Self::Flag),
+            _ => Err(format!("Unknown duplicate action '{s}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for DuplicateAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Skip => write!(f, "skip"),
+            Self::Quarantine => write!(f, "quarantine"),
+            Self::Flag => write!(f, "flag"),
+        }
+    }
+}
+
+/// How incoming songs are checked and placed
+#[derive(Debug, Clone)]
+pub struct ImportOptions<'a> {
+    pub library: &'a Path,
+    /// Where rejected songs go, keeping their path within the incoming folder
+    pub quarantine: &'a Path,
+    pub mode: Mode,
+    pub template: &'a Template,
+    pub sanitizer: &'a Sanitizer,
+    pub required: &'a RequiredTags,
+    pub duplicates: DuplicateAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Import,
+    Quarantine,
+    Skip,
+}
+
+/// Why an incoming song is not simply imported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case", tag = "issue")]
+pub enum Issue {
+    /// A song with the same audio stream is in the library or imported before it
+    Duplicate {
+        of: PathBuf,
+    },
+    /// A song with the same artist, album and title is in the library or imported before it
+    MetadataDuplicate {
+        of: PathBuf,
+    },
+    MissingTags {
+        tags: Vec<String>,
+    },
+    /// The template can't place the song
+    Unsortable,
+    /// Another song is at, or is imported to, its destination
+    Conflict {
+        dest: PathBuf,
+    },
+    /// The song can't be probed, such as a truncated download
+    Unreadable {
+        error: String,
+    },
+}
+
+/// What happens to an incoming song
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Decision<'a> {
+    pub path: &'a Path,
+    pub verdict: Verdict,
+    /// Where the song goes, in the library or the quarantine folder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dest: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub issues: Vec<Issue>,
+}
+
+/// The reviewed import of an incoming folder
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Import<'a> {
+    pub songs: Vec<Decision<'a>>,
+    #[serde(skip)]
+    pub transactions: Vec<Transaction>,
+}
+
+/// What identifies a song in the library
+struct Identity {
+    stream: Option<blake3::Hash>,
+    /// Simplified artist, album and title
+    metadata: Option<String>,
+}
+
+impl Identity {
+    fn read(song: &Path) -> crate::Result<(Self, Tags)> {
+        let tags = read_file_tags(song, false)?;
+        let identity = Self {
+            stream: hash_stream(song)?,
+            metadata: metadata_key(&tags),
+        };
+        Ok((identity, tags))
+    }
+}
+
+fn metadata_key(tags: &Tags) -> Option<String> {
+    let artist = [Field::AlbumArtist, Field::Artist]
+        .iter()
+        .find_map(|f| f.value(tags))?;
+    let title = Field::Title.value(tags)?;
+    let album = Field::Album.value(tags).unwrap_or_default();
+    let key = [artist, album, title]
+        .iter()
+        .map(|v| simplify(v).into_iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\0");
+    Some(key)
+}
+
+/// Check every song of the `incoming` folder against the library songs and the required tags,
+/// then plan importing the accepted ones and quarantining the rejected ones
+pub fn plan_import<'a>(
+    incoming: &Path,
+    songs: &'a [PathBuf],
+    library: &[PathBuf],
+    options: &ImportOptions,
+) -> crate::Result<Import<'a>> {
+    let songs: Vec<&Path> = songs
+        .iter()
+        .map(PathBuf::as_path)
+        .filter(|s| !s.starts_with(options.quarantine))
+        .collect();
+    let known: Vec<crate::Result<(Identity, Tags)>> =
+        library.par_iter().map(|s| Identity::read(s)).collect();
+    let mut streams: HashMap<blake3::Hash, PathBuf> = HashMap::new();
+    let mut metadata: HashMap<String, PathBuf> = HashMap::new();
+    for (song, known) in library.iter().zip(known) {
+        let identity = match known {
+            Ok((identity, _)) => identity,
+            Err(e) => {
+                warn!("Skipping {}: {e}", song.display());
+                continue;
+            }
+        };
+        if let Some(stream) = identity.stream {
+            streams.entry(stream).or_insert_with(|| song.clone());
+        }
+        if let Some(key) = identity.metadata {
+            metadata.entry(key).or_insert_with(|| song.clone());
+        }
+    }
+    let candidates: Vec<crate::Result<(Identity, Tags)>> =
+        songs.par_iter().map(|s| Identity::read(s)).collect();
+    let readable: Vec<&Path> = songs
+        .iter()
+        .zip(&candidates)
+        .filter(|(_, c)| c.is_ok())
+        .map(|(s, _)| *s)
+        .collect();
+    let targets: HashMap<&Path, Option<PathBuf>> = destinations(
+        options.library,
+        &readable,
+        options.template,
+        options.sanitizer,
+    )?
+    .into_iter()
+    .collect();
+
+    let mut import = Import {
+        songs: Vec::new(),
+        transactions: Vec::new(),
+    };
+    let mut taken: HashSet<PathBuf> = HashSet::new();
+    for (song, candidate) in songs.iter().copied().zip(candidates) {
+        let mut issues = Vec::new();
+        let (identity, duplicate, target) = match candidate {
+            Ok((identity, tags)) => {
+                let stream_of = identity.stream.and_then(|s| streams.get(&s));
+                let metadata_of = identity.metadata.as_ref().and_then(|k| metadata.get(k));
+                match (stream_of, metadata_of) {
+                    (Some(of), _) => issues.push(Issue::Duplicate { of: of.clone() }),
+                    (None, Some(of)) => issues.push(Issue::MetadataDuplicate { of: of.clone() }),
+                    (None, None) => {}
+                }
+                let duplicate = !issues.is_empty();
+                let missing = options.required.missing(song, &tags);
+                if !missing.is_empty() {
+                    issues.push(Issue::MissingTags { tags: missing });
+                }
+                let target = targets.get(song).cloned().flatten();
+                // a duplicate's destination is usually the song it duplicates
+                let importing = !duplicate || options.duplicates == DuplicateAction::Flag;
+                match &target {
+                    None => issues.push(Issue::Unsortable),
+                    Some(dest)
+                        if importing
+                            && (taken.contains(dest) || dest.symlink_metadata().is_ok()) =>
+                    {
+                        issues.push(Issue::Conflict { dest: dest.clone() })
+                    }
+                    Some(_) => {}
+                }
+                (Some(identity), duplicate, target)
+            }
+            Err(e) => {
+                issues.push(Issue::Unreadable {
+                    error: e.to_string(),
+                });
+                (None, false, None)
+            }
+        };
+        let rejected = issues.len() > usize::from(duplicate);
+        let verdict = match (rejected, duplicate, options.duplicates) {
+            (true, ..) | (false, true, DuplicateAction::Quarantine) => Verdict::Quarantine,
+            (false, true, DuplicateAction::Skip) => Verdict::Skip,
+            _ => Verdict::Import,
+        };
+        let dest = match verdict {
+            Verdict::Import => target,
+            Verdict::Quarantine => {
+                let relative = song.strip_prefix(incoming).unwrap_or(song);
+                Some(options.quarantine.join(relative))
+            }
+            Verdict::Skip => None,
+        };
+        let mut decision = Decision {
+            path: song,
+            verdict,
+            dest,
+            issues,
+        };
+        if let Some(dest) = &decision.dest {
+            match place_song(song, dest.clone(), options.mode) {
+                Ok(mut transactions) => import.transactions.append(&mut transactions),
+                // an earlier run left a song of the same name in the quarantine
+                Err(Error::AlreadyExists { dest, .. }) => {
+                    decision.verdict = Verdict::Skip;
+                    decision.dest = None;
+                    decision.issues.push(Issue::Conflict { dest });
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        // songs imported earlier in the run count as part of the library
+        if decision.verdict == Verdict::Import {
+            let dest = decision
+                .dest
+                .clone()
+                .expect("Imported songs have a destination");
+            let identity = identity.expect("Imported songs are readable");
+            if let Some(stream) = identity.stream {
+                streams.entry(stream).or_insert_with(|| dest.clone());
+            }
+            if let Some(key) = identity.metadata {
+                metadata.entry(key).or_insert_with(|| dest.clone());
+            }
+            taken.insert(dest);
+        }
+        import.songs.push(decision);
+    }
+    import.transactions.sort();
+    import.transactions.dedup();
+    Ok(import)
+}
+
+impl std::fmt::Display for Decision<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verb = match self.verdict {
+            Verdict::Import => "Import",
+            Verdict::Quarantine => "Quarantine",
+            Verdict::Skip => "Skip",
+        };
+        write!(f, "{verb} '{}'", self.path.to_string_lossy())?;
+        if let Some(dest) = &self.dest {
+            write!(f, " to '{}'", dest.to_string_lossy())?;
+        }
+        for (i, issue) in self.issues.iter().enumerate() {
+            let separator = if i == 0 { ":" } else { "," };
+            write!(f, "{separator} {issue}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Duplicate { of } => write!(f, "duplicate of '{}'", of.to_string_lossy()),
+            Self::MetadataDuplicate { of } => {
+                write!(f, "same metadata as '{}'", of.to_string_lossy())
+            }
+            Self::MissingTags { tags } => write!(f, "missing {}", tags.join(", ")),
+            Self::Unsortable => write!(f, "lacks the tags of its destination"),
+            Self::Conflict { dest } => write!(f, "'{}' is taken", dest.to_string_lossy()),
+            Self::Unreadable { error } => write!(f, "unreadable: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_song;
+
+    struct Folders {
+        dir: tempfile::TempDir,
+    }
+
+    impl Folders {
+        fn new() -> Self {
+            let folders = Self {
+                dir: tempfile::tempdir().unwrap(),
+            };
+            write_song(
+                &folders.library().join("A/T.flac"),
+                1,
+                &[("Artist", "A"), ("TrackTitle", "T")],
+            );
+            std::fs::create_dir(folders.dir.path().join("incoming")).unwrap();
+            folders
+        }
+
+        fn library(&self) -> PathBuf {
+            self.dir.path().join("library")
+        }
+
+        fn incoming(&self, name: &str) -> PathBuf {
+            self.dir.path().join("incoming").join(name)
+        }
+
+        fn quarantine(&self) -> PathBuf {
+            self.dir.path().join("quarantine")
+        }
+
+        /// The verdict, destination and issues of each of `songs`, in the incoming folder
+        fn plan(
+            &self,
+            songs: &[PathBuf],
+            duplicates: DuplicateAction,
+        ) -> Vec<(Verdict, Option<PathBuf>, Vec<Issue>)> {
+            let mut library: Vec<PathBuf> = std::fs::read_dir(self.library().join("A"))
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .collect();
+            library.sort();
+            let (template, sanitizer) = ("{artist}/{title}".parse().unwrap(), Sanitizer::default());
+            let (root, quarantine) = (self.library(), self.quarantine());
+            let options = ImportOptions {
+                library: &root,
+                quarantine: &quarantine,
+                mode: Mode::Move,
+                template: &template,
+                sanitizer: &sanitizer,
+                required: &RequiredTags::default(),
+                duplicates,
+            };
+            let incoming = self.dir.path().join("incoming");
+            let import = plan_import(&incoming, songs, &library, &options).unwrap();
+            import
+                .songs
+                .into_iter()
+                .map(|d| (d.verdict, d.dest, d.issues))
+                .collect()
+        }
+    }
+
+    fn song(path: &Path, seed: i32, tags: &[(&str, &str)]) -> PathBuf {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        write_song(path, seed, tags);
+        path.to_path_buf()
+    }
+
+    #[test]
+    fn duplicates_are_skipped_quarantined_or_flagged() {
+        let folders = Folders::new();
+        let known = folders.library().join("A/T.flac");
+        let songs = [
+            song(
+                &folders.incoming("same stream.flac"),
+                1,
+                &[("Artist", "A"), ("TrackTitle", "U")],
+            ),
+            song(
+                &folders.incoming("same tags.flac"),
+                2,
+                &[("Artist", "A"), ("TrackTitle", "T")],
+            ),
+        ];
+        let issues = [
+            Issue::Duplicate { of: known.clone() },
+            Issue::MetadataDuplicate { of: known.clone() },
+        ];
+
+        let skipped = folders.plan(&songs, DuplicateAction::Skip);
+        for ((verdict, dest, found), issue) in skipped.into_iter().zip(&issues) {
+            assert_eq!((verdict, dest), (Verdict::Skip, None));
+            assert_eq!(found, std::slice::from_ref(issue));
+        }
+
+        let quarantined = folders.plan(&songs, DuplicateAction::Quarantine);
+        let dests: Vec<_> = quarantined
+            .iter()
+            .map(|(v, d, _)| (*v, d.clone()))
+            .collect();
+        assert_eq!(
+            dests,
+            [
+                (
+                    Verdict::Quarantine,
+                    Some(folders.quarantine().join("same stream.flac"))
+                ),
+                (
+                    Verdict::Quarantine,
+                    Some(folders.quarantine().join("same tags.flac"))
+                ),
+            ]
+        );
+
+        // flagged duplicates still need a free destination
+        let flagged = folders.plan(&songs, DuplicateAction::Flag);
+        assert_eq!(
+            flagged[0],
+            (
+                Verdict::Import,
+                Some(folders.library().join("A/U.flac")),
+                vec![issues[0].clone()]
+            )
+        );
+        assert_eq!(flagged[1].0, Verdict::Quarantine);
+        assert_eq!(
+            flagged[1].2,
+            [issues[1].clone(), Issue::Conflict { dest: known }]
+        );
+    }
+
+    #[test]
+    fn songs_of_a_run_conflict_with_each_other() {
+        let folders = Folders::new();
+        let tags = |album| [("Artist", "B"), ("TrackTitle", "X"), ("Album", album)];
+        let songs = [
+            song(&folders.incoming("one/x.flac"), 3, &tags("One")),
+            song(&folders.incoming("two/x.flac"), 4, &tags("Two")),
+        ];
+        let dest = folders.library().join("B/X.flac");
+        let plan = folders.plan(&songs, DuplicateAction::Skip);
+        assert_eq!(plan[0], (Verdict::Import, Some(dest.clone()), vec![]));
+        assert_eq!(
+            plan[1],
+            (
+                Verdict::Quarantine,
+                Some(folders.quarantine().join("two/x.flac")),
+                vec![Issue::Conflict { dest }]
+            )
+        );
+    }
+
+    #[test]
+    fn untagged_and_unreadable_songs_are_quarantined() {
+        let folders = Folders::new();
+        // an unreadable library song is left out rather than failing the import
+        std::fs::write(folders.library().join("A/broken.flac"), "fLaC").unwrap();
+        let broken = folders.incoming("broken.flac");
+        std::fs::write(&broken, "fLaC").unwrap();
+        let songs = [
+            song(&folders.incoming("untitled.flac"), 5, &[("Artist", "C")]),
+            broken,
+        ];
+        let plan = folders.plan(&songs, DuplicateAction::Skip);
+        assert_eq!(
+            plan[0],
+            (
+                Verdict::Quarantine,
+                Some(folders.quarantine().join("untitled.flac")),
+                vec![
+                    Issue::MissingTags {
+                        tags: vec!["title".to_string()]
+                    },
+                    Issue::Unsortable
+                ]
+            )
+        );
+        let (verdict, dest, issues) = &plan[1];
+        assert_eq!(*verdict, Verdict::Quarantine);
+        assert_eq!(*dest, Some(folders.quarantine().join("broken.flac")));
+        assert!(
+            matches!(issues[..], [Issue::Unreadable { .. }]),
+            "{issues:?}"
+        );
+    }
+}
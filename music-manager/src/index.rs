@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::{
+    album::{Album, Library, Track, read_tracks},
+    filter::Filter,
+    tags::Tags,
+};
+
+/// Length of ids in hex digits
+const ID_LENGTH: usize = 16;
+
+/// A track as read into the [`LibraryIndex`]
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct IndexedTrack {
+    /// Derived from the path, and the track's position within a CUE image, so it stays
+    /// the same across rebuilds
+    pub id: String,
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disc: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub codec: &'static str,
+    /// Duration in seconds, if the container knows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// Start in seconds within `path`, for the virtual tracks of a CUE image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<f64>,
+    /// Size of the file in bytes
+    pub size: u64,
+    pub tags: Tags,
+}
+
+impl IndexedTrack {
+    fn new(track: Track, part: usize) -> crate::Result<Self> {
+        Ok(Self {
+            id: track_id(track.path, part),
+            path: track.path.to_path_buf(),
+            disc: track.disc,
+            number: track.number,
+            title: track.title,
+            codec: track.codec,
+            duration: track.duration,
+            offset: track.offset,
+            size: track.path.metadata()?.len(),
+            tags: track.tags,
+        })
+    }
+
+    /// The track for the album model, without reading the song again
+    pub fn track(&self) -> Track<'_> {
+        let mut track = Track::new(&self.path, self.tags.clone(), self.codec, self.duration);
+        track.id = Some(self.id.clone());
+        track.offset = self.offset;
+        track
+    }
+}
+
+/// Every track of a library read once and kept in memory, to answer repeated queries
+#[derive(Debug, Clone, Default)]
+pub struct LibraryIndex {
+    /// The song files the tracks come from
+    pub songs: Vec<PathBuf>,
+    /// CUE images are split into their virtual tracks
+    pub tracks: Vec<IndexedTrack>,
+}
+
+impl LibraryIndex {
+    pub fn build(songs: Vec<PathBuf>) -> crate::Result<Self> {
+        let mut tracks = Vec::new();
+        let mut previous: Option<(&Path, usize)> = None;
+        for track in read_tracks(&songs)? {
+            // virtual tracks of a CUE image follow each other
+            let part = match previous {
+                Some((path, part)) if path == track.path => part + 1,
+                _ => usize::from(track.offset.is_some()),
+            };
+            previous = Some((track.path, part));
+            tracks.push(IndexedTrack::new(track, part)?);
+        }
+        Ok(Self { songs, tracks })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&IndexedTrack> {
+        self.tracks.iter().find(|t| t.id == id)
+    }
+
+    pub fn filter<'a>(&'a self, filter: &'a Filter) -> impl Iterator<Item = &'a IndexedTrack> {
+        self.tracks
+            .iter()
+            .filter(|t| filter.matches(&t.path, &t.tags))
+    }
+
+    /// The indexed tracks grouped into albums
+    pub fn library(&self) -> Library<'_> {
+        Library::new(self.tracks.iter().map(IndexedTrack::track).collect())
+    }
+}
+
+/// The id of the song at `path`, or of its `part`th virtual track counting from 1
+fn track_id(path: &Path, part: usize) -> String {
+    let path = path.as_os_str().as_encoded_bytes();
+    match part {
+        0 => short_hash(&[path]),
+        part => short_hash(&[path, &part.to_le_bytes()]),
+    }
+}
+
+/// The id of `album`, derived from its directory and name
+pub fn album_id(album: &Album) -> String {
+    let dir = album.dir.as_os_str().as_encoded_bytes();
+    format!(
+        "al-{}",
+        short_hash(&[dir, album.name.to_lowercase().as_bytes()])
+    )
+}
+
+/// The id of the artist called `name`, ignoring case
+pub fn artist_id(name: &str) -> String {
+    format!("ar-{}", short_hash(&[name.to_lowercase().as_bytes()]))
+}
+
+fn short_hash(parts: &[&[u8]]) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_hex()[..ID_LENGTH].to_string()
+}
@@ -0,0 +1,190 @@
+use std::path::{Component, Path};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use tracing::warn;
+
+use crate::{
+    sort::Transaction,
+    tags::{TagChanges, Tags, read_file_tags},
+    template::{Field, Segment, Template},
+};
+
+/// Read tags from the end of `path` as laid out by `pattern`, the inverse of
+/// [`Template::render`]. `None` if the path does not fit the pattern.
+pub fn infer_tags(pattern: &Template, path: &Path) -> Option<Tags> {
+    let components = pattern.components();
+    let mut names: Vec<String> = path
+        .components()
+        .rev()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .take(components.len())
+        .collect();
+    if names.len() < components.len() {
+        return None;
+    }
+    names.reverse();
+    if let Some(stem) = path.file_stem() {
+        *names.last_mut()? = stem.to_string_lossy().into_owned();
+    }
+
+    let mut tags = Tags::new();
+    for (segments, name) in components.iter().zip(&names) {
+        for (field, value) in match_component(segments, name)? {
+            let value = match field {
+                Field::Track | Field::Disc => value.parse::<u32>().ok()?.to_string(),
+                _ => value,
+            };
+            // a field appearing twice has to agree with itself
+            match tags.get(field.tag()) {
+                Some(existing) if *existing != value => return None,
+                _ => tags.insert(field.tag().to_string(), value),
+            };
+        }
+    }
+    Some(tags)
+}
+
+/// Match `name` against the segments of a component, fields take as little as possible
+fn match_component(segments: &[Segment], name: &str) -> Option<Vec<(Field, String)>> {
+    let Some((segment, rest)) = segments.split_first() else {
+        return name.is_empty().then(Vec::new);
+    };
+    match segment {
+        Segment::Literal(literal) => match_component(rest, name.strip_prefix(literal.as_str())?),
+        Segment::Field { fields, .. } => {
+            // a field falling back to others is read as the first of them
+            let field = fields[0];
+            name.char_indices()
+                .skip(1)
+                .map(|(i, _)| i)
+                .chain([name.len()])
+                .filter(|&end| accepts(field, name[..end].trim()))
+                .find_map(|end| {
+                    let mut matched = match_component(rest, &name[end..])?;
+                    matched.insert(0, (field, name[..end].trim().to_string()));
+                    Some(matched)
+                })
+        }
+    }
+}
+
+fn accepts(field: Field, value: &str) -> bool {
+    match field {
+        Field::Track | Field::Disc => {
+            !value.is_empty() && value.chars().all(|c| c.is_ascii_digit())
+        }
+        Field::Year => value.len() == 4 && value.chars().all(|c| c.is_ascii_digit()),
+        _ => !value.is_empty(),
+    }
+}
+
+/// Plan writing the tags inferred from each song's path by the first matching pattern.
+/// Tags a song already has are kept unless `overwrite` is set.
+pub fn infer_transactions(
+    songs: &[impl AsRef<Path> + Sync],
+    patterns: &[Template],
+    overwrite: bool,
+) -> crate::Result<Vec<Transaction>> {
+    let transactions: crate::Result<Vec<Option<Transaction>>> = songs
+        .par_iter()
+        .map(|song| {
+            let song = song.as_ref();
+            let Some(inferred) = patterns.iter().find_map(|p| infer_tags(p, song)) else {
+                warn!("No pattern matches '{}'", song.to_string_lossy());
+                return Ok(None);
+            };
+            let existing = read_file_tags(song, false)?;
+            let tags: TagChanges = inferred
+                .into_iter()
+                .filter(|(key, value)| match existing.get(key) {
+                    Some(old) => overwrite && old != value,
+                    None => true,
+                })
+                .map(|(key, value)| (key, Some(value)))
+                .collect();
+            Ok((!tags.is_empty()).then(|| Transaction::WriteTags {
+                path: song.to_path_buf(),
+                tags,
+            }))
+        })
+        .collect();
+    Ok(transactions?.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_song;
+
+    fn infer(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+        let pattern: Template = pattern.parse().unwrap();
+        infer_tags(&pattern, Path::new(path)).map(|tags| tags.into_iter().collect())
+    }
+
+    fn tags(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+        tags.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn fields_are_read_from_the_end_of_the_path() {
+        assert_eq!(
+            infer(
+                "{artist}/{year} - {album}/{track} - {title}",
+                "/music/Sigur Rós/1999 - Ágætis byrjun/03 - Starálfur.flac"
+            ),
+            Some(tags(&[
+                ("Album", "Ágætis byrjun"),
+                ("Artist", "Sigur Rós"),
+                ("Date", "1999"),
+                ("TrackNumber", "3"),
+                ("TrackTitle", "Starálfur"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn fields_take_as_little_as_possible() {
+        assert_eq!(
+            infer("{artist} - {title}", "A - B - C.mp3"),
+            Some(tags(&[("Artist", "A"), ("TrackTitle", "B - C")]))
+        );
+        // track numbers are digits only
+        assert_eq!(infer("{track} {title}", "Intro 2.mp3"), None);
+    }
+
+    #[test]
+    fn paths_not_fitting_the_pattern_are_rejected() {
+        assert_eq!(infer("{artist}/{album}/{title}", "Song.flac"), None);
+        assert_eq!(infer("{track} - {title}", "Song.flac"), None);
+        assert_eq!(infer("{year} {title}", "99 Luftballons.flac"), None);
+        // a field appearing twice has to agree with itself
+        assert_eq!(
+            infer("{artist}/{artist} - {title}", "A/B - Song.flac"),
+            None
+        );
+        assert!(infer("{artist}/{artist} - {title}", "A/A - Song.flac").is_some());
+    }
+
+    #[test]
+    fn existing_tags_are_kept_unless_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let song = dir.path().join("Artist/Title.flac");
+        write_song(&song, 1, &[("Artist", "Other")]);
+        let patterns = ["{artist}/{title}".parse().unwrap()];
+
+        let planned =
+            |overwrite| match &infer_transactions(&[&song], &patterns, overwrite).unwrap()[..] {
+                [Transaction::WriteTags { tags, .. }] => tags.clone(),
+                transactions => panic!("{transactions:?}"),
+            };
+        let title = ("TrackTitle".to_string(), Some("Title".to_string()));
+        let artist = ("Artist".to_string(), Some("Artist".to_string()));
+        assert_eq!(planned(false), TagChanges::from([title.clone()]));
+        assert_eq!(planned(true), TagChanges::from([artist, title]));
+    }
+}
@@ -1,24 +1,31 @@
-use std::{
-    collections::HashMap,
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 use symphonia::{
     core::{
         formats::FormatOptions,
         io::{MediaSourceStream, MediaSourceStreamOptions},
-        meta::{Metadata, MetadataOptions, Tag},
+        meta::MetadataOptions,
         probe::Hint,
     },
     default::{get_codecs, get_probe},
 };
+use tracing::warn;
+
+use crate::{
+    lyrics::Lyrics,
+    tags::{Tags, read_tags},
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Info {
     path: PathBuf,
-    metadata: HashMap<String, String>,
+    metadata: Tags,
     codec: &'static str,
+    /// Left out when there are none or they can't be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lyrics: Option<Lyrics>,
 }
 
 pub fn get_info(path: &Path, nonstandard: bool) -> crate::Result<Info> {
@@ -29,13 +36,9 @@ pub fn get_info(path: &Path, nonstandard: bool) -> crate::Result<Info> {
         &FormatOptions::default(),
         &MetadataOptions::default(),
     )?;
-    let mut metadata = HashMap::new();
-    if let Some(mut md) = probe.metadata.get() {
-        add_metadata(&mut md, &mut metadata, nonstandard);
-    }
+    let metadata = read_tags(&mut probe, nonstandard);
 
-    let mut format = probe.format;
-    add_metadata(&mut format.metadata(), &mut metadata, nonstandard);
+    let format = probe.format;
 
     let codec = format
         .default_track()
@@ -43,32 +46,15 @@ pub fn get_info(path: &Path, nonstandard: bool) -> crate::Result<Info> {
         .map(|c| c.long_name)
         .unwrap_or("Unknown");
 
+    let lyrics = Lyrics::read(path)
+        .inspect_err(|e| warn!("Not reading the lyrics of {}: {e}", path.display()))
+        .ok()
+        .filter(|l| !l.is_empty());
+
     Ok(Info {
         path: path.to_path_buf(),
         metadata,
         codec,
+        lyrics,
     })
 }
-
-fn add_metadata(metadata: &mut Metadata, map: &mut HashMap<String, String>, nonstandard: bool) {
-    let Some(md) = metadata.skip_to_latest() else {
-        return;
-    };
-
-    for tag in md.tags() {
-        let Some((key, value)) = format_tag(tag, nonstandard) else {
-            continue;
-        };
-        map.insert(key, value);
-    }
-}
-
-fn format_tag(tag: &Tag, nonstandard: bool) -> Option<(String, String)> {
-    if let Some(key) = tag.std_key {
-        Some((format!("{key:?}"), tag.value.to_string()))
-    } else if nonstandard {
-        Some((tag.key.to_string(), tag.value.to_string()))
-    } else {
-        None
-    }
-}
@@ -1,11 +1,36 @@
+pub mod album;
+pub mod analysis;
+pub mod config;
+pub mod cue;
+mod decode;
 pub mod duplicates;
+pub mod edit;
 mod error;
+pub mod filter;
+pub mod import;
+pub mod index;
+pub mod infer;
 pub mod info;
+pub mod lint;
+pub mod lossless;
+pub mod loudness;
+pub mod lyrics;
 pub mod metadata;
+pub mod musicbrainz;
+pub mod normalize;
+pub mod roots;
+pub mod sanitize;
+pub mod sheet;
 pub mod sort;
 pub mod stats;
+pub mod sync;
+pub mod tags;
+pub mod template;
+#[cfg(test)]
+mod test_util;
+pub mod transcode;
 mod walksongs;
 
 pub use error::Error;
 pub use error::Result;
-pub use walksongs::get_songs;
+pub use walksongs::{WalkOptions, get_songs, walk_songs};
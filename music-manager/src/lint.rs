@@ -0,0 +1,861 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelExtend, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    album::{Album, Track, group_albums, most_common, read_tracks},
+    filter::field_value,
+    sort::Transaction,
+    tags::{TagChanges, Tags},
+    template::Field,
+};
+
+/// Fields a song needs to count as tagged, template fields or tag keys as in filters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RequiredTags(pub Vec<String>);
+
+impl Default for RequiredTags {
+    fn default() -> Self {
+        Self(vec!["artist".to_string(), "title".to_string()])
+    }
+}
+
+impl RequiredTags {
+    /// The required fields `song` lacks
+    pub fn missing(&self, song: &Path, tags: &Tags) -> Vec<String> {
+        self.0
+            .iter()
+            .filter(|field| field_value(field, song, tags).is_none())
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LintConfig {
+    /// Names of rules not to run
+    pub disable: Vec<String>,
+    /// Allowed genres, the ID3v1 genres if empty
+    pub genres: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl std::str::FromStr for Severity {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "info" => Ok(Self::Info),
+            "warning" | "warn" => Ok(Self::Warning),
+            "error" => Ok(Self::Error),
+            _ => Err(format!("Unknown severity '{s}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Info => write!(f, "info"),
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Diagnostic<'a> {
+    /// The song, or the directory of the album, the diagnostic is about
+    pub path: &'a Path,
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// Tag changes to `path` fixing the problem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<TagChanges>,
+}
+
+impl std::fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: {} [{}]",
+            self.severity,
+            self.path.to_string_lossy(),
+            self.message,
+            self.rule
+        )
+    }
+}
+
+/// A check over songs or whole albums
+pub trait Rule: Sync {
+    fn name(&self) -> &'static str;
+
+    fn check_song<'a>(&self, _song: &Track<'a>, _out: &mut Vec<Diagnostic<'a>>) {}
+
+    fn check_album<'a>(&self, _album: &Album<'a>, _out: &mut Vec<Diagnostic<'a>>) {}
+}
+
+/// Every built in rule, less those disabled in `config`
+pub fn default_rules(required: &RequiredTags, config: &LintConfig) -> Vec<Box<dyn Rule>> {
+    all_rules(required, config)
+        .into_iter()
+        .filter(|r| !config.disable.iter().any(|d| d == r.name()))
+        .collect()
+}
+
+/// Every built in rule, including those disabled in `config`
+pub fn all_rules(required: &RequiredTags, config: &LintConfig) -> Vec<Box<dyn Rule>> {
+    let genres = match config.genres.is_empty() {
+        true => ID3_GENRES.iter().map(|g| g.to_string()).collect(),
+        false => config.genres.clone(),
+    };
+    vec![
+        Box::new(MissingTags(required.clone())),
+        Box::new(Whitespace),
+        Box::new(AllCaps),
+        Box::new(FilenameMismatch),
+        Box::new(NonstandardGenre(genres)),
+        Box::new(AlbumArtist),
+        Box::new(TrackNumbering),
+        Box::new(AlbumYear),
+        Box::new(MixedCodecs),
+    ]
+}
+
+/// Run `rules` over every song and album, sorted by path
+pub fn lint<'a>(
+    songs: &'a [PathBuf],
+    rules: &[Box<dyn Rule>],
+) -> crate::Result<Vec<Diagnostic<'a>>> {
+    let (albums, loose) = group_albums(read_tracks(songs)?);
+    let songs: Vec<&Track> = albums
+        .iter()
+        .flat_map(|a| &a.tracks)
+        .chain(&loose)
+        .collect();
+
+    let mut diagnostics: Vec<Diagnostic> = songs
+        .par_iter()
+        .flat_map_iter(|song| {
+            let mut out = Vec::new();
+            for rule in rules {
+                rule.check_song(song, &mut out);
+            }
+            out
+        })
+        .collect();
+    diagnostics.par_extend(albums.par_iter().flat_map_iter(|album| {
+        let mut out = Vec::new();
+        for rule in rules {
+            rule.check_album(album, &mut out);
+        }
+        out
+    }));
+    // tags of CUE images hold the whole sheet, so fixing one virtual track would break the others
+    let images: BTreeSet<&Path> = songs
+        .iter()
+        .filter(|t| t.offset.is_some())
+        .map(|t| t.path)
+        .collect();
+    for diagnostic in &mut diagnostics {
+        if images.contains(diagnostic.path) {
+            diagnostic.fix = None;
+        }
+    }
+    diagnostics.sort_by(|a, b| (a.path, a.rule, &a.message).cmp(&(b.path, b.rule, &b.message)));
+    // virtual tracks of an image repeat its problems
+    diagnostics.dedup_by(|a, b| (a.path, a.rule, &a.message) == (b.path, b.rule, &b.message));
+    Ok(diagnostics)
+}
+
+/// Tag writes applying every fix, one per song. Of fixes setting one tag differently, the
+/// first is kept and the others are left for linting again.
+pub fn fix_transactions(diagnostics: &[Diagnostic]) -> Vec<Transaction> {
+    let mut fixes: BTreeMap<&Path, BTreeMap<&str, (&Option<String>, &str)>> = BTreeMap::new();
+    for diagnostic in diagnostics {
+        let Some(fix) = &diagnostic.fix else {
+            continue;
+        };
+        let changes = fixes.entry(diagnostic.path).or_default();
+        for (key, value) in fix {
+            match changes.get(key.as_str()) {
+                Some((first, _)) if *first == value => {}
+                Some((first, rule)) => warn!(
+                    "Not fixing {key} of {} to {value:?} [{}]: {rule} sets it to {first:?}",
+                    diagnostic.path.to_string_lossy(),
+                    diagnostic.rule
+                ),
+                None => {
+                    changes.insert(key, (value, diagnostic.rule));
+                }
+            }
+        }
+    }
+    fixes
+        .into_iter()
+        .map(|(path, changes)| Transaction::WriteTags {
+            path: path.to_path_buf(),
+            tags: changes
+                .into_iter()
+                .map(|(key, (value, _))| (key.to_string(), value.clone()))
+                .collect(),
+        })
+        .collect()
+}
+
+fn fix(key: &str, value: &str) -> Option<TagChanges> {
+    Some(TagChanges::from([(
+        key.to_string(),
+        Some(value.to_string()),
+    )]))
+}
+
+struct MissingTags(RequiredTags);
+
+impl Rule for MissingTags {
+    fn name(&self) -> &'static str {
+        "missing-tags"
+    }
+
+    fn check_song<'a>(&self, song: &Track<'a>, out: &mut Vec<Diagnostic<'a>>) {
+        let missing = self.0.missing(song.path, &song.tags);
+        if !missing.is_empty() {
+            out.push(Diagnostic {
+                path: song.path,
+                rule: self.name(),
+                severity: Severity::Error,
+                message: format!("Missing {}", missing.join(", ")),
+                fix: None,
+            });
+        }
+    }
+}
+
+struct Whitespace;
+
+impl Rule for Whitespace {
+    fn name(&self) -> &'static str {
+        "whitespace"
+    }
+
+    fn check_song<'a>(&self, song: &Track<'a>, out: &mut Vec<Diagnostic<'a>>) {
+        for (key, value) in &song.tags {
+            let trimmed = value.trim();
+            if trimmed != value {
+                out.push(Diagnostic {
+                    path: song.path,
+                    rule: self.name(),
+                    severity: Severity::Warning,
+                    message: format!("{key} {value:?} has surrounding whitespace"),
+                    fix: fix(key, trimmed),
+                });
+            }
+        }
+    }
+}
+
+struct AllCaps;
+
+impl Rule for AllCaps {
+    fn name(&self) -> &'static str {
+        "all-caps"
+    }
+
+    fn check_song<'a>(&self, song: &Track<'a>, out: &mut Vec<Diagnostic<'a>>) {
+        for field in [Field::Title, Field::Artist, Field::Album] {
+            let Some(value) = song.field(field) else {
+                continue;
+            };
+            // short words such as "ABBA" or "AC/DC" are usually meant to be capitalized
+            let letters = value.chars().filter(|c| c.is_alphabetic()).count();
+            if letters > 4 && !value.chars().any(|c| c.is_lowercase()) {
+                out.push(Diagnostic {
+                    path: song.path,
+                    rule: self.name(),
+                    severity: Severity::Info,
+                    message: format!("{} {value:?} is in all caps", field.name()),
+                    fix: None,
+                });
+            }
+        }
+    }
+}
+
+struct FilenameMismatch;
+
+impl Rule for FilenameMismatch {
+    fn name(&self) -> &'static str {
+        "filename-mismatch"
+    }
+
+    fn check_song<'a>(&self, song: &Track<'a>, out: &mut Vec<Diagnostic<'a>>) {
+        let simplify = |s: &str| -> String {
+            s.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect()
+        };
+        let (Some(title), Some(stem)) = (song.field(Field::Title), song.path.file_stem()) else {
+            return;
+        };
+        if song.offset.is_some() {
+            return;
+        }
+        if !simplify(&stem.to_string_lossy()).contains(&simplify(&title)) {
+            out.push(Diagnostic {
+                path: song.path,
+                rule: self.name(),
+                severity: Severity::Info,
+                message: format!("File name does not contain the title {title:?}"),
+                fix: None,
+            });
+        }
+    }
+}
+
+struct NonstandardGenre(Vec<String>);
+
+impl Rule for NonstandardGenre {
+    fn name(&self) -> &'static str {
+        "nonstandard-genre"
+    }
+
+    fn check_song<'a>(&self, song: &Track<'a>, out: &mut Vec<Diagnostic<'a>>) {
+        let Some(genre) = song.field(Field::Genre) else {
+            return;
+        };
+        if self.0.contains(&genre) {
+            return;
+        }
+        let standard = self.0.iter().find(|g| g.eq_ignore_ascii_case(&genre));
+        out.push(Diagnostic {
+            path: song.path,
+            rule: self.name(),
+            severity: Severity::Info,
+            message: match standard {
+                Some(standard) => format!("Genre {genre:?} should be written {standard:?}"),
+                None => format!("Genre {genre:?} is not a standard genre"),
+            },
+            fix: standard.and_then(|g| fix(Field::Genre.tag(), g)),
+        });
+    }
+}
+
+/// Songs of an album whose `field` differs from the album's most common value
+fn album_mismatches<'a>(
+    rule: &'static str,
+    album: &Album<'a>,
+    field: Field,
+    out: &mut Vec<Diagnostic<'a>>,
+) {
+    let values: Vec<_> = album.tracks.iter().map(|t| t.field(field)).collect();
+    let Some(common) = most_common(values.iter().flatten()) else {
+        return;
+    };
+    if values.iter().all(|v| v.as_ref() == Some(common)) {
+        return;
+    }
+    for (song, value) in album.tracks.iter().zip(&values) {
+        if value.as_ref() == Some(common) {
+            continue;
+        }
+        out.push(Diagnostic {
+            path: song.path,
+            rule,
+            severity: Severity::Warning,
+            message: format!(
+                "{} {:?} differs from {common:?} on the rest of {:?}",
+                field.name(),
+                value.as_deref().unwrap_or_default(),
+                album.name
+            ),
+            fix: fix(field.tag(), common),
+        });
+    }
+}
+
+struct AlbumArtist;
+
+impl Rule for AlbumArtist {
+    fn name(&self) -> &'static str {
+        "album-artist"
+    }
+
+    fn check_album<'a>(&self, album: &Album<'a>, out: &mut Vec<Diagnostic<'a>>) {
+        album_mismatches(self.name(), album, Field::AlbumArtist, out);
+    }
+}
+
+struct AlbumYear;
+
+impl Rule for AlbumYear {
+    fn name(&self) -> &'static str {
+        "album-year"
+    }
+
+    fn check_album<'a>(&self, album: &Album<'a>, out: &mut Vec<Diagnostic<'a>>) {
+        if album.inconsistent.contains(&Field::Year.name()) {
+            album_mismatches(self.name(), album, Field::Date, out);
+        }
+    }
+}
+
+struct TrackNumbering;
+
+impl Rule for TrackNumbering {
+    fn name(&self) -> &'static str {
+        "track-numbering"
+    }
+
+    fn check_album<'a>(&self, album: &Album<'a>, out: &mut Vec<Diagnostic<'a>>) {
+        let mut seen: HashMap<(u32, u32), &Track> = HashMap::new();
+        for track in &album.tracks {
+            let Some(number) = track.number else {
+                continue;
+            };
+            let disc = track.disc.unwrap_or(1);
+            if let Some(other) = seen.insert((disc, number), track) {
+                out.push(Diagnostic {
+                    path: track.path,
+                    rule: self.name(),
+                    severity: Severity::Warning,
+                    message: format!(
+                        "Track {number} of disc {disc} is also {}",
+                        other.path.to_string_lossy()
+                    ),
+                    fix: None,
+                });
+            }
+        }
+        if !album.missing.is_empty() {
+            out.push(Diagnostic {
+                path: album.dir,
+                rule: self.name(),
+                severity: Severity::Warning,
+                message: format!(
+                    "{:?} is missing track {}",
+                    album.name,
+                    album.missing.join(", ")
+                ),
+                fix: None,
+            });
+        }
+    }
+}
+
+struct MixedCodecs;
+
+impl Rule for MixedCodecs {
+    fn name(&self) -> &'static str {
+        "mixed-codecs"
+    }
+
+    fn check_album<'a>(&self, album: &Album<'a>, out: &mut Vec<Diagnostic<'a>>) {
+        if album.inconsistent.contains(&"codec") {
+            let codecs: BTreeSet<_> = album.tracks.iter().map(|t| t.codec).collect();
+            out.push(Diagnostic {
+                path: album.dir,
+                rule: self.name(),
+                severity: Severity::Warning,
+                message: format!(
+                    "{:?} mixes {}",
+                    album.name,
+                    codecs.into_iter().collect::<Vec<_>>().join(", ")
+                ),
+                fix: None,
+            });
+        }
+    }
+}
+
+/// The ID3v1 genres including the Winamp extensions
+const ID3_GENRES: &[&str] = &[
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "Alternative Rock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native American",
+    "Cabaret",
+    "New Wave",
+    "Psychedelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+    "Folk",
+    "Folk-Rock",
+    "National Folk",
+    "Swing",
+    "Fast Fusion",
+    "Bebop",
+    "Latin",
+    "Revival",
+    "Celtic",
+    "Bluegrass",
+    "Avantgarde",
+    "Gothic Rock",
+    "Progressive Rock",
+    "Psychedelic Rock",
+    "Symphonic Rock",
+    "Slow Rock",
+    "Big Band",
+    "Chorus",
+    "Easy Listening",
+    "Acoustic",
+    "Humour",
+    "Speech",
+    "Chanson",
+    "Opera",
+    "Chamber Music",
+    "Sonata",
+    "Symphony",
+    "Booty Bass",
+    "Primus",
+    "Porn Groove",
+    "Satire",
+    "Slow Jam",
+    "Club",
+    "Tango",
+    "Samba",
+    "Folklore",
+    "Ballad",
+    "Power Ballad",
+    "Rhythmic Soul",
+    "Freestyle",
+    "Duet",
+    "Punk Rock",
+    "Drum Solo",
+    "A Cappella",
+    "Euro-House",
+    "Dance Hall",
+    "Goa",
+    "Drum & Bass",
+    "Club-House",
+    "Hardcore",
+    "Terror",
+    "Indie",
+    "BritPop",
+    "Negerpunk",
+    "Polsk Punk",
+    "Beat",
+    "Christian Gangsta Rap",
+    "Heavy Metal",
+    "Black Metal",
+    "Crossover",
+    "Contemporary Christian",
+    "Christian Rock",
+    "Merengue",
+    "Salsa",
+    "Thrash Metal",
+    "Anime",
+    "JPop",
+    "Synthpop",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(path: &'static str, codec: &'static str, tags: &[(&str, &str)]) -> Track<'static> {
+        let tags = tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Track::new(Path::new(path), tags, codec, Some(60.0))
+    }
+
+    /// The path, message and fix of each diagnostic `rule` finds in `tracks`
+    fn check(
+        rule: &dyn Rule,
+        tracks: Vec<Track<'static>>,
+    ) -> Vec<(&'static Path, String, Option<TagChanges>)> {
+        let mut out = Vec::new();
+        for song in &tracks {
+            rule.check_song(song, &mut out);
+        }
+        let (albums, _) = group_albums(tracks);
+        for album in &albums {
+            rule.check_album(album, &mut out);
+        }
+        out.into_iter()
+            .map(|d| (d.path, d.message, d.fix))
+            .collect()
+    }
+
+    fn album(key: &str, values: [&str; 3]) -> Vec<Track<'static>> {
+        ["/lib/X/1.flac", "/lib/X/2.flac", "/lib/X/3.flac"]
+            .into_iter()
+            .zip(values)
+            .enumerate()
+            .map(|(i, (path, value))| {
+                let number = (i + 1).to_string();
+                track(
+                    path,
+                    "flac",
+                    &[("Album", "X"), ("TrackNumber", &number), (key, value)],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn missing_tags_are_errors() {
+        let tracks = vec![
+            track("/lib/a.flac", "flac", &[("Artist", "A")]),
+            track(
+                "/lib/b.flac",
+                "flac",
+                &[("Artist", "B"), ("TrackTitle", "b")],
+            ),
+        ];
+        let found = check(&MissingTags(RequiredTags::default()), tracks);
+        assert_eq!(
+            found,
+            [(Path::new("/lib/a.flac"), "Missing title".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let tracks = vec![track(
+            "/lib/a.flac",
+            "flac",
+            &[("Artist", " A "), ("Album", "B")],
+        )];
+        let [(_, _, fix)] = &check(&Whitespace, tracks)[..] else {
+            panic!("One tag has whitespace");
+        };
+        assert_eq!(*fix, super::fix("Artist", "A"));
+    }
+
+    #[test]
+    fn long_all_caps_values_are_noted() {
+        let tracks = vec![
+            track("/lib/a.flac", "flac", &[("TrackTitle", "HELLO WORLD")]),
+            track("/lib/b.flac", "flac", &[("TrackTitle", "ABBA")]),
+            track("/lib/c.flac", "flac", &[("TrackTitle", "Hello World")]),
+        ];
+        let found = check(&AllCaps, tracks);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, Path::new("/lib/a.flac"));
+    }
+
+    #[test]
+    fn file_names_contain_their_title() {
+        let tracks = vec![
+            track(
+                "/lib/01 - Hello, World.flac",
+                "flac",
+                &[("TrackTitle", "hello world")],
+            ),
+            track("/lib/02 - Hello.flac", "flac", &[("TrackTitle", "Goodbye")]),
+        ];
+        let found = check(&FilenameMismatch, tracks);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, Path::new("/lib/02 - Hello.flac"));
+    }
+
+    #[test]
+    fn genres_are_fixed_to_their_standard_spelling() {
+        let rule = NonstandardGenre(vec!["Rock".to_string()]);
+        let tracks = vec![
+            track("/lib/a.flac", "flac", &[("Genre", "Rock")]),
+            track("/lib/b.flac", "flac", &[("Genre", "rock")]),
+            track("/lib/c.flac", "flac", &[("Genre", "Shoegaze")]),
+        ];
+        let found = check(&rule, tracks);
+        let fixes: Vec<_> = found.iter().map(|(p, _, f)| (*p, f.clone())).collect();
+        assert_eq!(
+            fixes,
+            [
+                (Path::new("/lib/b.flac"), super::fix("Genre", "Rock")),
+                (Path::new("/lib/c.flac"), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn album_artists_and_years_follow_the_album() {
+        let found = check(&AlbumArtist, album("AlbumArtist", ["A", "B", "A"]));
+        let fixes: Vec<_> = found.iter().map(|(p, _, f)| (*p, f.clone())).collect();
+        assert_eq!(
+            fixes,
+            [(Path::new("/lib/X/2.flac"), super::fix("AlbumArtist", "A"))]
+        );
+
+        let found = check(&AlbumYear, album("Date", ["1999", "1999", "2000"]));
+        let fixes: Vec<_> = found.iter().map(|(p, _, f)| (*p, f.clone())).collect();
+        assert_eq!(
+            fixes,
+            [(
+                Path::new("/lib/X/3.flac"),
+                super::fix(Field::Date.tag(), "1999")
+            )]
+        );
+        assert!(check(&AlbumYear, album("Date", ["1999"; 3])).is_empty());
+    }
+
+    #[test]
+    fn track_numbers_are_unique_and_complete() {
+        let found = check(&TrackNumbering, album("TrackNumber", ["1", "1", "4"]));
+        let paths: Vec<_> = found.iter().map(|(p, m, _)| (*p, m.as_str())).collect();
+        assert_eq!(
+            paths,
+            [
+                (
+                    Path::new("/lib/X/2.flac"),
+                    "Track 1 of disc 1 is also /lib/X/1.flac"
+                ),
+                (Path::new("/lib/X"), "\"X\" is missing track 2, 3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn albums_mixing_codecs_are_reported_once() {
+        let mut tracks = album("Artist", ["A"; 3]);
+        tracks[1].codec = "mp3";
+        let found = check(&MixedCodecs, tracks);
+        assert_eq!(
+            found,
+            [(
+                Path::new("/lib/X"),
+                "\"X\" mixes flac, mp3".to_string(),
+                None
+            )]
+        );
+    }
+
+    #[test]
+    fn rules_disabled_in_config_are_left_out() {
+        let config = LintConfig {
+            disable: vec!["whitespace".to_string()],
+            ..Default::default()
+        };
+        let names: Vec<_> = default_rules(&RequiredTags::default(), &config)
+            .iter()
+            .map(|r| r.name())
+            .collect();
+        assert!(!names.contains(&"whitespace"));
+        assert!(names.contains(&"missing-tags"));
+    }
+
+    #[test]
+    fn conflicting_fixes_keep_the_first() {
+        let path = Path::new("/lib/a.flac");
+        let diagnostic = |rule, changes: &[(&str, &str)]| Diagnostic {
+            path,
+            rule,
+            severity: Severity::Warning,
+            message: String::new(),
+            fix: Some(
+                changes
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), Some(v.to_string())))
+                    .collect(),
+            ),
+        };
+        let diagnostics = [
+            diagnostic("album-artist", &[("AlbumArtist", "A")]),
+            diagnostic("whitespace", &[("AlbumArtist", "B")]),
+            diagnostic("whitespace", &[("Artist", "C")]),
+        ];
+        let [Transaction::WriteTags { path: fixed, tags }] = &fix_transactions(&diagnostics)[..]
+        else {
+            panic!("One song is fixed");
+        };
+        assert_eq!(fixed, path);
+        assert_eq!(
+            *tags,
+            TagChanges::from([
+                ("AlbumArtist".to_string(), Some("A".to_string())),
+                ("Artist".to_string(), Some("C".to_string())),
+            ])
+        );
+    }
+}
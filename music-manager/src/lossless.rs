@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use serde::Serialize;
+
+/// Samples per FFT window
+const WINDOW: usize = 4096;
+/// Lowest and highest lowpass searched for, lossy encoders cut somewhere in between
+const CUTOFF_RANGE: (f64, f64) = (11_000.0, 21_000.0);
+/// Width of the bands compared on either side of a cutoff
+const BAND_HZ: f64 = 1_000.0;
+/// Lowpass frequencies of common lossy encoder settings
+const LOSSY_SHELVES: &[f64] = &[16_000.0, 19_000.0, 20_000.0];
+/// How close a cutoff has to be to a shelf to count as one
+const SHELF_TOLERANCE: f64 = 300.0;
+/// Drops smaller than this are normal roll off
+const MIN_DROP: f64 = 10.0;
+/// Drops this large are as steep as lossy encoders get
+const MAX_DROP: f64 = 40.0;
+/// Bands quieter than this hold no content worth judging, in dB
+const MIN_LEVEL: f64 = -110.0;
+const SUSPICIOUS_SCORE: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LosslessCheck {
+    /// Frequency in Hz above which the spectrum falls away, if it does
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cutoff: Option<f64>,
+    /// Level difference in dB between the bands below and above the cutoff
+    pub drop: f64,
+    /// How likely the song was decoded from a lossy file, from 0 to 1
+    pub score: f64,
+}
+
+impl LosslessCheck {
+    pub fn is_suspicious(&self) -> bool {
+        self.score >= SUSPICIOUS_SCORE
+    }
+}
+
+/// Average power spectrum of the mono mix of a stream
+pub(crate) struct Spectrum {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    pending: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    power: Vec<f64>,
+    windows: u64,
+    sample_rate: u32,
+}
+
+impl Spectrum {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        let hann = (0..WINDOW)
+            .map(|i| {
+                let phase = std::f32::consts::TAU * i as f32 / (WINDOW - 1) as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(WINDOW),
+            window: hann,
+            pending: Vec::with_capacity(WINDOW),
+            buffer: Vec::with_capacity(WINDOW),
+            power: vec![0.0; WINDOW / 2],
+            windows: 0,
+            sample_rate,
+        }
+    }
+
+    /// Add interleaved samples
+    pub(crate) fn push(&mut self, samples: &[f32], channels: usize) {
+        for frame in samples.chunks_exact(channels) {
+            self.pending
+                .push(frame.iter().sum::<f32>() / channels as f32);
+            if self.pending.len() == WINDOW {
+                self.transform();
+            }
+        }
+    }
+
+    fn transform(&mut self) {
+        self.buffer.clear();
+        self.buffer.extend(
+            self.pending
+                .iter()
+                .zip(&self.window)
+                .map(|(s, w)| Complex::new(s * w, 0.0)),
+        );
+        self.pending.clear();
+        self.fft.process(&mut self.buffer);
+        for (power, bin) in self.power.iter_mut().zip(&self.buffer) {
+            *power += bin.norm_sqr() as f64;
+        }
+        self.windows += 1;
+    }
+
+    /// Look for the steepest drop in the spectrum where lossy encoders cut off
+    pub(crate) fn check(self) -> Option<LosslessCheck> {
+        let nyquist = self.sample_rate as f64 / 2.0;
+        if self.windows == 0 || nyquist < CUTOFF_RANGE.0 + BAND_HZ {
+            return None;
+        }
+        let bin_hz = self.sample_rate as f64 / WINDOW as f64;
+        let levels: Vec<f64> = self
+            .power
+            .iter()
+            .map(|p| 10.0 * (p / self.windows as f64 + 1e-20).log10())
+            .collect();
+        let band = (BAND_HZ / bin_hz) as usize;
+        let mean = |bins: &[f64]| bins.iter().sum::<f64>() / bins.len().max(1) as f64;
+
+        let first = (CUTOFF_RANGE.0 / bin_hz) as usize;
+        let last = ((CUTOFF_RANGE.1.min(nyquist - BAND_HZ)) / bin_hz) as usize;
+        let (cutoff, drop) = (first..=last)
+            .filter_map(|bin| {
+                let below = mean(&levels[bin - band..bin]);
+                let above = mean(&levels[bin..(bin + band).min(levels.len())]);
+                (below > MIN_LEVEL).then_some((bin, below - above))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(bin, drop)| (bin as f64 * bin_hz, drop))
+            .unwrap_or_default();
+
+        if drop < MIN_DROP {
+            return Some(LosslessCheck {
+                cutoff: None,
+                drop: drop.max(0.0),
+                score: 0.0,
+            });
+        }
+        let mut score = (drop - MIN_DROP) / (MAX_DROP - MIN_DROP);
+        if LOSSY_SHELVES
+            .iter()
+            .any(|shelf| (shelf - cutoff).abs() <= SHELF_TOLERANCE)
+        {
+            score += 0.2;
+        }
+        Some(LosslessCheck {
+            cutoff: Some(cutoff),
+            drop,
+            score: score.clamp(0.0, 1.0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 44100;
+
+    fn check(samples: &[f32], sample_rate: u32) -> Option<LosslessCheck> {
+        let mut spectrum = Spectrum::new(sample_rate);
+        spectrum.push(samples, 1);
+        spectrum.check()
+    }
+
+    /// Two seconds of uniform white noise, the same on every run
+    fn noise() -> Vec<f32> {
+        let mut state = 0x2545_f491_u32;
+        (0..2 * RATE)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state as f32 / u32::MAX as f32 - 0.5) * 0.5
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lowpassed_signals_look_lossy() {
+        // partials every 100 Hz up to where an MP3 encoder would cut
+        let samples: Vec<f32> = (0..2 * RATE)
+            .map(|i| {
+                let t = i as f64 / RATE as f64;
+                (1..=160)
+                    .map(|k| {
+                        (std::f64::consts::TAU * (k as f64 * 100.0 * t + k as f64 * 0.37)).sin()
+                    })
+                    .sum::<f64>() as f32
+                    * 0.005
+            })
+            .collect();
+        let check = check(&samples, RATE).unwrap();
+        let cutoff = check.cutoff.unwrap();
+        assert!((cutoff - 16_000.0).abs() < 100.0, "{check:?}");
+        assert!(check.drop > MAX_DROP, "{check:?}");
+        assert!(check.is_suspicious(), "{check:?}");
+    }
+
+    #[test]
+    fn full_band_noise_looks_lossless() {
+        let check = check(&noise(), RATE).unwrap();
+        assert_eq!(check.cutoff, None, "{check:?}");
+        assert_eq!(check.score, 0.0);
+        assert!(!check.is_suspicious());
+    }
+
+    #[test]
+    fn low_sample_rates_are_not_judged() {
+        assert_eq!(check(&noise(), 16000), None);
+        assert_eq!(check(&[], RATE), None);
+    }
+}
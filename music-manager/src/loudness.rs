@@ -0,0 +1,412 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use ebur128::{EbuR128, Mode};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::{
+    Error,
+    album::{Album, Track, group_albums},
+    decode::decode_samples,
+    sort::Transaction,
+    tags::{TagChanges, Tags, read_file_tags},
+};
+
+/// ReplayGain 2.0 reference loudness in LUFS
+const REPLAYGAIN_REFERENCE: f64 = -18.0;
+/// EBU R128 reference loudness in LUFS, used by `R128_*` tags
+const R128_REFERENCE: f64 = -23.0;
+
+/// Tags carrying gain data, checked for consistency within albums
+const GAIN_KEYS: &[&str] = &[
+    "ReplayGainTrackGain",
+    "ReplayGainAlbumGain",
+    "R128_TRACK_GAIN",
+    "R128_ALBUM_GAIN",
+];
+/// Gain tags that must be identical across an album
+const ALBUM_GAIN_KEYS: &[&str] = &["ReplayGainAlbumGain", "R128_ALBUM_GAIN"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GainTags {
+    /// `REPLAYGAIN_*` tags relative to -18 LUFS
+    #[default]
+    ReplayGain,
+    /// `R128_*` tags relative to -23 LUFS, as used by Opus
+    R128,
+}
+
+impl std::str::FromStr for GainTags {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "replaygain" => Ok(Self::ReplayGain),
+            "r128" => Ok(Self::R128),
+            _ => Err(format!("Unknown gain tags '{s}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for GainTags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReplayGain => write!(f, "replaygain"),
+            Self::R128 => write!(f, "r128"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Loudness {
+    /// Integrated loudness in LUFS
+    pub integrated: f64,
+    /// Loudness range in LU
+    pub range: f64,
+    /// True peak in dBTP
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    /// Fails with [`Error::Silent`] when the audio is silent or entirely below the gate
+    fn measure<'a>(
+        meters: impl Iterator<Item = &'a EbuR128> + Clone,
+        true_peak: f64,
+    ) -> crate::Result<Self> {
+        let loudness = Self {
+            integrated: EbuR128::loudness_global_multiple(meters.clone())?,
+            range: EbuR128::loudness_range_multiple(meters)?,
+            true_peak: 20.0 * true_peak.log10(),
+        };
+        match loudness.integrated.is_finite() && loudness.true_peak.is_finite() {
+            true => Ok(loudness),
+            false => Err(Error::Silent),
+        }
+    }
+
+    fn peak_linear(&self) -> f64 {
+        10f64.powf(self.true_peak / 20.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TrackLoudness<'a> {
+    pub path: &'a Path,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    pub loudness: Loudness,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AlbumLoudness<'a> {
+    pub album: String,
+    pub tracks: Vec<&'a Path>,
+    pub loudness: Loudness,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LoudnessReport<'a> {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tracks: Vec<TrackLoudness<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub albums: Vec<AlbumLoudness<'a>>,
+    /// Songs too quiet to measure, which get no gain tags
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub silent: Vec<&'a Path>,
+}
+
+/// Measure every song and every album, grouping albums as [`group_albums`] does. Songs too
+/// quiet to measure are reported apart.
+pub fn analyze_loudness(songs: &[PathBuf]) -> crate::Result<LoudnessReport<'_>> {
+    let measured = songs
+        .par_iter()
+        .map(|s| -> crate::Result<_> {
+            let Some((meter, peak)) = meter(s)? else {
+                warn!("Skipping {}: no audio", s.display());
+                return Ok(None);
+            };
+            let tags = read_file_tags(s, false)?;
+            let loudness = match Loudness::measure(std::iter::once(&meter), peak) {
+                Err(Error::Silent) => {
+                    // no gain tags are written for it
+                    error!("Skipping {}: {}", s.display(), Error::Silent);
+                    None
+                }
+                loudness => Some(loudness?),
+            };
+            Ok(Some((s.as_path(), tags, loudness, meter, peak)))
+        })
+        .filter_map(Result::transpose)
+        .collect::<crate::Result<Vec<_>>>()?;
+    let (measured, silent): (Vec<_>, Vec<_>) = measured
+        .into_iter()
+        .partition(|(_, _, l, _, _)| l.is_some());
+
+    let meters: HashMap<&Path, (&EbuR128, f64)> = measured
+        .iter()
+        .map(|(path, _, _, meter, peak)| (*path, (meter, *peak)))
+        .collect();
+    let tracks = measured
+        .iter()
+        .map(|(path, tags, ..)| Track::new(path, tags.clone(), "unknown", None))
+        .collect();
+    let (albums, _) = group_albums(tracks);
+    let albums = albums
+        .iter()
+        .map(|album| -> crate::Result<_> {
+            let tracks: Vec<_> = album.tracks.iter().map(|t| meters[t.path]).collect();
+            let peak = tracks.iter().map(|t| t.1).fold(0.0, f64::max);
+            Ok(AlbumLoudness {
+                album: album.title(),
+                loudness: Loudness::measure(tracks.iter().map(|t| t.0), peak)?,
+                tracks: album.tracks.iter().map(|t| t.path).collect(),
+            })
+        })
+        .collect::<crate::Result<Vec<_>>>()?;
+    let tracks = measured
+        .iter()
+        .map(|(path, tags, loudness, _, _)| TrackLoudness {
+            path,
+            album: album(tags),
+            loudness: loudness.expect("Silent songs are partitioned out"),
+        })
+        .collect();
+    let silent = silent.into_iter().map(|(path, ..)| path).collect();
+    Ok(LoudnessReport {
+        tracks,
+        albums,
+        silent,
+    })
+}
+
+/// Decode a song into a loudness meter, also returning its linear true peak
+fn meter(path: &Path) -> crate::Result<Option<(EbuR128, f64)>> {
+    let mut meter: Option<EbuR128> = None;
+    let info = decode_samples(path, |info, samples| {
+        let meter = match &mut meter {
+            Some(meter) => meter,
+            None => meter.insert(EbuR128::new(
+                info.channels as u32,
+                info.sample_rate,
+                Mode::I | Mode::LRA | Mode::TRUE_PEAK,
+            )?),
+        };
+        meter.add_frames_f32(samples)?;
+        Ok(())
+    })?;
+    let (Some(info), Some(meter)) = (info, meter) else {
+        return Ok(None);
+    };
+    let peak = (0..info.channels as u32)
+        .map(|c| meter.true_peak(c))
+        .try_fold(0.0, |max, peak| peak.map(|p| f64::max(max, p)))?;
+    Ok(Some((meter, peak)))
+}
+
+fn album(tags: &Tags) -> Option<String> {
+    tags.get("Album").map(|a| a.trim().to_string())
+}
+
+/// Tag writes storing the measured gain of every track and its album
+pub fn gain_transactions(report: &LoudnessReport, gain_tags: GainTags) -> Vec<Transaction> {
+    let albums: BTreeMap<&Path, &Loudness> = report
+        .albums
+        .iter()
+        .flat_map(|a| a.tracks.iter().map(|t| (*t, &a.loudness)))
+        .collect();
+    report
+        .tracks
+        .iter()
+        .map(|track| {
+            let mut changes = TagChanges::new();
+            let album = albums.get(track.path).copied();
+            match gain_tags {
+                GainTags::ReplayGain => {
+                    replaygain(&mut changes, "Track", &track.loudness);
+                    if let Some(album) = album {
+                        replaygain(&mut changes, "Album", album);
+                    }
+                }
+                GainTags::R128 => {
+                    r128(&mut changes, "TRACK", &track.loudness);
+                    if let Some(album) = album {
+                        r128(&mut changes, "ALBUM", album);
+                    }
+                }
+            }
+            Transaction::WriteTags {
+                path: track.path.to_path_buf(),
+                tags: changes,
+            }
+        })
+        .collect()
+}
+
+fn replaygain(changes: &mut TagChanges, scope: &str, loudness: &Loudness) {
+    changes.insert(
+        format!("ReplayGain{scope}Gain"),
+        Some(format!(
+            "{:.2} dB",
+            REPLAYGAIN_REFERENCE - loudness.integrated
+        )),
+    );
+    changes.insert(
+        format!("ReplayGain{scope}Peak"),
+        Some(format!("{:.6}", loudness.peak_linear())),
+    );
+}
+
+fn r128(changes: &mut TagChanges, scope: &str, loudness: &Loudness) {
+    // Q7.8 fixed point
+    let gain = ((R128_REFERENCE - loudness.integrated) * 256.0)
+        .round()
+        .clamp(i16::MIN as f64, i16::MAX as f64);
+    changes.insert(format!("R128_{scope}_GAIN"), Some(format!("{gain}")));
+}
+
+/// Albums whose tracks disagree on their gain data, or only some of which have it
+pub fn inconsistent_gain_albums(songs: &[(&Path, Tags)]) -> Vec<String> {
+    let tracks = songs
+        .iter()
+        .map(|(path, tags)| Track::new(path, tags.clone(), "unknown", None))
+        .collect();
+    let (albums, _) = group_albums(tracks);
+    albums
+        .iter()
+        .filter(|album| {
+            let tracks = &album.tracks;
+            let partial = GAIN_KEYS.iter().any(|key| {
+                let tagged = tracks.iter().filter(|t| t.tags.contains_key(*key)).count();
+                tagged != 0 && tagged != tracks.len()
+            });
+            let mismatched = ALBUM_GAIN_KEYS.iter().any(|key| {
+                let values: BTreeSet<_> = tracks.iter().filter_map(|t| t.tags.get(*key)).collect();
+                values.len() > 1
+            });
+            partial || mismatched
+        })
+        .map(Album::title)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cue::write_flac,
+        tags::{TagChanges, write_tags},
+    };
+
+    /// Write a second of a 440 Hz tone `amplitude` loud to `path`
+    fn write_tone(path: &Path, amplitude: f64) {
+        let samples: Vec<i32> = (0..44100)
+            .map(|i| {
+                let phase = i as f64 * 440.0 * std::f64::consts::TAU / 44100.0;
+                (amplitude * phase.sin() * i16::MAX as f64) as i32
+            })
+            .collect();
+        write_flac(path, &samples, 1, 16, 44100).unwrap();
+        let album = TagChanges::from([("Album".to_string(), Some("Album".to_string()))]);
+        write_tags(path, &album).unwrap();
+    }
+
+    #[test]
+    fn silent_songs_get_no_gain_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let (loud, silent) = (dir.path().join("loud.flac"), dir.path().join("silent.flac"));
+        write_tone(&loud, 0.5);
+        write_tone(&silent, 0.0);
+
+        let songs = vec![loud.clone(), silent.clone()];
+        let report = analyze_loudness(&songs).unwrap();
+        let tracks: Vec<&Path> = report.tracks.iter().map(|t| t.path).collect();
+        assert_eq!(tracks, [loud.as_path()]);
+        assert_eq!(report.albums[0].tracks, [loud.as_path()]);
+        assert!(report.albums[0].loudness.integrated.is_finite());
+        assert_eq!(report.silent, [silent.as_path()]);
+
+        let transactions = gain_transactions(&report, GainTags::ReplayGain);
+        assert_eq!(transactions.len(), 1);
+    }
+
+    /// Tag `path` as a track of `artist`'s Greatest Hits
+    fn greatest_hits(path: &Path, artist: &str) -> Tags {
+        let changes = TagChanges::from([
+            ("Album".to_string(), Some("Greatest Hits".to_string())),
+            ("AlbumArtist".to_string(), Some(artist.to_string())),
+        ]);
+        write_tags(path, &changes).unwrap();
+        read_file_tags(path, false).unwrap()
+    }
+
+    #[test]
+    fn same_named_albums_are_measured_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("A"), dir.path().join("B"));
+        std::fs::create_dir_all(&a).unwrap();
+        std::fs::create_dir_all(&b).unwrap();
+        let (quiet, loud) = (a.join("01.flac"), b.join("01.flac"));
+        write_tone(&quiet, 0.1);
+        write_tone(&loud, 0.8);
+        greatest_hits(&quiet, "Artist A");
+        greatest_hits(&loud, "Artist B");
+
+        let songs = vec![quiet.clone(), loud.clone()];
+        let report = analyze_loudness(&songs).unwrap();
+        let albums: Vec<_> = report
+            .albums
+            .iter()
+            .map(|a| (a.album.as_str(), a.tracks.clone()))
+            .collect();
+        assert_eq!(
+            albums,
+            [
+                ("Artist A - Greatest Hits", vec![quiet.as_path()]),
+                ("Artist B - Greatest Hits", vec![loud.as_path()]),
+            ]
+        );
+        let (q, l) = (&report.albums[0].loudness, &report.albums[1].loudness);
+        assert!(q.integrated < l.integrated - 10.0);
+    }
+
+    #[test]
+    fn gain_consistency_is_checked_per_album() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("A"), dir.path().join("B"));
+        std::fs::create_dir_all(&a).unwrap();
+        std::fs::create_dir_all(&b).unwrap();
+        let songs: Vec<PathBuf> = [a.join("01.flac"), b.join("01.flac"), b.join("02.flac")].into();
+        for (song, artist) in songs.iter().zip(["Artist A", "Artist B", "Artist B"]) {
+            write_tone(song, 0.5);
+            greatest_hits(song, artist);
+        }
+        let gain = |value: &str| {
+            TagChanges::from([("ReplayGainAlbumGain".to_string(), Some(value.to_string()))])
+        };
+        // each album is consistent on its own, but their gains differ
+        write_tags(&songs[0], &gain("-3.00 dB")).unwrap();
+        write_tags(&songs[1], &gain("-6.00 dB")).unwrap();
+        write_tags(&songs[2], &gain("-6.00 dB")).unwrap();
+        let tagged = || -> Vec<(&Path, Tags)> {
+            songs
+                .iter()
+                .map(|s| (s.as_path(), read_file_tags(s, false).unwrap()))
+                .collect()
+        };
+        assert!(inconsistent_gain_albums(&tagged()).is_empty());
+
+        write_tags(&songs[2], &gain("-7.00 dB")).unwrap();
+        assert_eq!(
+            inconsistent_gain_albums(&tagged()),
+            ["Artist B - Greatest Hits"]
+        );
+    }
+}
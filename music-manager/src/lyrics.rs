@@ -0,0 +1,300 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use lofty::{
+    config::ParseOptions,
+    file::{AudioFile, TaggedFileExt},
+    id3::v2::{Frame, SynchronizedTextFrame, TimestampFormat},
+    mpeg::MpegFile,
+    tag::ItemKey,
+};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    sheet::TagDiff,
+    sort::Transaction,
+    tags::{Tags, read_file_tags},
+};
+
+/// The tag embedded lyrics are written to, USLT in ID3v2 and `LYRICS` in Vorbis comments
+pub const LYRICS_KEY: &str = "Lyrics";
+
+/// The lyrics of a song, embedded in its tags or in an `.lrc` file beside it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Lyrics {
+    /// Embedded lyrics without timestamps, from USLT frames or `LYRICS` comments
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unsynced: Option<String>,
+    /// Embedded timestamped lyrics as LRC, from SYLT frames or `LYRICS` comments holding LRC
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub synced: Option<String>,
+    /// The `.lrc` file beside the song
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sidecar: Option<PathBuf>,
+}
+
+impl Lyrics {
+    pub fn read(path: &Path) -> crate::Result<Self> {
+        let mut lyrics = Self::default();
+        let tagged = lofty::read_from_path(path)?;
+        let embedded = tagged
+            .tags()
+            .iter()
+            .find_map(|t| t.get_string(&ItemKey::Lyrics))
+            .filter(|l| !l.trim().is_empty());
+        match embedded {
+            Some(text) if is_lrc(text) => lyrics.synced = Some(text.to_string()),
+            Some(text) => lyrics.unsynced = Some(text.to_string()),
+            None => {}
+        }
+        if lyrics.synced.is_none() && is_mpeg(path) {
+            lyrics.synced = read_sylt(path)?;
+        }
+        let sidecar = sidecar_path(path);
+        lyrics.sidecar = sidecar.is_file().then_some(sidecar);
+        Ok(lyrics)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unsynced.is_none() && self.synced.is_none() && self.sidecar.is_none()
+    }
+
+    /// The embedded lyrics, timestamped ones if there are any
+    pub fn embedded(&self) -> Option<&str> {
+        self.synced.as_deref().or(self.unsynced.as_deref())
+    }
+}
+
+/// Whether the song at `path` with `tags` has lyrics, embedded or beside it
+pub fn has_lyrics(path: &Path, tags: &Tags) -> bool {
+    tags.get(LYRICS_KEY).is_some_and(|l| !l.trim().is_empty())
+        || sidecar_path(path).is_file()
+        || (is_mpeg(path) && read_sylt(path).ok().flatten().is_some())
+}
+
+/// Where the `.lrc` file of a song is
+pub fn sidecar_path(song: &Path) -> PathBuf {
+    song.with_extension("lrc")
+}
+
+fn is_mpeg(path: &Path) -> bool {
+    path.extension().is_some_and(|e| {
+        ["mp1", "mp2", "mp3"]
+            .iter()
+            .any(|m| e.eq_ignore_ascii_case(m))
+    })
+}
+
+/// Whether `text` has LRC timestamps such as `[01:23.45]`
+pub fn is_lrc(text: &str) -> bool {
+    text.lines().any(|line| {
+        let Some((time, _)) = line
+            .trim()
+            .strip_prefix('[')
+            .and_then(|l| l.split_once(']'))
+        else {
+            return false;
+        };
+        let Some((minutes, seconds)) = time.split_once(':') else {
+            return false;
+        };
+        !minutes.is_empty()
+            && minutes.chars().all(|c| c.is_ascii_digit())
+            && seconds.parse::<f64>().is_ok()
+    })
+}
+
+/// The first SYLT frame of an MPEG file as LRC, skipping those timed in MPEG frames
+fn read_sylt(path: &Path) -> crate::Result<Option<String>> {
+    let file = MpegFile::read_from(&mut File::open(path)?, ParseOptions::new())?;
+    let Some(id3v2) = file.id3v2() else {
+        return Ok(None);
+    };
+    let sylt = id3v2.into_iter().find_map(|frame| match frame {
+        Frame::Binary(binary) if frame.id().as_str() == "SYLT" => {
+            SynchronizedTextFrame::parse(&binary.data, frame.flags()).ok()
+        }
+        _ => None,
+    });
+    Ok(sylt
+        .filter(|s| s.timestamp_format == TimestampFormat::MS)
+        .map(|s| to_lrc(&s.content)))
+}
+
+/// Lines timed in milliseconds as LRC
+fn to_lrc(lines: &[(u32, String)]) -> String {
+    lines
+        .iter()
+        .map(|(ms, text)| {
+            let centis = ms / 10;
+            format!(
+                "[{:02}:{:02}.{:02}]{}\n",
+                centis / 6000,
+                centis / 100 % 60,
+                centis % 100,
+                text.trim_end_matches(['\n', '\r'])
+            )
+        })
+        .collect()
+}
+
+/// Embed the `.lrc` file of every song having one, keeping lyrics already embedded
+/// unless `overwrite` is set
+pub fn embed_diffs(
+    songs: &[impl AsRef<Path> + Sync],
+    overwrite: bool,
+) -> crate::Result<Vec<TagDiff>> {
+    let diffs: crate::Result<Vec<Option<TagDiff>>> = songs
+        .par_iter()
+        .map(|song| {
+            let song = song.as_ref();
+            let sidecar = sidecar_path(song);
+            if !sidecar.is_file() {
+                return Ok(None);
+            }
+            let lrc = std::fs::read_to_string(&sidecar)?;
+            let lrc = lrc.trim_start_matches('\u{feff}').trim_end();
+            let tags = read_file_tags(song, false)?;
+            let old: Tags = tags
+                .get(LYRICS_KEY)
+                .map(|l| (LYRICS_KEY.to_string(), l.clone()))
+                .into_iter()
+                .collect();
+            if !old.is_empty() && !overwrite {
+                return Ok(None);
+            }
+            let new = Tags::from([(LYRICS_KEY.to_string(), lrc.to_string())]);
+            Ok(TagDiff::between(song, &old, &new))
+        })
+        .collect();
+    Ok(diffs?.into_iter().flatten().collect())
+}
+
+/// Write the embedded lyrics of every song to an `.lrc` file beside it, keeping
+/// existing files unless `overwrite` is set
+pub fn extract_transactions(
+    songs: &[impl AsRef<Path> + Sync],
+    overwrite: bool,
+) -> crate::Result<Vec<Transaction>> {
+    let transactions: crate::Result<Vec<Option<Transaction>>> = songs
+        .par_iter()
+        .map(|song| {
+            let lyrics = Lyrics::read(song.as_ref())?;
+            let Some(embedded) = lyrics.embedded() else {
+                return Ok(None);
+            };
+            let contents = format!("{}\n", embedded.trim_end());
+            let path = sidecar_path(song.as_ref());
+            let unchanged = match &lyrics.sidecar {
+                Some(sidecar) => !overwrite || std::fs::read_to_string(sidecar)? == contents,
+                None => false,
+            };
+            Ok((!unchanged).then_some(Transaction::WriteFile { path, contents }))
+        })
+        .collect();
+    Ok(transactions?.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_song;
+
+    const LRC: &str = "[00:01.00]First line\n[00:02.50]Second line";
+
+    #[test]
+    fn embedded_lyrics_are_synced_when_timestamped() {
+        let dir = tempfile::tempdir().unwrap();
+        let (synced, unsynced) = (dir.path().join("a.flac"), dir.path().join("b.flac"));
+        write_song(&synced, 1, &[(LYRICS_KEY, LRC)]);
+        write_song(&unsynced, 2, &[(LYRICS_KEY, "Just words")]);
+
+        let lyrics = Lyrics::read(&synced).unwrap();
+        assert_eq!(lyrics.synced.as_deref(), Some(LRC));
+        assert_eq!(lyrics.unsynced, None);
+        assert_eq!(lyrics.embedded(), Some(LRC));
+
+        let lyrics = Lyrics::read(&unsynced).unwrap();
+        assert_eq!(lyrics.embedded(), Some("Just words"));
+        assert!(lyrics.sidecar.is_none());
+    }
+
+    #[test]
+    fn sidecars_are_found_beside_songs() {
+        let dir = tempfile::tempdir().unwrap();
+        let song = dir.path().join("song.flac");
+        write_song(&song, 1, &[]);
+        assert!(Lyrics::read(&song).unwrap().is_empty());
+
+        std::fs::write(sidecar_path(&song), LRC).unwrap();
+        let lyrics = Lyrics::read(&song).unwrap();
+        assert_eq!(lyrics.sidecar, Some(dir.path().join("song.lrc")));
+        assert_eq!(lyrics.embedded(), None);
+        assert!(has_lyrics(&song, &Tags::new()));
+    }
+
+    #[test]
+    fn embedding_keeps_embedded_lyrics_unless_overwriting() {
+        let dir = tempfile::tempdir().unwrap();
+        let (bare, embedded) = (dir.path().join("a.flac"), dir.path().join("b.flac"));
+        write_song(&bare, 1, &[]);
+        write_song(&embedded, 2, &[(LYRICS_KEY, "Old words")]);
+        for song in [&bare, &embedded] {
+            std::fs::write(sidecar_path(song), format!("\u{feff}{LRC}\n")).unwrap();
+        }
+        let songs = [bare.clone(), embedded.clone()];
+
+        let diffs = embed_diffs(&songs, false).unwrap();
+        let paths: Vec<_> = diffs.iter().map(|d| &d.path).collect();
+        assert_eq!(paths, [&bare]);
+
+        let diffs = embed_diffs(&songs, true).unwrap();
+        let expected = Tags::from([(LYRICS_KEY.to_string(), LRC.to_string())]);
+        let old = Tags::from([(LYRICS_KEY.to_string(), "Old words".to_string())]);
+        assert_eq!(
+            diffs,
+            [
+                TagDiff::between(&bare, &Tags::new(), &expected).unwrap(),
+                TagDiff::between(&embedded, &old, &expected).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn extracting_keeps_sidecars_unless_overwriting() {
+        let dir = tempfile::tempdir().unwrap();
+        let (fresh, stale, current) = (
+            dir.path().join("a.flac"),
+            dir.path().join("b.flac"),
+            dir.path().join("c.flac"),
+        );
+        for (seed, song) in [&fresh, &stale, &current].into_iter().enumerate() {
+            write_song(song, seed as i32 + 1, &[(LYRICS_KEY, LRC)]);
+        }
+        std::fs::write(sidecar_path(&stale), "[00:01.00]Other words\n").unwrap();
+        std::fs::write(sidecar_path(&current), format!("{LRC}\n")).unwrap();
+        let songs = [fresh.clone(), stale.clone(), current.clone()];
+        let write = |song: &Path| Transaction::WriteFile {
+            path: sidecar_path(song),
+            contents: format!("{LRC}\n"),
+        };
+
+        let transactions = extract_transactions(&songs, false).unwrap();
+        assert_eq!(transactions, [write(&fresh)]);
+
+        let transactions = extract_transactions(&songs, true).unwrap();
+        assert_eq!(transactions, [write(&fresh), write(&stale)]);
+    }
+
+    #[test]
+    fn lrc_needs_timestamps() {
+        assert!(is_lrc(LRC));
+        assert!(is_lrc("[ar:Artist]\n[01:02]Line"));
+        assert!(!is_lrc("[Chorus]\nWords"));
+        assert!(!is_lrc("Words"));
+    }
+}
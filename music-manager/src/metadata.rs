@@ -13,10 +13,10 @@ pub fn get_title(probed: &mut ProbeResult) -> Option<String> {
 
 pub fn get_standard_metadata(probed: &mut ProbeResult, key: StandardTagKey) -> Option<String> {
     // try from metadata in container format
-    if let Some(mut metadata) = probed.metadata.get() {
-        if let Some(val) = try_get_key(&mut metadata, key) {
-            return Some(val);
-        }
+    if let Some(mut metadata) = probed.metadata.get()
+        && let Some(val) = try_get_key(&mut metadata, key)
+    {
+        return Some(val);
     }
 
     // try other metadata
@@ -29,10 +29,10 @@ pub fn get_standard_metadata(probed: &mut ProbeResult, key: StandardTagKey) -> O
 fn try_get_key(metadata: &mut Metadata, key: StandardTagKey) -> Option<String> {
     let metadata = metadata.skip_to_latest()?;
     for tag in metadata.tags() {
-        if let Some(k) = tag.std_key {
-            if k == key {
-                return Some(tag.value.to_string());
-            }
+        if let Some(k) = tag.std_key
+            && k == key
+        {
+            return Some(tag.value.to_string());
         }
     }
     None
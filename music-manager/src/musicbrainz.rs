@@ -0,0 +1,561 @@
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    album::{Album, Track},
+    sheet::TagDiff,
+    tags::{Tags, read_file_tags},
+};
+
+/// Track durations closer than this are the same, in seconds
+const DURATION_GRACE: f64 = 10.0;
+/// Duration differences past the grace this large count as entirely different
+const DURATION_MAX: f64 = 30.0;
+/// Releases below this album title similarity with a different track count are skipped
+const MIN_TITLE_SIMILARITY: f64 = 0.5;
+
+/// Weights of the parts of a match distance
+const ALBUM_WEIGHT: f64 = 3.0;
+const ARTIST_WEIGHT: f64 = 3.0;
+const TRACK_COUNT_WEIGHT: f64 = 2.0;
+const TRACK_TITLE_WEIGHT: f64 = 2.0;
+const DURATION_WEIGHT: f64 = 2.0;
+const YEAR_WEIGHT: f64 = 1.0;
+
+/// A release as found in MusicBrainz JSON dumps and web service responses
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(rename = "artist-credit", default)]
+    pub artist_credit: Vec<ArtistCredit>,
+    #[serde(rename = "release-group", default)]
+    pub release_group: Option<ReleaseGroup>,
+    #[serde(default)]
+    pub media: Vec<Medium>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArtistCredit {
+    pub name: String,
+    #[serde(default)]
+    pub joinphrase: String,
+    pub artist: Artist,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Artist {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReleaseGroup {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Medium {
+    pub position: u32,
+    #[serde(default)]
+    pub tracks: Vec<ReleaseTrack>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReleaseTrack {
+    pub id: String,
+    pub position: u32,
+    pub title: String,
+    /// Length in milliseconds
+    #[serde(default)]
+    pub length: Option<u64>,
+    pub recording: Recording,
+    #[serde(rename = "artist-credit", default)]
+    pub artist_credit: Vec<ArtistCredit>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Recording {
+    pub id: String,
+}
+
+impl Release {
+    pub fn artist(&self) -> String {
+        credit_name(&self.artist_credit)
+    }
+
+    /// Every track with the position of its medium
+    fn tracks(&self) -> impl Iterator<Item = (u32, &ReleaseTrack)> {
+        self.media
+            .iter()
+            .flat_map(|m| m.tracks.iter().map(move |t| (m.position, t)))
+    }
+
+    fn track_count(&self) -> usize {
+        self.media.iter().map(|m| m.tracks.len()).sum()
+    }
+
+    fn year(&self) -> Option<&str> {
+        self.date.as_deref().and_then(|d| d.get(..4))
+    }
+}
+
+fn credit_name(credits: &[ArtistCredit]) -> String {
+    credits
+        .iter()
+        .map(|c| format!("{}{}", c.name, c.joinphrase))
+        .collect()
+}
+
+/// The release closest to an album
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Candidate {
+    pub release_id: String,
+    pub title: String,
+    pub artist: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    /// From 0 for a perfect match to 1 for nothing in common
+    pub distance: f64,
+    #[serde(skip)]
+    pub release: Release,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MatchReport<'a> {
+    pub albums: Vec<AlbumMatch<'a>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AlbumMatch<'a> {
+    pub dir: &'a Path,
+    pub album: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidate: Option<Candidate>,
+}
+
+/// Find the closest release for each album in one pass over a dump at `database`, either
+/// a JSON array of releases or a release per line as in MusicBrainz's JSON dumps
+pub fn match_albums<'a>(database: &Path, albums: &[Album<'a>]) -> crate::Result<MatchReport<'a>> {
+    let mut reader = BufReader::new(File::open(database)?);
+    let is_array = reader.fill_buf()?.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[');
+    let best = if is_array {
+        let mut json = String::new();
+        reader.read_to_string(&mut json)?;
+        let releases: Vec<Release> = serde_json::from_str(&json)?;
+        releases
+            .into_iter()
+            .fold(vec![None; albums.len()], |best, release| {
+                consider(best, albums, release)
+            })
+    } else {
+        reader
+            .lines()
+            .par_bridge()
+            .try_fold(
+                || vec![None; albums.len()],
+                |best, line| -> crate::Result<_> {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        return Ok(best);
+                    }
+                    Ok(consider(best, albums, serde_json::from_str(&line)?))
+                },
+            )
+            .try_reduce(
+                || vec![None; albums.len()],
+                |a, b| Ok(a.into_iter().zip(b).map(|(a, b)| closest(a, b)).collect()),
+            )?
+    };
+    let albums = albums
+        .iter()
+        .zip(best)
+        .map(|(album, candidate)| AlbumMatch {
+            dir: album.dir,
+            album: album.title(),
+            candidate,
+        })
+        .collect();
+    Ok(MatchReport { albums })
+}
+
+fn consider(
+    best: Vec<Option<Candidate>>,
+    albums: &[Album],
+    release: Release,
+) -> Vec<Option<Candidate>> {
+    best.into_iter()
+        .zip(albums)
+        .map(|(best, album)| match distance(album, &release) {
+            Some(distance) if best.as_ref().is_none_or(|b| distance < b.distance) => {
+                Some(Candidate {
+                    release_id: release.id.clone(),
+                    title: release.title.clone(),
+                    artist: release.artist(),
+                    date: release.date.clone(),
+                    distance,
+                    release: release.clone(),
+                })
+            }
+            _ => best,
+        })
+        .collect()
+}
+
+fn closest(a: Option<Candidate>, b: Option<Candidate>) -> Option<Candidate> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b.distance < a.distance { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// How different `album` is from `release`, `None` if it is not worth comparing
+pub fn distance(album: &Album, release: &Release) -> Option<f64> {
+    let title = similarity(&album.name, &release.title);
+    let count = release.track_count();
+    if title < MIN_TITLE_SIMILARITY && count != album.tracks.len() {
+        return None;
+    }
+    let mut parts = vec![(ALBUM_WEIGHT, 1.0 - title)];
+    if let Some(artist) = &album.artist {
+        parts.push((ARTIST_WEIGHT, 1.0 - similarity(artist, &release.artist())));
+    }
+    let max_count = count.max(album.tracks.len()).max(1) as f64;
+    parts.push((
+        TRACK_COUNT_WEIGHT,
+        count.abs_diff(album.tracks.len()) as f64 / max_count,
+    ));
+
+    let pairs = pair_tracks(&album.tracks, release);
+    if !pairs.is_empty() {
+        let titles = pairs
+            .iter()
+            .map(|(track, rt)| {
+                track
+                    .title
+                    .as_deref()
+                    .map_or(1.0, |t| 1.0 - similarity(t, &rt.title))
+            })
+            .sum::<f64>();
+        parts.push((TRACK_TITLE_WEIGHT, titles / pairs.len() as f64));
+        let durations: Vec<f64> = pairs
+            .iter()
+            .filter_map(|(track, rt)| {
+                let difference = (track.duration? - rt.length? as f64 / 1000.0).abs();
+                Some(((difference - DURATION_GRACE) / DURATION_MAX).clamp(0.0, 1.0))
+            })
+            .collect();
+        if !durations.is_empty() {
+            let mean = durations.iter().sum::<f64>() / durations.len() as f64;
+            parts.push((DURATION_WEIGHT, mean));
+        }
+    }
+    if let (Some(year), Some(release_year)) = (&album.year, release.year()) {
+        parts.push((YEAR_WEIGHT, if year == release_year { 0.0 } else { 1.0 }));
+    }
+    let weights: f64 = parts.iter().map(|(w, _)| w).sum();
+    Some(parts.iter().map(|(w, d)| w * d).sum::<f64>() / weights)
+}
+
+/// Pair each track with the release track of the same disc and number, or the one in its
+/// place when tracks are unnumbered
+fn pair_tracks<'t, 'r>(
+    tracks: &'t [Track<'t>],
+    release: &'r Release,
+) -> Vec<(&'t Track<'t>, &'r ReleaseTrack)> {
+    let numbered = tracks.iter().all(|t| t.number.is_some());
+    tracks
+        .iter()
+        .enumerate()
+        .filter_map(|(i, track)| {
+            let found = match numbered {
+                true => release.tracks().find(|(disc, rt)| {
+                    *disc == track.disc.unwrap_or(1) && Some(rt.position) == track.number
+                }),
+                false => release.tracks().nth(i),
+            };
+            found.map(|(_, rt)| (track, rt))
+        })
+        .collect()
+}
+
+/// The canonical tags of each of the album's tracks, as `TagDiff`s against their current
+/// tags. CUE images only get the release's tags, once, as their virtual tracks share them.
+pub fn release_diffs(album: &Album, release: &Release) -> crate::Result<Vec<TagDiff>> {
+    let discs = release.media.len();
+    let mut diffs = Vec::new();
+    let images: BTreeSet<&Path> = album
+        .tracks
+        .iter()
+        .filter(|t| t.offset.is_some())
+        .map(|t| t.path)
+        .collect();
+    for image in images {
+        let old = read_file_tags(image, false)?;
+        let mut new = old.clone();
+        new.extend(release_tags(release));
+        diffs.extend(TagDiff::between(image, &old, &new));
+    }
+    for (track, rt) in pair_tracks(&album.tracks, release) {
+        if track.offset.is_some() {
+            continue;
+        }
+        let old = read_file_tags(track.path, false)?;
+        let mut new = old.clone();
+        new.extend(release_tags(release));
+        let disc = release
+            .media
+            .iter()
+            .find(|m| m.tracks.iter().any(|t| t.id == rt.id));
+        let artist = match rt.artist_credit.is_empty() {
+            true => &release.artist_credit,
+            false => &rt.artist_credit,
+        };
+        new.extend(tags([
+            ("TrackTitle", Some(rt.title.clone())),
+            ("TrackNumber", Some(rt.position.to_string())),
+            ("TrackTotal", disc.map(|d| d.tracks.len().to_string())),
+            (
+                "DiscNumber",
+                disc.filter(|_| discs > 1).map(|d| d.position.to_string()),
+            ),
+            ("Artist", Some(credit_name(artist))),
+            // the Picard layout, where the track id tag holds the recording
+            ("MusicBrainzTrackId", Some(rt.recording.id.clone())),
+            ("MusicBrainzReleaseTrackId", Some(rt.id.clone())),
+            (
+                "MusicBrainzArtistId",
+                artist.first().map(|c| c.artist.id.clone()),
+            ),
+        ]));
+        diffs.extend(TagDiff::between(track.path, &old, &new));
+    }
+    Ok(diffs)
+}
+
+fn release_tags(release: &Release) -> Tags {
+    tags([
+        ("Album", Some(release.title.clone())),
+        ("AlbumArtist", Some(release.artist())),
+        ("Date", release.date.clone()),
+        ("MusicBrainzAlbumId", Some(release.id.clone())),
+        (
+            "MusicBrainzAlbumArtistId",
+            release.artist_credit.first().map(|c| c.artist.id.clone()),
+        ),
+        (
+            "MusicBrainzReleaseGroupId",
+            release.release_group.as_ref().map(|g| g.id.clone()),
+        ),
+    ])
+}
+
+fn tags<const N: usize>(values: [(&str, Option<String>); N]) -> Tags {
+    values
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value.filter(|v| !v.is_empty())?)))
+        .collect()
+}
+
+/// How alike two titles are from 0 to 1, ignoring case and punctuation
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (simplify(a), simplify(b));
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(&a, &b) as f64 / longest as f64
+}
+
+pub(crate) fn simplify(s: &str) -> Vec<char> {
+    s.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .collect()
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{album::Library, test_util::write_song};
+
+    const TITLES: [&str; 3] = ["Intro", "Svefn-g-englar", "Starálfur"];
+    const DURATIONS: [f64; 3] = [96.0, 603.0, 410.0];
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/musicbrainz")
+            .join(name)
+    }
+
+    fn releases() -> Vec<Release> {
+        serde_json::from_reader(File::open(fixture("releases.json")).unwrap()).unwrap()
+    }
+
+    /// Songs of the album as ripped, tagged without MusicBrainz ids
+    fn write_album(dir: &Path) -> Vec<PathBuf> {
+        (1..=3)
+            .map(|n| {
+                let path = dir.join(format!("0{n}.flac"));
+                let tags = [
+                    ("Album", "Ágætis byrjun"),
+                    ("Artist", "Sigur Rós"),
+                    ("Date", "1999"),
+                    ("TrackNumber", &n.to_string()),
+                    ("TrackTitle", TITLES[n - 1]),
+                ];
+                write_song(&path, n as i32, &tags);
+                path
+            })
+            .collect()
+    }
+
+    fn album(songs: &[PathBuf]) -> Album<'_> {
+        let tracks = songs
+            .iter()
+            .zip(DURATIONS)
+            .map(|(song, duration)| {
+                let tags = read_file_tags(song, false).unwrap();
+                Track::new(song, tags, "flac", Some(duration))
+            })
+            .collect();
+        let mut library = Library::new(tracks);
+        library.albums.remove(0)
+    }
+
+    #[test]
+    fn distances_rank_releases() {
+        let dir = tempfile::tempdir().unwrap();
+        let songs = write_album(dir.path());
+        let album = album(&songs);
+        let [original, reissue, takk] = &releases()[..] else {
+            panic!("The fixture has three releases");
+        };
+        let original = distance(&album, original).unwrap();
+        let reissue = distance(&album, reissue).unwrap();
+        assert!(original < 0.01, "{original}");
+        assert!(original < reissue && reissue < 0.5, "{reissue}");
+        assert_eq!(distance(&album, takk), None);
+    }
+
+    #[test]
+    fn dumps_as_array_or_lines_match_alike() {
+        let dir = tempfile::tempdir().unwrap();
+        let songs = write_album(dir.path());
+        let albums = [album(&songs)];
+        for dump in ["releases.json", "releases.ndjson"] {
+            let report = match_albums(&fixture(dump), &albums).unwrap();
+            let [matched] = &report.albums[..] else {
+                panic!("{dump}: {report:?}");
+            };
+            assert_eq!(matched.dir, dir.path());
+            let candidate = matched.candidate.as_ref().unwrap();
+            assert_eq!(candidate.release_id, "release-1999", "{dump}");
+            assert_eq!(candidate.artist, "Sigur Rós");
+            assert_eq!(candidate.date.as_deref(), Some("1999-06-12"));
+        }
+    }
+
+    #[test]
+    fn release_diffs_add_canonical_tags_and_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let songs = write_album(dir.path());
+        let album = album(&songs);
+        let diffs = release_diffs(&album, &releases()[0]).unwrap();
+        assert_eq!(diffs.len(), 3);
+
+        let new = |diff: &TagDiff, key: &str| diff.changes.get(key).map(|c| c.new.clone());
+        let values = |value: &str| Some(vec![value.to_string()]);
+        let third = &diffs[2];
+        assert_eq!(third.path, songs[2]);
+        assert_eq!(new(third, "Album"), None);
+        assert_eq!(new(third, "Date"), values("1999-06-12"));
+        assert_eq!(new(third, "Artist"), values("Sigur Rós & Amiina"));
+        assert_eq!(new(third, "AlbumArtist"), values("Sigur Rós"));
+        assert_eq!(new(third, "TrackTotal"), values("3"));
+        assert_eq!(new(third, "DiscNumber"), None);
+        assert_eq!(new(third, "MusicBrainzTrackId"), values("recording-3"));
+        assert_eq!(new(third, "MusicBrainzReleaseTrackId"), values("track-3"));
+        assert_eq!(
+            new(third, "MusicBrainzReleaseGroupId"),
+            values("group-agaetis")
+        );
+        assert_eq!(
+            new(third, "MusicBrainzArtistId"),
+            values("f6f2326f-6b25-4170-b89d-e235b25508e8")
+        );
+        assert_eq!(new(&diffs[0], "Artist"), None);
+        assert_eq!(new(&diffs[0], "MusicBrainzAlbumId"), values("release-1999"));
+    }
+
+    #[test]
+    fn cue_images_get_the_release_tags_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("image.flac");
+        write_song(&image, 1, &[("Album", "Ágætis byrjun"), ("Date", "1999")]);
+        let tracks: String = TITLES
+            .iter()
+            .enumerate()
+            .map(|(i, title)| {
+                format!(
+                    "  TRACK 0{} AUDIO\n    TITLE \"{title}\"\n    INDEX 01 00:00:0{}\n",
+                    i + 1,
+                    i * 2
+                )
+            })
+            .collect();
+        std::fs::write(
+            dir.path().join("image.cue"),
+            format!("PERFORMER \"Sigur Rós\"\nTITLE \"Ágætis byrjun\"\nFILE \"image.flac\" WAVE\n{tracks}"),
+        )
+        .unwrap();
+        let tracks = Track::read(&image).unwrap().split_cue();
+        assert_eq!(tracks.len(), 3);
+        let mut library = Library::new(tracks);
+        let album = library.albums.remove(0);
+
+        let diffs = release_diffs(&album, &releases()[0]).unwrap();
+        let [diff] = &diffs[..] else {
+            panic!("{diffs:?}");
+        };
+        assert_eq!(diff.path, image);
+        let new = |key: &str| diff.changes.get(key).map(|c| c.new.clone());
+        assert_eq!(
+            new("MusicBrainzAlbumId"),
+            Some(vec!["release-1999".to_string()])
+        );
+        assert_eq!(new("TrackTitle"), None);
+        assert_eq!(new("TrackNumber"), None);
+        assert_eq!(new("Artist"), None);
+    }
+}
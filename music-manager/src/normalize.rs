@@ -0,0 +1,523 @@
+use std::{path::Path, str::FromStr};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{
+    sheet::TagDiff,
+    tags::{TagValues, read_file_tag_values},
+    template::Field,
+};
+
+/// The tag featured artists are moved to
+pub const FEATURING_KEY: &str = "FEATURING";
+
+/// Tags holding names and titles, the ones case and featured artists are fixed in
+const TEXT_FIELDS: &[Field] = &[
+    Field::Title,
+    Field::Artist,
+    Field::AlbumArtist,
+    Field::Album,
+];
+const ARTIST_FIELDS: &[Field] = &[Field::Artist, Field::AlbumArtist, Field::Composer];
+
+/// Ways of writing "featuring", longest first so `feat.` is not read as `feat`
+const FEAT_MARKERS: &[&str] = &["featuring", "feat.", "feat", "ft.", "ft"];
+/// Separators between several artists in one value
+const ARTIST_SEPARATORS: &[&str] = &[";", " / ", " // "];
+
+/// A change made to the tags of every song
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    /// Remove surrounding whitespace and repeated spaces within a line
+    Trim,
+    /// Compose Unicode characters so `é` is always one character
+    Nfc,
+    /// Capitalize words of titles and names, except small words of the song's language
+    TitleCase,
+    /// Move "feat. X" out of titles and artists into the `FEATURING` tag
+    Feat,
+    /// Split "Artist1; Artist2" into one value per artist
+    SplitArtists,
+    /// Remove track numbers such as "03 - " from the start of titles
+    StripTrackNumber,
+}
+
+impl Transform {
+    pub const ALL: &[Transform] = &[
+        Self::Trim,
+        Self::Nfc,
+        Self::StripTrackNumber,
+        Self::Feat,
+        Self::SplitArtists,
+        Self::TitleCase,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Trim => "trim",
+            Self::Nfc => "nfc",
+            Self::TitleCase => "title-case",
+            Self::Feat => "feat",
+            Self::SplitArtists => "split-artists",
+            Self::StripTrackNumber => "strip-track-number",
+        }
+    }
+
+    pub fn apply(self, tags: &mut TagValues, language: Language) {
+        match self {
+            Self::Trim => {
+                for value in tags.values_mut().flatten() {
+                    *value = match value.contains('\n') {
+                        // lyrics and comments keep their lines
+                        true => value.trim().to_string(),
+                        false => value.split_whitespace().collect::<Vec<_>>().join(" "),
+                    };
+                }
+            }
+            Self::Nfc => {
+                for value in tags.values_mut().flatten() {
+                    *value = value.nfc().collect();
+                }
+            }
+            Self::TitleCase => {
+                let language = song_language(tags).unwrap_or(language);
+                let keys = TEXT_FIELDS.iter().map(|f| f.tag()).chain([FEATURING_KEY]);
+                for key in keys {
+                    for value in tags.get_mut(key).into_iter().flatten() {
+                        *value = title_case(value, language);
+                    }
+                }
+            }
+            Self::Feat => move_featured(tags),
+            Self::SplitArtists => {
+                for field in ARTIST_FIELDS {
+                    if let Some(values) = tags.get_mut(field.tag()) {
+                        *values = split_artists(values);
+                    }
+                }
+            }
+            Self::StripTrackNumber => {
+                // "3/12" style numbering
+                let track = tags
+                    .get(Field::Track.tag())
+                    .and_then(|v| v.first())
+                    .and_then(|t| t.split('/').next())
+                    .map(|t| t.trim().to_string());
+                for title in tags.get_mut(Field::Title.tag()).into_iter().flatten() {
+                    if let Some(stripped) = strip_track_number(title, track.as_deref()) {
+                        *title = stripped;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Transform {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|t| t.name() == s.trim().to_ascii_lowercase())
+            .copied()
+            .ok_or_else(|| format!("Unknown transform '{s}'"))
+    }
+}
+
+impl std::fmt::Display for Transform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The language deciding which words title case leaves in lower case
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+}
+
+impl Language {
+    fn small_words(self) -> &'static [&'static str] {
+        match self {
+            Self::English => &[
+                "a", "an", "and", "as", "at", "but", "by", "for", "from", "in", "into", "nor",
+                "of", "on", "onto", "or", "per", "so", "the", "to", "up", "via", "vs", "vs.",
+                "with", "yet",
+            ],
+            Self::German => &[
+                "am", "an", "auf", "aus", "bei", "das", "dem", "den", "der", "des", "die", "ein",
+                "eine", "einer", "für", "im", "in", "mit", "nach", "oder", "und", "vom", "von",
+                "zu", "zum", "zur",
+            ],
+            Self::French => &[
+                "à", "au", "aux", "avec", "d'", "dans", "de", "des", "du", "en", "et", "l'", "la",
+                "le", "les", "ou", "par", "pour", "sur", "un", "une",
+            ],
+            Self::Spanish => &[
+                "a", "al", "con", "de", "del", "el", "en", "la", "las", "los", "o", "para", "por",
+                "sin", "un", "una", "y",
+            ],
+            Self::Italian => &[
+                "a", "al", "con", "da", "del", "della", "di", "e", "gli", "i", "il", "in", "la",
+                "le", "lo", "o", "per", "su", "un", "una",
+            ],
+        }
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+    /// ISO 639-1 or 639-2 codes, as found in `Language` tags
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "en" | "eng" | "english" => Ok(Self::English),
+            "de" | "deu" | "ger" | "german" => Ok(Self::German),
+            "fr" | "fra" | "fre" | "french" => Ok(Self::French),
+            "es" | "spa" | "spanish" => Ok(Self::Spanish),
+            "it" | "ita" | "italian" => Ok(Self::Italian),
+            _ => Err(format!("Unknown language '{s}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::English => write!(f, "en"),
+            Self::German => write!(f, "de"),
+            Self::French => write!(f, "fr"),
+            Self::Spanish => write!(f, "es"),
+            Self::Italian => write!(f, "it"),
+        }
+    }
+}
+
+fn song_language(tags: &TagValues) -> Option<Language> {
+    tags.get("Language")?.first()?.parse().ok()
+}
+
+/// Capitalize lower case words, leaving small words alone unless they start a phrase.
+/// Words with capitals such as "ABBA" or "iPhone" are kept, unless the whole value is
+/// in capitals.
+fn title_case(value: &str, language: Language) -> String {
+    // short values such as "AC/DC" are usually meant to be capitalized
+    let letters = value.chars().filter(|c| c.is_alphabetic()).count();
+    let shouting = letters > 4 && !value.chars().any(char::is_lowercase);
+    let words: Vec<&str> = value.split(' ').collect();
+    let mut phrase_start = true;
+    let mut out = Vec::with_capacity(words.len());
+    for (i, word) in words.iter().enumerate() {
+        let lower = word.to_lowercase();
+        let bare = lower.trim_matches(|c: char| !c.is_alphanumeric() && c != '\'');
+        let is_small = language.small_words().contains(&bare)
+            || language
+                .small_words()
+                .iter()
+                .any(|s| s.ends_with('\'') && bare.starts_with(s));
+        let is_last = i + 1 == words.len();
+        out.push(if !shouting && word.chars().any(char::is_uppercase) {
+            word.to_string()
+        } else if is_small && !phrase_start && !is_last {
+            lower
+        } else {
+            capitalize(&lower)
+        });
+        phrase_start = word.ends_with([':', '(', '[', '-', '/']) || word.is_empty();
+    }
+    out.join(" ")
+}
+
+/// Upper case the first letter, past any opening punctuation
+fn capitalize(word: &str) -> String {
+    match word.char_indices().find(|(_, c)| c.is_alphanumeric()) {
+        Some((i, c)) => {
+            format!(
+                "{}{}{}",
+                &word[..i],
+                c.to_uppercase(),
+                &word[i + c.len_utf8()..]
+            )
+        }
+        None => word.to_string(),
+    }
+}
+
+/// Move "(feat. X)" out of titles and artists, and "A feat. X" out of artists, into
+/// [`FEATURING_KEY`]
+fn move_featured(tags: &mut TagValues) {
+    let mut featured = tags.get(FEATURING_KEY).cloned().unwrap_or_default();
+    for field in [Field::Title, Field::Artist] {
+        // titles such as "Six ft Under" only feature artists in brackets
+        let bare = field == Field::Artist;
+        for value in tags.get_mut(field.tag()).into_iter().flatten() {
+            if let Some((rest, artists)) = split_featured(value, bare) {
+                *value = rest;
+                for artist in split_artists(&[artists]) {
+                    if !featured.iter().any(|f| f.eq_ignore_ascii_case(&artist)) {
+                        featured.push(artist);
+                    }
+                }
+            }
+        }
+    }
+    if !featured.is_empty() {
+        tags.insert(FEATURING_KEY.to_string(), featured);
+    }
+}
+
+/// Split a value into what precedes a featuring marker and the featured artists, looking for
+/// unbracketed markers too when `bare`
+fn split_featured(value: &str, bare: bool) -> Option<(String, String)> {
+    let lower = value.to_lowercase();
+    // indices into `lower` are only valid in `value` when lowercasing kept every length
+    if lower.len() != value.len() {
+        return None;
+    }
+    // bracketed, such as "Title (feat. X)" or "Title [ft. X]"
+    for (open, close) in [('(', ')'), ('[', ']')] {
+        let mut from = 0;
+        while let Some(start) = lower[from..].find(open).map(|i| i + from) {
+            let inner = &lower[start + 1..];
+            if let Some(marker) = FEAT_MARKERS
+                .iter()
+                .find(|m| inner.starts_with(&format!("{m} ")))
+                && let Some(end) = lower[start..].find(close).map(|i| i + start)
+            {
+                let artists = value[start + 1 + marker.len()..end].trim().to_string();
+                let rest = format!("{} {}", value[..start].trim_end(), value[end + 1..].trim());
+                return Some((rest.trim().to_string(), artists));
+            }
+            from = start + 1;
+        }
+    }
+    if !bare {
+        return None;
+    }
+    // bare, such as "Artist feat. X"
+    FEAT_MARKERS.iter().find_map(|marker| {
+        let start = lower.find(&format!(" {marker} "))?;
+        let artists = value[start + marker.len() + 2..].trim().to_string();
+        Some((value[..start].trim().to_string(), artists))
+    })
+}
+
+/// Turn "A; B" or "A / B" into separate values
+fn split_artists(values: &[String]) -> Vec<String> {
+    let mut values = values.to_vec();
+    for separator in ARTIST_SEPARATORS {
+        values = values
+            .iter()
+            .flat_map(|v| v.split(separator))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect();
+    }
+    values
+}
+
+/// The title without a leading track number such as "03 - ", "3." or "03_". Numbers
+/// only followed by a space are stripped when they are the song's track number, so
+/// titles like "99 Luftballons" are kept.
+fn strip_track_number(title: &str, track: Option<&str>) -> Option<String> {
+    let digits = title.len() - title.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 || digits > 3 {
+        return None;
+    }
+    let (number, rest) = title.split_at(digits);
+    let separated = rest.trim_start();
+    let stripped = match separated.strip_prefix(['-', '.', '_', ')']) {
+        Some(stripped) => stripped,
+        None if rest.starts_with(' ')
+            && track.and_then(|t| t.parse::<u32>().ok()) == number.parse().ok() =>
+        {
+            separated
+        }
+        None => return None,
+    };
+    let stripped = stripped.trim_start_matches([' ', '_']);
+    (!stripped.is_empty()).then(|| stripped.to_string())
+}
+
+/// Diff the tags of every song with the tags `transforms` turn them into, in order
+pub fn normalize_diffs(
+    songs: &[impl AsRef<Path> + Sync],
+    transforms: &[Transform],
+    language: Language,
+) -> crate::Result<Vec<TagDiff>> {
+    let diffs: crate::Result<Vec<Option<TagDiff>>> = songs
+        .par_iter()
+        .map(|song| {
+            let song = song.as_ref();
+            let tags = read_file_tag_values(song, true)?;
+            let mut normalized = tags.clone();
+            for transform in transforms {
+                transform.apply(&mut normalized, language);
+            }
+            Ok(TagDiff::between_values(song, &tags, &normalized))
+        })
+        .collect();
+    Ok(diffs?.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tags::{read_file_tags, write_tag_values},
+        test_util::write_song,
+    };
+
+    fn values(tags: &[(&str, &[&str])]) -> TagValues {
+        tags.iter()
+            .map(|(key, values)| {
+                (
+                    key.to_string(),
+                    values.iter().map(|v| v.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn normalized(transform: Transform, tags: &[(&str, &[&str])]) -> TagValues {
+        let mut tags = values(tags);
+        transform.apply(&mut tags, Language::English);
+        tags
+    }
+
+    #[test]
+    fn trim_keeps_lines() {
+        let tags = normalized(
+            Transform::Trim,
+            &[
+                ("TrackTitle", &["  A   Song "]),
+                ("Lyrics", &[" one\n  two "]),
+            ],
+        );
+        assert_eq!(
+            tags,
+            values(&[("TrackTitle", &["A Song"]), ("Lyrics", &["one\n  two"])])
+        );
+    }
+
+    #[test]
+    fn nfc_composes_accents() {
+        let tags = normalized(Transform::Nfc, &[("Artist", &["Beyonce\u{301}"])]);
+        assert_eq!(tags, values(&[("Artist", &["Beyoncé"])]));
+    }
+
+    #[test]
+    fn title_case_by_language() {
+        let tags = normalized(
+            Transform::TitleCase,
+            &[
+                ("TrackTitle", &["the end of the world"]),
+                ("Artist", &["ABBA"]),
+            ],
+        );
+        assert_eq!(
+            tags,
+            values(&[
+                ("TrackTitle", &["The End of the World"]),
+                ("Artist", &["ABBA"])
+            ])
+        );
+        let tags = normalized(
+            Transform::TitleCase,
+            &[
+                ("TrackTitle", &["lied von der erde"]),
+                ("Language", &["deu"]),
+            ],
+        );
+        assert_eq!(tags["TrackTitle"], ["Lied von der Erde"]);
+        assert_eq!(title_case("HELLO WORLD", Language::English), "Hello World");
+    }
+
+    #[test]
+    fn featured_artists_move_to_their_tag() {
+        let tags = normalized(
+            Transform::Feat,
+            &[
+                ("TrackTitle", &["Song (feat. B; C)"]),
+                ("Artist", &["A ft. b"]),
+            ],
+        );
+        assert_eq!(
+            tags,
+            values(&[
+                ("TrackTitle", &["Song"]),
+                ("Artist", &["A"]),
+                (FEATURING_KEY, &["B", "C"]),
+            ])
+        );
+
+        let tags = normalized(
+            Transform::Feat,
+            &[("TrackTitle", &["Six ft Under"]), ("Artist", &["A"])],
+        );
+        assert_eq!(
+            tags,
+            values(&[("TrackTitle", &["Six ft Under"]), ("Artist", &["A"])])
+        );
+    }
+
+    #[test]
+    fn artists_split_into_values() {
+        let tags = normalized(
+            Transform::SplitArtists,
+            &[("Artist", &["A; B", "C / D"]), ("TrackTitle", &["E; F"])],
+        );
+        assert_eq!(
+            tags,
+            values(&[("Artist", &["A", "B", "C", "D"]), ("TrackTitle", &["E; F"])])
+        );
+    }
+
+    #[test]
+    fn track_numbers_strip_from_titles() {
+        assert_eq!(
+            strip_track_number("03 - Song", None).as_deref(),
+            Some("Song")
+        );
+        assert_eq!(strip_track_number("3. Song", None).as_deref(), Some("Song"));
+        assert_eq!(
+            strip_track_number("03 Song", Some("3")).as_deref(),
+            Some("Song")
+        );
+        assert_eq!(strip_track_number("99 Luftballons", Some("4")), None);
+        assert_eq!(strip_track_number("1999", None), None);
+        let tags = normalized(
+            Transform::StripTrackNumber,
+            &[("TrackTitle", &["07 Song"]), ("TrackNumber", &["7/12"])],
+        );
+        assert_eq!(tags["TrackTitle"], ["Song"]);
+    }
+
+    #[test]
+    fn split_values_are_written_and_read_back_separately() {
+        let dir = tempfile::tempdir().unwrap();
+        let song = dir.path().join("song.flac");
+        write_song(&song, 1, &[("Artist", "A; B")]);
+
+        let diffs =
+            normalize_diffs(&[&song], &[Transform::SplitArtists], Language::English).unwrap();
+        assert_eq!(diffs.len(), 1);
+        write_tag_values(&song, &diffs[0].tag_values()).unwrap();
+
+        assert_eq!(
+            read_file_tag_values(&song, true).unwrap()["Artist"],
+            ["A", "B"]
+        );
+        assert_eq!(read_file_tags(&song, true).unwrap()["Artist"], "A");
+        assert!(
+            normalize_diffs(&[&song], &[Transform::SplitArtists], Language::English)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A library root and the name results from it are tagged with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LibraryRoot {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl LibraryRoot {
+    /// `arg` as one of the `named` roots, or as a root named after its path
+    pub fn resolve(arg: &Path, named: &BTreeMap<String, PathBuf>) -> Self {
+        let by_name = named.get(arg.to_string_lossy().as_ref());
+        let by_path = || named.iter().find(|(_, path)| *path == arg);
+        match (by_name, by_path()) {
+            (Some(path), _) => Self {
+                name: arg.to_string_lossy().into_owned(),
+                path: path.clone(),
+            },
+            (None, Some((name, path))) => Self {
+                name: name.clone(),
+                path: path.clone(),
+            },
+            (None, None) => Self {
+                name: arg.to_string_lossy().into_owned(),
+                path: arg.to_path_buf(),
+            },
+        }
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.path)
+    }
+}
+
+/// The root holding `path`, the innermost if roots are nested
+pub fn root_of<'r>(roots: &'r [LibraryRoot], path: &Path) -> Option<&'r LibraryRoot> {
+    roots
+        .iter()
+        .filter(|r| r.contains(path))
+        .max_by_key(|r| r.path.components().count())
+}
+
+/// A path and the name of the root holding it
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RootedPath<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<&'a str>,
+    pub path: &'a Path,
+}
+
+impl<'a> RootedPath<'a> {
+    pub fn new(roots: &'a [LibraryRoot], path: &'a Path) -> Self {
+        Self {
+            root: root_of(roots, path).map(|r| r.name.as_str()),
+            path,
+        }
+    }
+}
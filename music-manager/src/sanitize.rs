@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+/// Characters that cannot appear in a file name on Windows, FAT or SMB shares
+const WINDOWS_RESERVED_CHARACTERS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+/// Device names Windows refuses as a file name, with or without an extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+const REPLACEMENT: char = '_';
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    /// Only forbid what POSIX filesystems forbid ('/' and NUL)
+    #[default]
+    Posix,
+    /// Safe for Windows, FAT and SMB: no reserved characters, device names or trailing dots
+    Windows,
+    /// Transliterate to ASCII and apply the Windows restrictions
+    Ascii,
+}
+
+impl std::str::FromStr for Profile {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "posix" => Ok(Self::Posix),
+            "windows" | "fat" | "smb" => Ok(Self::Windows),
+            "ascii" => Ok(Self::Ascii),
+            _ => Err(format!("Unknown sanitization profile '{s}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Posix => write!(f, "posix"),
+            Self::Windows => write!(f, "windows"),
+            Self::Ascii => write!(f, "ascii"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sanitizer {
+    pub profile: Profile,
+    /// Maximum length of a single path component in bytes
+    pub max_length: usize,
+    /// Prefix of the name used when nothing survives sanitization
+    pub fallback: String,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Self {
+            profile: Profile::default(),
+            max_length: 255,
+            fallback: "Unknown".to_string(),
+        }
+    }
+}
+
+impl Sanitizer {
+    pub fn new(profile: Profile) -> Self {
+        Self {
+            profile,
+            ..Default::default()
+        }
+    }
+
+    /// Sanitize a directory name
+    pub fn component(&self, s: &str) -> String {
+        let sanitized = self.finish(self.clean(s), self.max_length);
+        if sanitized.is_empty() {
+            self.fallback_name(s, self.max_length)
+        } else {
+            sanitized
+        }
+    }
+
+    /// Sanitize a file name, keeping the extension intact when truncating
+    pub fn filename(&self, stem: &str, extension: &str) -> String {
+        let extension = self.finish(self.clean(extension), self.max_length / 2);
+        if extension.is_empty() {
+            return self.component(stem);
+        }
+        let max_stem = self.max_length.saturating_sub(extension.len() + 1);
+        let mut stem_sanitized = self.finish(self.clean(stem), max_stem);
+        if stem_sanitized.is_empty() {
+            stem_sanitized = self.fallback_name(stem, max_stem);
+        }
+        format!("{stem_sanitized}.{extension}")
+    }
+
+    fn clean(&self, s: &str) -> String {
+        let s: String = match self.profile {
+            Profile::Posix | Profile::Windows => s.chars().map(normalize_quote).collect(),
+            Profile::Ascii => transliterate(s),
+        };
+        s.chars()
+            .filter(|c| !c.is_control())
+            .map(|c| match (self.profile, c) {
+                (_, '/') => REPLACEMENT,
+                (Profile::Windows | Profile::Ascii, c)
+                    if WINDOWS_RESERVED_CHARACTERS.contains(&c) =>
+                {
+                    REPLACEMENT
+                }
+                (_, c) => c,
+            })
+            .collect()
+    }
+
+    /// Trim, truncate and apply the name restrictions of the profile
+    fn finish(&self, mut s: String, max_length: usize) -> String {
+        s = s.trim().to_string();
+        truncate(&mut s, max_length);
+        if self.profile != Profile::Posix {
+            // Windows silently drops trailing dots and spaces
+            s.truncate(s.trim_end_matches(['.', ' ']).len());
+            if is_reserved_name(&s) {
+                s.insert(0, REPLACEMENT);
+                truncate(&mut s, max_length);
+            }
+        }
+        if s.chars().all(|c| c == '.') {
+            s.clear();
+        }
+        s
+    }
+
+    /// A stable, non-empty name derived from the original string
+    fn fallback_name(&self, original: &str, max_length: usize) -> String {
+        let hash = blake3::hash(original.as_bytes()).to_hex();
+        let mut name = format!("{}-{}", self.fallback, &hash[..8]);
+        truncate(&mut name, max_length.max(1));
+        if name.is_empty() {
+            name.push(REPLACEMENT);
+        }
+        name
+    }
+}
+
+fn normalize_quote(c: char) -> char {
+    match c {
+        '’' | '‘' => '\'',
+        c => c,
+    }
+}
+
+/// Decompose into base characters and drop everything that is not ASCII
+fn transliterate(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.nfkd().filter(|c| !is_combining_mark(*c)) {
+        match c {
+            c if c.is_ascii() => out.push(c),
+            '’' | '‘' => out.push('\''),
+            '“' | '”' => out.push('"'),
+            '–' | '—' => out.push('-'),
+            'ß' => out.push_str("ss"),
+            'æ' => out.push_str("ae"),
+            'Æ' => out.push_str("AE"),
+            'œ' => out.push_str("oe"),
+            'Œ' => out.push_str("OE"),
+            'ø' => out.push('o'),
+            'Ø' => out.push('O'),
+            'ł' => out.push('l'),
+            'Ł' => out.push('L'),
+            'đ' | 'ð' => out.push('d'),
+            'Đ' | 'Ð' => out.push('D'),
+            'þ' => out.push_str("th"),
+            'Þ' => out.push_str("TH"),
+            _ => {}
+        }
+    }
+    out
+}
+
+fn is_reserved_name(s: &str) -> bool {
+    let stem = s.split('.').next().unwrap_or_default().trim_end();
+    WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(stem))
+}
+
+/// Truncate to at most `max_length` bytes without splitting a character
+fn truncate(s: &mut String, max_length: usize) {
+    if s.len() <= max_length {
+        return;
+    }
+    let mut end = max_length;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn posix_only_replaces_slashes_and_controls() {
+        let sanitizer = Sanitizer::new(Profile::Posix);
+        assert_eq!(sanitizer.component("AC/DC"), "AC_DC");
+        assert_eq!(sanitizer.component("What?\u{7}"), "What?");
+        assert_eq!(sanitizer.component("Don’t Stop"), "Don't Stop");
+        assert_eq!(sanitizer.component("CON"), "CON");
+    }
+
+    #[test]
+    fn windows_reserved_characters_names_and_trailing_dots() {
+        let sanitizer = Sanitizer::new(Profile::Windows);
+        assert_eq!(sanitizer.component("What? <Live>"), "What_ _Live_");
+        assert_eq!(sanitizer.component("Vol. 2..."), "Vol. 2");
+        assert_eq!(sanitizer.component("con"), "_con");
+        assert_eq!(sanitizer.filename("aux", "flac"), "_aux.flac");
+        assert_eq!(sanitizer.component("Beyoncé"), "Beyoncé");
+    }
+
+    #[test]
+    fn ascii_transliterates() {
+        let sanitizer = Sanitizer::new(Profile::Ascii);
+        assert_eq!(sanitizer.component("Beyoncé – Straße"), "Beyonce - Strasse");
+        assert_eq!(
+            sanitizer.component("Sigur Rós: Ágætis byrjun"),
+            "Sigur Ros_ Agaetis byrjun"
+        );
+    }
+
+    #[test]
+    fn empty_names_fall_back_to_a_stable_name() {
+        let sanitizer = Sanitizer::new(Profile::Ascii);
+        let name = sanitizer.component("東京");
+        assert!(name.starts_with("Unknown-"), "{name}");
+        assert_eq!(name, sanitizer.component("東京"));
+        assert_ne!(name, sanitizer.component("大阪"));
+        assert!(sanitizer.component("..").starts_with("Unknown-"));
+    }
+
+    #[test]
+    fn truncation_keeps_characters_and_extensions_whole() {
+        let sanitizer = Sanitizer {
+            max_length: 10,
+            ..Sanitizer::new(Profile::Posix)
+        };
+        assert_eq!(sanitizer.component("ééééééé"), "ééééé");
+        assert_eq!(
+            sanitizer.filename("A very long title", "flac"),
+            "A ver.flac"
+        );
+    }
+}
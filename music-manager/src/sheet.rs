@@ -0,0 +1,480 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    Error,
+    duplicates::hash_stream,
+    filter::Filter,
+    tags::{TagValues, Tags, read_file_tags, write_tag_values},
+};
+
+/// Columns every sheet starts with, tag columns follow
+const PATH_COLUMN: &str = "path";
+const HASH_COLUMN: &str = "hash";
+
+/// A spreadsheet-friendly format tags are exported to and imported from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SheetFormat {
+    #[default]
+    Csv,
+    Tsv,
+    Json,
+}
+
+impl SheetFormat {
+    /// The format a file's extension suggests
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    fn delimiter(self) -> u8 {
+        match self {
+            Self::Tsv => b'\t',
+            _ => b',',
+        }
+    }
+}
+
+impl FromStr for SheetFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "tsv" => Ok(Self::Tsv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown sheet format '{s}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for SheetFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Csv => write!(f, "csv"),
+            Self::Tsv => write!(f, "tsv"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// The tags of one song, identified by its path and the hash of its audio stream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Row {
+    pub path: PathBuf,
+    pub hash: String,
+    pub tags: Tags,
+}
+
+/// Read the tags and stream hash of every song matching `filter`
+pub fn export_rows(songs: &[impl AsRef<Path> + Sync], filter: &Filter) -> crate::Result<Vec<Row>> {
+    let rows: crate::Result<Vec<Option<Row>>> = songs
+        .par_iter()
+        .map(|song| {
+            let path = song.as_ref();
+            let tags = read_file_tags(path, false)?;
+            if !filter.matches(path, &tags) {
+                return Ok(None);
+            }
+            Ok(Some(Row {
+                path: path.to_path_buf(),
+                hash: stream_hash(path)?,
+                tags,
+            }))
+        })
+        .collect();
+    Ok(rows?.into_iter().flatten().collect())
+}
+
+fn stream_hash(path: &Path) -> crate::Result<String> {
+    Ok(hash_stream(path)?
+        .map(|h| h.to_hex().to_string())
+        .unwrap_or_default())
+}
+
+/// Write `rows` as a sheet with a column per tag any row has
+pub fn write_sheet(rows: &[Row], format: SheetFormat, writer: impl Write) -> crate::Result<()> {
+    if format == SheetFormat::Json {
+        serde_json::to_writer_pretty(writer, rows)?;
+        return Ok(());
+    }
+    let keys: BTreeSet<&str> = rows
+        .iter()
+        .flat_map(|r| r.tags.keys().map(String::as_str))
+        .collect();
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(format.delimiter())
+        .from_writer(writer);
+    writer.write_record([PATH_COLUMN, HASH_COLUMN].iter().chain(&keys))?;
+    for row in rows {
+        let mut record = vec![row.path.to_string_lossy().into_owned(), row.hash.clone()];
+        record.extend(
+            keys.iter()
+                .map(|k| row.tags.get(*k).cloned().unwrap_or_default()),
+        );
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read a sheet written by [`write_sheet`]
+pub fn read_sheet(format: SheetFormat, reader: impl Read) -> crate::Result<Vec<Row>> {
+    if format == SheetFormat::Json {
+        return Ok(serde_json::from_reader(reader)?);
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(format.delimiter())
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| Error::InvalidSheet(format!("Missing '{name}' column")))
+    };
+    let (path, hash) = (column(PATH_COLUMN)?, column(HASH_COLUMN)?);
+    reader
+        .records()
+        .map(|record| {
+            let record = record?;
+            let tags = headers
+                .iter()
+                .zip(&record)
+                .enumerate()
+                .filter(|(i, _)| *i != path && *i != hash)
+                .map(|(_, (key, value))| (key.to_string(), value.to_string()))
+                .collect();
+            Ok(Row {
+                path: PathBuf::from(&record[path]),
+                hash: record[hash].to_string(),
+                tags,
+            })
+        })
+        .collect()
+}
+
+/// A tag's values before and after an import, empty when it is missing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Change {
+    pub old: Vec<String>,
+    pub new: Vec<String>,
+}
+
+/// The tag changes an import makes to one song
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TagDiff {
+    pub path: PathBuf,
+    pub changes: BTreeMap<String, Change>,
+}
+
+impl TagDiff {
+    /// The changes turning `old` into `new`, `None` if there are none
+    pub fn between(path: &Path, old: &Tags, new: &Tags) -> Option<Self> {
+        let values = |tags: &Tags| -> TagValues {
+            tags.iter()
+                .map(|(key, value)| (key.clone(), vec![value.clone()]))
+                .collect()
+        };
+        Self::between_values(path, &values(old), &values(new))
+    }
+
+    /// The changes turning every value of `old` into those of `new`
+    pub fn between_values(path: &Path, old: &TagValues, new: &TagValues) -> Option<Self> {
+        let changes: BTreeMap<String, Change> = old
+            .keys()
+            .chain(new.keys())
+            .filter(|key| old.get(*key) != new.get(*key))
+            .map(|key| {
+                let change = Change {
+                    old: old.get(key).cloned().unwrap_or_default(),
+                    new: new.get(key).cloned().unwrap_or_default(),
+                };
+                (key.clone(), change)
+            })
+            .collect();
+        (!changes.is_empty()).then(|| Self {
+            path: path.to_path_buf(),
+            changes,
+        })
+    }
+
+    /// The new values of each changed tag, none for removed tags
+    pub fn tag_values(&self) -> TagValues {
+        self.changes
+            .iter()
+            .map(|(key, change)| (key.clone(), change.new.clone()))
+            .collect()
+    }
+}
+
+impl std::fmt::Display for TagDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path.to_string_lossy())?;
+        for (key, Change { old, new }) in &self.changes {
+            for old in old {
+                write!(f, "\n-{key}={old:?}")?;
+            }
+            for new in new {
+                write!(f, "\n+{key}={new:?}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Match each row to a song in `songs` and diff their tags.
+/// Rows are matched by path, or by stream hash when the song at the path is gone or
+/// different. An empty value removes the tag, tags a row leaves out are kept.
+pub fn import_diffs(
+    rows: &[Row],
+    songs: &[impl AsRef<Path> + Sync],
+) -> crate::Result<Vec<TagDiff>> {
+    let matched: Vec<(&Row, Option<PathBuf>)> = rows
+        .par_iter()
+        .map(|row| {
+            let same = row.path.exists() && stream_hash(&row.path)? == row.hash;
+            Ok((row, same.then(|| row.path.clone())))
+        })
+        .collect::<crate::Result<_>>()?;
+
+    // only hash the library when some song has moved
+    let mut by_hash: HashMap<String, Vec<&Path>> = HashMap::new();
+    if matched.iter().any(|(_, path)| path.is_none()) {
+        let hashes: Vec<(String, &Path)> = songs
+            .par_iter()
+            .map(|s| Ok((stream_hash(s.as_ref())?, s.as_ref())))
+            .collect::<crate::Result<_>>()?;
+        for (hash, song) in hashes {
+            by_hash.entry(hash).or_default().push(song);
+        }
+    }
+
+    // a song moved to where another was listed is only matched by its own row
+    let mut used: BTreeSet<PathBuf> = matched.iter().filter_map(|(_, p)| p.clone()).collect();
+    let mut diffs = Vec::new();
+    for (row, path) in matched {
+        let path = path.or_else(|| {
+            let mut candidates = by_hash
+                .get(&row.hash)?
+                .iter()
+                .filter(|s| !used.contains(**s));
+            let first = candidates.clone().next()?;
+            let song = candidates
+                .find(|s| s.file_name() == row.path.file_name())
+                .unwrap_or(first)
+                .to_path_buf();
+            used.insert(song.clone());
+            Some(song)
+        });
+        let Some(path) = path else {
+            warn!("No song matches '{}'", row.path.to_string_lossy());
+            continue;
+        };
+        let tags = read_file_tags(&path, false)?;
+        let mut edited = tags.clone();
+        for (key, value) in &row.tags {
+            match value.is_empty() {
+                true => edited.remove(key),
+                false => edited.insert(key.clone(), value.clone()),
+            };
+        }
+        diffs.extend(TagDiff::between(&path, &tags, &edited));
+    }
+    Ok(diffs)
+}
+
+/// Apply every diff or none of them. Tags are written to copies of the songs, which
+/// replace the originals only once all writes succeeded. The originals are kept aside
+/// until every copy is in place, so a failed rename puts them all back.
+pub fn apply_diffs(diffs: &[TagDiff]) -> crate::Result<()> {
+    // diffs of one song would share its staged copy and backup
+    let mut paths = BTreeSet::new();
+    if let Some(diff) = diffs.iter().find(|d| !paths.insert(&d.path)) {
+        return Err(Error::ConflictingDiffs(diff.path.clone()));
+    }
+    let mut staged: Vec<(PathBuf, &Path)> = Vec::new();
+    let written = diffs.iter().try_for_each(|diff| {
+        let copy = staging_path(&diff.path, "import");
+        std::fs::copy(&diff.path, &copy)?;
+        staged.push((copy.clone(), &diff.path));
+        write_tag_values(&copy, &diff.tag_values())
+    });
+    if let Err(e) = written {
+        for (copy, _) in &staged {
+            let _ = std::fs::remove_file(copy);
+        }
+        return Err(e);
+    }
+    let mut replaced: Vec<(PathBuf, &Path)> = Vec::new();
+    let renamed = staged.iter().try_for_each(|(copy, path)| {
+        let backup = staging_path(path, "backup");
+        std::fs::rename(path, &backup)?;
+        if let Err(e) = std::fs::rename(copy, path) {
+            let _ = std::fs::rename(&backup, path);
+            return Err(e);
+        }
+        replaced.push((backup, path));
+        Ok(())
+    });
+    if let Err(e) = renamed {
+        for (backup, path) in replaced {
+            let _ = std::fs::rename(backup, path);
+        }
+        for (copy, _) in &staged {
+            let _ = std::fs::remove_file(copy);
+        }
+        return Err(e.into());
+    }
+    for (backup, _) in replaced {
+        std::fs::remove_file(backup)?;
+    }
+    Ok(())
+}
+
+/// A hidden sibling of `path` keeping its extension
+fn staging_path(path: &Path, purpose: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".songman-{purpose}-{name}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_song;
+
+    fn row(path: &str, tags: &[(&str, &str)]) -> Row {
+        Row {
+            path: PathBuf::from(path),
+            hash: "abc".to_string(),
+            tags: tags
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn sheets_read_back_what_was_written() {
+        let rows = [
+            row("a, \"b\".flac", &[("Artist", "A"), ("Comment", "one\ntwo")]),
+            row("c.flac", &[("TrackTitle", "C\tD")]),
+        ];
+        for format in [SheetFormat::Csv, SheetFormat::Tsv, SheetFormat::Json] {
+            let mut sheet = Vec::new();
+            write_sheet(&rows, format, &mut sheet).unwrap();
+            let read = read_sheet(format, sheet.as_slice()).unwrap();
+            if format == SheetFormat::Json {
+                assert_eq!(read, rows);
+                continue;
+            }
+            // a column the row has no tag for reads back empty
+            assert_eq!(read[0].tags["Artist"], "A");
+            assert_eq!(read[0].tags["Comment"], "one\ntwo");
+            assert_eq!(read[0].tags["TrackTitle"], "");
+            assert_eq!(read[1].path, rows[1].path);
+            assert_eq!(read[1].tags["TrackTitle"], "C\tD");
+        }
+    }
+
+    #[test]
+    fn sheets_need_path_and_hash_columns() {
+        let read = read_sheet(SheetFormat::Csv, "path,Artist\na.flac,A\n".as_bytes());
+        assert!(matches!(read, Err(Error::InvalidSheet(_))));
+        assert_eq!(
+            SheetFormat::from_path(Path::new("tags.TSV")),
+            Some(SheetFormat::Tsv)
+        );
+        assert_eq!(SheetFormat::from_path(Path::new("tags.xlsx")), None);
+    }
+
+    #[test]
+    fn moved_songs_are_matched_by_their_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let (song, moved) = (dir.path().join("a.flac"), dir.path().join("moved/a.flac"));
+        write_song(&song, 1, &[("Artist", "A"), ("TrackTitle", "T")]);
+        let mut rows = export_rows(&[&song], &Filter::All).unwrap();
+        std::fs::create_dir(moved.parent().unwrap()).unwrap();
+        std::fs::rename(&song, &moved).unwrap();
+
+        rows[0].tags.insert("Artist".to_string(), "B".to_string());
+        rows[0].tags.insert("TrackTitle".to_string(), String::new());
+        let diffs = import_diffs(&rows, &[&moved]).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].path, moved);
+        assert_eq!(
+            diffs[0].tag_values(),
+            TagValues::from([
+                ("Artist".to_string(), vec!["B".to_string()]),
+                ("TrackTitle".to_string(), vec![]),
+            ])
+        );
+
+        apply_diffs(&diffs).unwrap();
+        let tags = read_file_tags(&moved, false).unwrap();
+        assert_eq!(tags.get("Artist").map(String::as_str), Some("B"));
+        assert_eq!(tags.get("TrackTitle"), None);
+        assert!(import_diffs(&rows, &[&moved]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn failed_renames_put_every_song_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.flac"), dir.path().join("b.flac"));
+        write_song(&a, 1, &[("Artist", "A")]);
+        write_song(&b, 2, &[("Artist", "A")]);
+        // b cannot be set aside onto a directory that is not empty
+        std::fs::create_dir_all(staging_path(&b, "backup").join("blocker")).unwrap();
+
+        let mut rows = export_rows(&[&a, &b], &Filter::All).unwrap();
+        for row in &mut rows {
+            row.tags.insert("Artist".to_string(), "B".to_string());
+        }
+        let diffs = import_diffs(&rows, &[&a, &b]).unwrap();
+        assert_eq!(diffs.len(), 2);
+        assert!(apply_diffs(&diffs).is_err());
+
+        for song in [&a, &b] {
+            let tags = read_file_tags(song, false).unwrap();
+            assert_eq!(tags.get("Artist").map(String::as_str), Some("A"));
+        }
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, [".songman-backup-b.flac", "a.flac", "b.flac"]);
+    }
+
+    #[test]
+    fn diffs_sharing_a_song_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let song = dir.path().join("a.flac");
+        write_song(&song, 1, &[("Artist", "A")]);
+        let tags = read_file_tags(&song, false).unwrap();
+        let diffs: Vec<TagDiff> = ["B", "C"]
+            .into_iter()
+            .flat_map(|artist| {
+                let mut new = tags.clone();
+                new.insert("Artist".to_string(), artist.to_string());
+                TagDiff::between(&song, &tags, &new)
+            })
+            .collect();
+        assert_eq!(diffs.len(), 2);
+
+        assert!(matches!(apply_diffs(&diffs), Err(Error::ConflictingDiffs(p)) if p == song));
+        let tags = read_file_tags(&song, false).unwrap();
+        assert_eq!(tags.get("Artist").map(String::as_str), Some("A"));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,38 +1,112 @@
 use std::{
     cmp::Ordering,
-    fs::File,
+    collections::HashMap,
     path::{Path, PathBuf},
 };
 
-use serde::Serialize;
-use symphonia::{core::io::MediaSourceStream, default::get_probe};
-use tracing::info;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
 use crate::{
     Error,
-    metadata::{get_artist, get_title},
+    album::{Track, group_albums},
+    cue::{find_cue, rename_file},
+    lyrics::sidecar_path,
+    sanitize::Sanitizer,
+    tags::{TagChanges, write_tags},
+    template::Template,
 };
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Move songs into the destination
+    #[default]
+    Move,
+    /// Copy songs, leaving the source untouched
+    Copy,
+    /// Hardlink songs, sharing storage with the source
+    Hardlink,
+    /// Symlink songs to their absolute source path
+    Symlink,
+    /// Copy-on-write clone of the songs, requires filesystem support
+    Reflink,
+}
+
+impl std::str::FromStr for Mode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "move" => Ok(Self::Move),
+            "copy" => Ok(Self::Copy),
+            "hardlink" => Ok(Self::Hardlink),
+            "symlink" => Ok(Self::Symlink),
+            "reflink" => Ok(Self::Reflink),
+            _ => Err(format!("Unknown sort mode '{s}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Move => write!(f, "move"),
+            Self::Copy => write!(f, "copy"),
+            Self::Hardlink => write!(f, "hardlink"),
+            Self::Symlink => write!(f, "symlink"),
+            Self::Reflink => write!(f, "reflink"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Transaction {
     Mkdir(PathBuf),
+    Remove(PathBuf),
+    Rmdir(PathBuf),
     Move { src: PathBuf, dest: PathBuf },
+    Copy { src: PathBuf, dest: PathBuf },
+    Hardlink { src: PathBuf, dest: PathBuf },
+    Symlink { src: PathBuf, dest: PathBuf },
+    Reflink { src: PathBuf, dest: PathBuf },
+    WriteTags { path: PathBuf, tags: TagChanges },
+    WriteFile { path: PathBuf, contents: String },
 }
 
 impl std::fmt::Display for Transaction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
+        let (verb, src, dest) = match self {
             Self::Mkdir(path) => {
-                write!(f, "Create directory '{}'", path.to_string_lossy())
+                return write!(f, "Create directory '{}'", path.to_string_lossy());
             }
-            Self::Move { src, dest } => {
-                write!(
-                    f,
-                    "Rename '{}' to '{}'",
-                    src.to_string_lossy(),
-                    dest.to_string_lossy()
-                )
+            Self::Remove(path) => return write!(f, "Remove '{}'", path.to_string_lossy()),
+            Self::Rmdir(path) => {
+                return write!(f, "Remove directory '{}'", path.to_string_lossy());
             }
-        }
+            Self::WriteTags { path, tags } => {
+                write!(f, "Tag '{}':", path.to_string_lossy())?;
+                for (key, value) in tags {
+                    match value {
+                        Some(value) => write!(f, " {key}={value:?}")?,
+                        None => write!(f, " -{key}")?,
+                    }
+                }
+                return Ok(());
+            }
+            Self::WriteFile { path, .. } => return write!(f, "Write '{}'", path.to_string_lossy()),
+            Self::Move { src, dest } => ("Rename", src, dest),
+            Self::Copy { src, dest } => ("Copy", src, dest),
+            Self::Hardlink { src, dest } => ("Hardlink", src, dest),
+            Self::Symlink { src, dest } => ("Symlink", src, dest),
+            Self::Reflink { src, dest } => ("Reflink", src, dest),
+        };
+        write!(
+            f,
+            "{verb} '{}' to '{}'",
+            src.to_string_lossy(),
+            dest.to_string_lossy()
+        )
     }
 }
 impl PartialOrd for Transaction {
@@ -44,96 +118,385 @@ impl PartialOrd for Transaction {
 impl Ord for Transaction {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         match (self, other) {
-            (Self::Move { .. }, Self::Move { .. }) => Ordering::Equal,
-            (Self::Mkdir(_), Self::Mkdir(_)) => Ordering::Equal,
-            (Self::Move { .. }, Self::Mkdir(_)) => Ordering::Greater,
-            (Self::Mkdir(_), Self::Move { .. }) => Ordering::Less,
+            // parents are created before and removed after their children
+            (Self::Mkdir(a), Self::Mkdir(b)) => a.cmp(b),
+            (Self::Rmdir(a), Self::Rmdir(b)) => b.cmp(a),
+            _ => self.rank().cmp(&other.rank()),
         }
     }
 }
 
 impl Transaction {
-    pub fn apply(&self) -> Result<(), std::io::Error> {
+    /// Removals free space and directories before anything is created
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Remove(_) => 0,
+            Self::Rmdir(_) => 1,
+            Self::Mkdir(_) => 2,
+            _ => 3,
+        }
+    }
+
+    pub(crate) fn new(mode: Mode, src: PathBuf, dest: PathBuf) -> Self {
+        match mode {
+            Mode::Move => Self::Move { src, dest },
+            Mode::Copy => Self::Copy { src, dest },
+            Mode::Hardlink => Self::Hardlink { src, dest },
+            Mode::Symlink => Self::Symlink { src, dest },
+            Mode::Reflink => Self::Reflink { src, dest },
+        }
+    }
+
+    pub fn apply(&self) -> crate::Result<()> {
         info!("{self}");
         match self {
             Self::Mkdir(path) => std::fs::create_dir(path)?,
-            Self::Move { src, dest } => std::fs::rename(src, dest)?,
+            Self::Remove(path) => std::fs::remove_file(path)?,
+            Self::Rmdir(path) => std::fs::remove_dir(path)?,
+            Self::Move { src, dest } => move_file(src, dest)?,
+            Self::Copy { src, dest } => {
+                std::fs::copy(src, dest)?;
+            }
+            Self::Hardlink { src, dest } => std::fs::hard_link(src, dest)?,
+            Self::Symlink { src, dest } => symlink(src, dest)?,
+            Self::Reflink { src, dest } => reflink_copy::reflink(src, dest)?,
+            Self::WriteTags { path, tags } => write_tags(path, tags)?,
+            Self::WriteFile { path, contents } => std::fs::write(path, contents)?,
         }
         Ok(())
     }
 }
 
+/// Rename `src` to `dest`, copying and removing it when they are on different filesystems
+fn move_file(src: &Path, dest: &Path) -> std::io::Result<()> {
+    match std::fs::rename(src, dest) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => copy_and_remove(src, dest),
+        result => result,
+    }
+}
+
+fn copy_and_remove(src: &Path, dest: &Path) -> std::io::Result<()> {
+    if let Err(e) = std::fs::copy(src, dest) {
+        // a partial copy is not left behind
+        let _ = std::fs::remove_file(dest);
+        return Err(e);
+    }
+    std::fs::remove_file(src)
+}
+
+#[cfg(unix)]
+fn symlink(src: &Path, dest: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(src, dest)
+}
+
+#[cfg(windows)]
+fn symlink(src: &Path, dest: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(src, dest)
+}
+
+/// How songs are sorted
+#[derive(Debug, Clone)]
+pub struct SortOptions<'a> {
+    pub mode: Mode,
+    pub template: &'a Template,
+    pub sanitizer: &'a Sanitizer,
+    /// Skip songs whose destination is taken instead of failing
+    pub skip_existing: bool,
+}
+
+/// Plan sorting `songs` found under `source` into `dest`.
+/// With any mode but [`Mode::Move`], songs already inside `dest` are left alone so that
+/// a destination nested in the source is not mirrored into itself.
+/// `planned` holds the destinations of earlier plans, such as those of other sources sorted
+/// into the same `dest`, by the song placed there. A song planned to one of them is taken
+/// like one whose destination exists.
 pub fn sort_songs_transactions(
-    prefix: &Path,
-    songs: &[impl AsRef<Path>],
+    source: &Path,
+    dest: &Path,
+    songs: &[impl AsRef<Path> + Sync],
+    options: &SortOptions,
+    planned: &mut HashMap<PathBuf, PathBuf>,
 ) -> crate::Result<Vec<Transaction>> {
-    let mut transactions: Vec<Transaction> = songs
+    let mode = options.mode;
+    let nested = mode != Mode::Move && source != dest && dest.starts_with(source);
+    let songs: Vec<&Path> = songs
         .iter()
-        .map(|s| sort_song(prefix, s.as_ref()))
-        .try_fold(Vec::new(), |mut v, r| -> crate::Result<_> {
-            v.append(&mut r?);
-            Ok(v)
-        })?;
+        .map(|s| s.as_ref())
+        .filter(|s| !(nested && s.starts_with(dest)))
+        .collect();
+    let mut transactions = Vec::new();
+    for (song, target) in destinations(dest, &songs, options.template, options.sanitizer)? {
+        let target = target.ok_or(Error::MissingMetadata)?;
+        let placed = match planned.get(&target) {
+            Some(other) => Err(Error::SameDestination {
+                src: song.to_path_buf(),
+                other: other.clone(),
+                dest: target.clone(),
+            }),
+            None => place_song(song, target.clone(), mode),
+        };
+        match placed {
+            Ok(mut placed) => {
+                transactions.append(&mut placed);
+                planned.insert(target, song.to_path_buf());
+            }
+            Err(e @ (Error::AlreadyExists { .. } | Error::SameDestination { .. }))
+                if options.skip_existing =>
+            {
+                warn!("Skipping: {e}");
+            }
+            Err(e) => return Err(e),
+        }
+    }
     // ensure that Mkdirs come before Moves
-    transactions.sort_unstable();
+    transactions.sort();
+    // songs sharing a new directory each request it
+    transactions.dedup();
     Ok(transactions)
 }
 
-fn sort_song(prefix: &Path, song: &Path) -> crate::Result<Vec<Transaction>> {
-    let mut probed = get_probe().format(
-        &Default::default(),
-        MediaSourceStream::new(Box::new(File::open(song)?), Default::default()),
-        &Default::default(),
-        &Default::default(),
-    )?;
-    let artist = get_artist(&mut probed).ok_or(crate::Error::MissingMetadata)?;
-    let title = get_title(&mut probed).ok_or(crate::Error::MissingMetadata)?;
-
-    let dest = target_location(
-        prefix,
-        &artist,
-        &title,
-        &song
-            .extension()
-            .expect("All songs have an extension")
-            .to_string_lossy(),
-    );
-
-    if song == dest.as_path() {
+/// Where the template places each of `songs` under `dest`, `None` for songs lacking the
+/// fields it needs
+pub fn destinations<'a>(
+    dest: &Path,
+    songs: &[&'a Path],
+    template: &Template,
+    sanitizer: &Sanitizer,
+) -> crate::Result<Vec<(&'a Path, Option<PathBuf>)>> {
+    // CUE images are sorted as a whole
+    let tracks = songs
+        .par_iter()
+        .map(|s| Track::read(s))
+        .collect::<crate::Result<_>>()?;
+    Ok(track_destinations(
+        tracks,
+        |_| Some(dest),
+        template,
+        sanitizer,
+    ))
+}
+
+/// Where the template places each of `tracks`, read as whole files, below the directory
+/// `dest` gives for it. `None` for tracks lacking the fields it needs or without a `dest`.
+pub fn track_destinations<'a, 'd>(
+    tracks: Vec<Track<'a>>,
+    dest: impl Fn(&Path) -> Option<&'d Path>,
+    template: &Template,
+    sanitizer: &Sanitizer,
+) -> Vec<(&'a Path, Option<PathBuf>)> {
+    let (albums, loose) = group_albums(tracks);
+    // tracks of an album are placed by the tags most of the album agrees on
+    let placed = albums
+        .iter()
+        .flat_map(|album| {
+            let shared = album.shared_tags();
+            album.tracks.iter().map(move |track| {
+                let mut tags = track.tags.clone();
+                tags.extend(shared.clone());
+                (track.path, tags)
+            })
+        })
+        .chain(loose.iter().map(|track| (track.path, track.tags.clone())));
+    placed
+        .map(|(song, tags)| {
+            let extension = song
+                .extension()
+                .expect("All songs have an extension")
+                .to_string_lossy();
+            let target =
+                dest(song).and_then(|dest| template.render(dest, &tags, &extension, sanitizer));
+            (song, target)
+        })
+        .collect()
+}
+
+/// Place `song` at `dest` as `mode` says, along with its lyrics file and the CUE sheet of
+/// an image, renamed to name the placed image
+pub(crate) fn place_song(
+    song: &Path,
+    dest: PathBuf,
+    mode: Mode,
+) -> crate::Result<Vec<Transaction>> {
+    if song == dest.as_path() || is_placed(song, &dest, mode)? {
         return Ok(Vec::new());
-    } else if dest.exists() {
+    } else if dest.symlink_metadata().is_ok() {
         return Err(Error::AlreadyExists {
             src: song.to_path_buf(),
             dest,
         });
     }
 
-    let mut transactions = Vec::new();
-    if !prefix.join(&artist).exists() {
-        transactions.push(Transaction::Mkdir(prefix.join(&artist)));
+    let mut transactions = mkdir_transactions(&dest);
+    // lyrics files follow their song
+    let sidecars = [(sidecar_path(song), sidecar_path(&dest))]
+        .into_iter()
+        .filter(|(src, dest)| src.is_file() && dest.symlink_metadata().is_err());
+    for (src, dest) in std::iter::once((song.to_path_buf(), dest.clone())).chain(sidecars) {
+        let src = match mode {
+            Mode::Symlink => std::path::absolute(src)?,
+            _ => src,
+        };
+        transactions.push(Transaction::new(mode, src, dest));
+    }
+    if let Some((cue, sheet)) = find_cue(song)
+        && let Some(file) = sheet.file_for(song)
+    {
+        let sheet_dest = dest.with_extension("cue");
+        if sheet_dest.symlink_metadata().is_err() {
+            let name = dest
+                .file_name()
+                .expect("Songs have a name")
+                .to_string_lossy();
+            let contents = rename_file(&cue, &file.name, &name)?;
+            // other modes leave the sheet beside the source, which a hardlink would share
+            if mode == Mode::Move {
+                transactions.push(Transaction::Move {
+                    src: cue,
+                    dest: sheet_dest.clone(),
+                });
+            }
+            transactions.push(Transaction::WriteFile {
+                path: sheet_dest,
+                contents,
+            });
+        }
     }
-    transactions.push(Transaction::Move {
-        src: song.to_path_buf(),
-        dest,
-    });
     Ok(transactions)
 }
 
-pub fn target_location(prefix: &Path, artist: &str, title: &str, extention: &str) -> PathBuf {
-    prefix
-        .join(sanitize(artist))
-        .join(sanitize(&format!("{title}.{extention}")))
+/// Create every missing ancestor of `dest`, outermost first
+pub(crate) fn mkdir_transactions(dest: &Path) -> Vec<Transaction> {
+    let mut missing: Vec<Transaction> = dest
+        .ancestors()
+        .skip(1)
+        .take_while(|p| !p.as_os_str().is_empty() && !p.exists())
+        .map(|p| Transaction::Mkdir(p.to_path_buf()))
+        .collect();
+    missing.reverse();
+    missing
 }
 
-fn sanitize(s: &str) -> String {
-    const VALID_CHARACTERS: &[char] = &[
-        '.', ',', '!', '(', ')', ':', '?', ' ', '\'', '"', '-', '_', '=', '&',
-    ];
-    s.chars()
-        .filter_map(|c| match c {
-            c if c.is_ascii_alphanumeric() || VALID_CHARACTERS.contains(&c) => Some(c),
-            '’' => Some('\''),
-            _ => None,
-        })
-        .collect()
+/// Whether a previous run already placed `src` at `dest`
+fn is_placed(src: &Path, dest: &Path, mode: Mode) -> std::io::Result<bool> {
+    let Ok(dest_metadata) = dest.symlink_metadata() else {
+        return Ok(false);
+    };
+    let src_metadata = src.metadata()?;
+    Ok(match mode {
+        Mode::Move => false,
+        Mode::Symlink => dest.read_link().ok() == Some(std::path::absolute(src)?),
+        Mode::Hardlink => is_same_file(&src_metadata, &dest_metadata),
+        Mode::Copy | Mode::Reflink => {
+            dest_metadata.is_file()
+                && dest_metadata.len() == src_metadata.len()
+                && dest_metadata.modified()? >= src_metadata.modified()?
+        }
+    })
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &std::fs::Metadata, b: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_: &std::fs::Metadata, _: &std::fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_song;
+
+    #[test]
+    fn moves_across_filesystems_copy_then_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dest) = (dir.path().join("song.flac"), dir.path().join("moved.flac"));
+        std::fs::write(&src, "song").unwrap();
+        copy_and_remove(&src, &dest).unwrap();
+        assert!(!src.exists());
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "song");
+
+        // a failed copy keeps the source
+        let missing = dir.path().join("missing/song.flac");
+        assert!(copy_and_remove(&dest, &missing).is_err());
+        assert!(dest.exists());
+    }
+
+    #[test]
+    fn cue_sheet_follows_its_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("rip/image.flac");
+        write_song(&image, 5, &[("Artist", "Band"), ("TrackTitle", "Live")]);
+        let sheet = "FILE \"image.wav\" WAVE\r\n  TRACK 01 AUDIO\r\n    INDEX 01 00:00:00\r\n  \
+                     TRACK 02 AUDIO\r\n    INDEX 01 00:00:03\r\n";
+        std::fs::write(dir.path().join("rip/image.cue"), sheet).unwrap();
+
+        let options = SortOptions {
+            mode: Mode::Move,
+            template: &Template::default(),
+            sanitizer: &Sanitizer::default(),
+            skip_existing: false,
+        };
+        let transactions = sort_songs_transactions(
+            dir.path(),
+            dir.path(),
+            &[&image],
+            &options,
+            &mut HashMap::new(),
+        )
+        .unwrap();
+        for transaction in &transactions {
+            transaction.apply().unwrap();
+        }
+
+        let placed = dir.path().join("Band/Live.flac");
+        let (cue, sheet) = find_cue(&placed).expect("The sheet is beside the image");
+        assert_eq!(cue, dir.path().join("Band/Live.cue"));
+        assert_eq!(sheet.files[0].name, "Live.flac");
+        assert_eq!(sheet.files[0].tracks.len(), 2);
+        assert!(!dir.path().join("rip/image.cue").exists());
+    }
+
+    #[test]
+    fn roots_sorted_together_never_share_a_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let tags = [("Artist", "Band"), ("TrackTitle", "Song")];
+        let [first, second] = ["a", "b"].map(|root| dir.path().join(root).join("song.flac"));
+        write_song(&first, 1, &tags);
+        write_song(&second, 2, &tags);
+        let dest = dir.path().join("sorted");
+        let mut options = SortOptions {
+            mode: Mode::Move,
+            template: &Template::default(),
+            sanitizer: &Sanitizer::default(),
+            skip_existing: false,
+        };
+        let plan = |options: &SortOptions| -> crate::Result<Vec<Transaction>> {
+            let mut planned = HashMap::new();
+            let mut transactions = Vec::new();
+            for song in [&first, &second] {
+                let source = song.parent().unwrap();
+                transactions.append(&mut sort_songs_transactions(
+                    source,
+                    &dest,
+                    &[song],
+                    options,
+                    &mut planned,
+                )?);
+            }
+            Ok(transactions)
+        };
+
+        assert!(matches!(plan(&options), Err(Error::SameDestination { .. })));
+        options.skip_existing = true;
+        let moves = plan(&options).unwrap();
+        let moves: Vec<_> = moves
+            .iter()
+            .filter(|t| matches!(t, Transaction::Move { .. }))
+            .collect();
+        assert_eq!(moves.len(), 1);
+    }
 }
@@ -1,74 +1,461 @@
 use std::{
+    collections::{BTreeMap, HashMap},
     fs::File,
+    io::BufReader,
     path::{Path, PathBuf},
 };
 
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use lofty::{file::FileType, probe::Probe};
+use rayon::iter::{Either, IntoParallelRefIterator, ParallelIterator};
 use serde::Serialize;
-use symphonia::{core::io::MediaSourceStream, default::get_probe};
+use symphonia::{
+    core::{codecs::CodecParameters, io::MediaSourceStream},
+    default::get_probe,
+};
 
 use crate::{
-    metadata::{get_artist, get_title},
-    sort::target_location,
+    album::{Album, Track, codec_name, duration, group_albums},
+    analysis::{Problem, SongAnalysis, analyze_songs},
+    lint::RequiredTags,
+    loudness::inconsistent_gain_albums,
+    lyrics::has_lyrics,
+    roots::{LibraryRoot, root_of},
+    sanitize::Sanitizer,
+    sort::track_destinations,
+    tags::{Tags, read_tags},
+    template::{Field, Template},
 };
 
+/// Entries in each top artists and albums ranking
+const TOP: usize = 10;
+/// Width of the bitrate breakdown's bands, in kbit/s
+const BITRATE_BAND: u64 = 64;
+
 #[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Stats<'a> {
     pub stats: StatNumbers,
+    /// The numbers of each root, when there are several
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub roots: BTreeMap<String, RootNumbers>,
+    pub breakdown: Breakdown,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub total: Vec<&'a Path>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub tagged: Vec<&'a Path>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub untagged: Vec<&'a Path>,
+    /// The required tags each untagged song lacks
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub missing_tags: BTreeMap<&'a Path, Vec<String>>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub sorted: Vec<&'a Path>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub unsorted: Vec<&'a Path>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub albums: Vec<String>,
+    /// Albums missing tracks or track numbers
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub incomplete_albums: Vec<String>,
+    /// Albums whose tracks disagree on their gain tags
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub inconsistent_gain: Vec<String>,
+    /// Songs with embedded lyrics or an `.lrc` file
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lyrics: Vec<&'a Path>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub no_lyrics: Vec<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<Quality<'a>>,
+}
+
+/// Songs with audio problems, see [`Problem`]
+#[derive(Debug, Clone, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Quality<'a> {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub clipped: Vec<&'a Path>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub padded: Vec<&'a Path>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dc_offset: Vec<&'a Path>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fake_stereo: Vec<&'a Path>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub compressed: Vec<&'a Path>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fake_lossless: Vec<&'a Path>,
+}
+
+impl<'a> Quality<'a> {
+    pub fn new(songs: &[SongAnalysis<'a>]) -> Self {
+        let mut quality = Self::default();
+        for song in songs {
+            for problem in &song.problems {
+                quality.songs_mut(*problem).push(song.path);
+            }
+        }
+        quality
+    }
+
+    fn songs_mut(&mut self, problem: Problem) -> &mut Vec<&'a Path> {
+        match problem {
+            Problem::Clipped => &mut self.clipped,
+            Problem::Padded => &mut self.padded,
+            Problem::DcOffset => &mut self.dc_offset,
+            Problem::FakeStereo => &mut self.fake_stereo,
+            Problem::Compressed => &mut self.compressed,
+            Problem::FakeLossless => &mut self.fake_lossless,
+        }
+    }
+
+    fn numbers(&self) -> QualityNumbers {
+        QualityNumbers {
+            clipped: self.clipped.len(),
+            padded: self.padded.len(),
+            dc_offset: self.dc_offset.len(),
+            fake_stereo: self.fake_stereo.len(),
+            compressed: self.compressed.len(),
+            fake_lossless: self.fake_lossless.len(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for problem in Problem::ALL {
+            self.songs_mut(*problem).clear();
+        }
+    }
 }
 
 impl<'a> Stats<'a> {
+    /// Keep only the numbers, dropping the lists of songs and albums
+    pub fn clear_lists(&mut self) {
+        self.total.clear();
+        self.tagged.clear();
+        self.untagged.clear();
+        self.missing_tags.clear();
+        self.sorted.clear();
+        self.unsorted.clear();
+        self.albums.clear();
+        self.incomplete_albums.clear();
+        self.inconsistent_gain.clear();
+        self.lyrics.clear();
+        self.no_lyrics.clear();
+        if let Some(quality) = &mut self.quality {
+            quality.clear();
+        }
+    }
+
     pub fn new(
         total: Vec<&'a Path>,
         tagged: Vec<&'a Path>,
         untagged: Vec<&'a Path>,
         sorted: Vec<&'a Path>,
         unsorted: Vec<&'a Path>,
+        inconsistent_gain: Vec<String>,
+        quality: Option<Quality<'a>>,
     ) -> Self {
         let mut s = Self {
             stats: Default::default(),
+            roots: BTreeMap::new(),
+            breakdown: Default::default(),
             total,
             tagged,
             untagged,
+            missing_tags: BTreeMap::new(),
+            albums: Vec::new(),
+            incomplete_albums: Vec::new(),
             sorted,
             unsorted,
+            inconsistent_gain,
+            lyrics: Vec::new(),
+            no_lyrics: Vec::new(),
+            quality,
         };
         s.update_numbers();
         s
     }
 
     fn update_numbers(&mut self) {
+        let mut missing = BTreeMap::new();
+        for tag in self.missing_tags.values().flatten() {
+            *missing.entry(tag.clone()).or_default() += 1;
+        }
         self.stats = StatNumbers {
             total: self.total.len(),
             tagged: self.tagged.len(),
             untagged: self.untagged.len(),
+            missing,
             sorted: self.sorted.len(),
             unsorted: self.unsorted.len(),
+            albums: self.albums.len(),
+            incomplete_albums: self.incomplete_albums.len(),
+            inconsistent_gain: self.inconsistent_gain.len(),
+            lyrics: self.lyrics.len(),
+            no_lyrics: self.no_lyrics.len(),
+            quality: self.quality.as_ref().map(Quality::numbers),
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct StatNumbers {
     pub total: usize,
     pub tagged: usize,
     pub untagged: usize,
+    /// Songs lacking each required tag
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub missing: BTreeMap<String, usize>,
     pub sorted: usize,
     pub unsorted: usize,
+    pub albums: usize,
+    pub incomplete_albums: usize,
+    pub inconsistent_gain: usize,
+    pub lyrics: usize,
+    pub no_lyrics: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityNumbers>,
+}
+
+/// The songs and albums under a library root
+#[derive(Debug, Clone, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RootNumbers {
+    pub path: PathBuf,
+    pub total: usize,
+    pub tagged: usize,
+    pub untagged: usize,
+    pub sorted: usize,
+    pub unsorted: usize,
+    pub albums: usize,
+    pub incomplete_albums: usize,
+    pub lyrics: usize,
+    pub no_lyrics: usize,
+    /// Total playing time in seconds
+    pub playtime: f64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct QualityNumbers {
+    pub clipped: usize,
+    pub padded: usize,
+    pub dc_offset: usize,
+    pub fake_stereo: usize,
+    pub compressed: usize,
+    pub fake_lossless: usize,
+}
+
+/// Distributions of the library's formats, tags and playing time
+#[derive(Debug, Clone, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Breakdown {
+    /// Total playing time in seconds
+    pub playtime: f64,
+    pub codec: BTreeMap<String, Share>,
+    /// Containers as found in the files' contents, whatever their extension
+    pub container: BTreeMap<String, Share>,
+    /// Average bitrates in bands of 64 kbit/s
+    pub bitrate: BTreeMap<String, Share>,
+    pub sample_rate: BTreeMap<String, Share>,
+    pub bit_depth: BTreeMap<String, Share>,
+    pub channels: BTreeMap<String, Share>,
+    pub decade: BTreeMap<String, Share>,
+    pub artists: Ranking,
+    pub albums: Ranking,
+    /// Percentage of songs having each template field
+    pub coverage: BTreeMap<&'static str, f64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Share {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Ranking {
+    pub by_count: Vec<Entry>,
+    pub by_duration: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Entry {
+    pub name: String,
+    pub songs: usize,
+    /// Playing time in seconds
+    pub duration: f64,
+}
+
+/// What probing a song says about its stream
+#[derive(Debug, Clone)]
+struct Format {
+    codec: &'static str,
+    container: &'static str,
+    sample_rate: Option<u32>,
+    bit_depth: Option<u32>,
+    channels: Option<usize>,
+    duration: Option<f64>,
+    bytes: u64,
+}
+
+impl Format {
+    fn new(path: &Path, params: Option<&CodecParameters>) -> std::io::Result<Self> {
+        Ok(Self {
+            codec: codec_name(params),
+            container: container_name(path)?,
+            sample_rate: params.and_then(|p| p.sample_rate),
+            bit_depth: params.and_then(|p| p.bits_per_sample),
+            channels: params.and_then(|p| p.channels).map(|c| c.count()),
+            duration: params.and_then(duration),
+            bytes: path.metadata()?.len(),
+        })
+    }
+
+    /// The band of 64 kbit/s holding the song's average bitrate, such as `128-191`
+    fn bitrate_band(&self) -> Option<String> {
+        let duration = self.duration.filter(|d| *d > 0.0)?;
+        let kbps = (self.bytes as f64 * 8.0 / duration / 1000.0) as u64;
+        let low = kbps / BITRATE_BAND * BITRATE_BAND;
+        Some(format!("{low}-{}", low + BITRATE_BAND - 1))
+    }
 }
 
-pub fn get_stats<'a>(prefix: &Path, songs: &'a [PathBuf]) -> crate::Result<Stats<'a>> {
-    let songs = songs
+/// The container of the song at `path`, sniffed from its first bytes
+fn container_name(path: &Path) -> std::io::Result<&'static str> {
+    let file = BufReader::new(File::open(path)?);
+    let probe = Probe::new(file).guess_file_type()?;
+    Ok(match probe.file_type() {
+        Some(FileType::Aac) => "adts",
+        Some(FileType::Aiff) => "aiff",
+        Some(FileType::Ape) => "ape",
+        Some(FileType::Flac) => "flac",
+        Some(FileType::Mpeg) => "mpeg",
+        Some(FileType::Mp4) => "mp4",
+        Some(FileType::Mpc) => "mpc",
+        Some(FileType::Opus | FileType::Vorbis | FileType::Speex) => "ogg",
+        Some(FileType::Wav) => "riff",
+        Some(FileType::WavPack) => "wavpack",
+        _ => "unknown",
+    })
+}
+
+impl Breakdown {
+    fn new(songs: &[(&Path, Tags)], formats: &[Format]) -> Self {
+        let mut breakdown = Self::default();
+        let mut artists: HashMap<String, Entry> = HashMap::new();
+        let mut albums: HashMap<String, Entry> = HashMap::new();
+        for ((_, tags), format) in songs.iter().zip(formats) {
+            let duration = format.duration.unwrap_or_default();
+            breakdown.playtime += duration;
+            let known = |v: Option<String>| v.unwrap_or_else(|| "unknown".to_string());
+            for (distribution, key) in [
+                (&mut breakdown.codec, format.codec.to_string()),
+                (&mut breakdown.container, format.container.to_string()),
+                (&mut breakdown.bitrate, known(format.bitrate_band())),
+                (
+                    &mut breakdown.sample_rate,
+                    known(format.sample_rate.map(|r| r.to_string())),
+                ),
+                (
+                    &mut breakdown.bit_depth,
+                    known(format.bit_depth.map(|b| b.to_string())),
+                ),
+                (
+                    &mut breakdown.channels,
+                    known(format.channels.map(|c| c.to_string())),
+                ),
+                (
+                    &mut breakdown.decade,
+                    known(
+                        Field::Year
+                            .value(tags)
+                            .and_then(|y| y.parse::<u32>().ok())
+                            .map(|y| format!("{}s", y / 10 * 10)),
+                    ),
+                ),
+            ] {
+                let share = distribution.entry(key).or_default();
+                share.files += 1;
+                share.bytes += format.bytes;
+            }
+
+            let artist = [Field::AlbumArtist, Field::Artist]
+                .iter()
+                .find_map(|f| f.value(tags));
+            if let Some(artist) = &artist {
+                count(&mut artists, artist.clone(), duration);
+            }
+            if let Some(album) = Field::Album.value(tags) {
+                let name = match &artist {
+                    Some(artist) => format!("{artist} - {album}"),
+                    None => album,
+                };
+                count(&mut albums, name, duration);
+            }
+        }
+        breakdown.artists = Ranking::new(artists.into_values().collect());
+        breakdown.albums = Ranking::new(albums.into_values().collect());
+        breakdown.coverage = Field::ALL
+            .iter()
+            .map(|field| {
+                let tagged = songs
+                    .iter()
+                    .filter(|(_, tags)| field.value(tags).is_some())
+                    .count();
+                let percent = tagged as f64 * 100.0 / songs.len().max(1) as f64;
+                (field.name(), (percent * 10.0).round() / 10.0)
+            })
+            .collect();
+        breakdown
+    }
+}
+
+fn count(entries: &mut HashMap<String, Entry>, name: String, duration: f64) {
+    let entry = entries.entry(name.clone()).or_insert_with(|| Entry {
+        name,
+        ..Default::default()
+    });
+    entry.songs += 1;
+    entry.duration += duration;
+}
+
+impl Ranking {
+    fn new(mut entries: Vec<Entry>) -> Self {
+        entries.sort_unstable_by(|a, b| b.songs.cmp(&a.songs).then_with(|| a.name.cmp(&b.name)));
+        let by_count = entries.iter().take(TOP).cloned().collect();
+        entries.sort_unstable_by(|a, b| {
+            b.duration
+                .total_cmp(&a.duration)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        entries.truncate(TOP);
+        Self {
+            by_count,
+            by_duration: entries,
+        }
+    }
+}
+
+/// Stats of `songs`, each sorted within the root of `roots` holding it
+pub fn get_stats<'a>(
+    roots: &[LibraryRoot],
+    songs: &'a [PathBuf],
+    template: &Template,
+    sanitizer: &Sanitizer,
+    required: &RequiredTags,
+    analyze: bool,
+) -> crate::Result<Stats<'a>> {
+    let quality = match analyze {
+        true => Some(Quality::new(&analyze_songs(songs, true)?.songs)),
+        false => None,
+    };
+    let (songs, formats): (Vec<_>, Vec<_>) = songs
         .par_iter()
         .map(|s| -> crate::Result<_> {
             let mut probed = get_probe().format(
@@ -77,57 +464,236 @@ pub fn get_stats<'a>(prefix: &Path, songs: &'a [PathBuf]) -> crate::Result<Stats
                 &Default::default(),
                 &Default::default(),
             )?;
-            Ok((s.as_path(), get_artist(&mut probed), get_title(&mut probed)))
+            let tags = read_tags(&mut probed, true);
+            let format = Format::new(s, probed.format.default_track().map(|t| &t.codec_params))?;
+            Ok(((s.as_path(), tags), format))
         })
-        .collect::<crate::Result<Vec<_>>>()?;
-    let total = songs.par_iter().map(|(p, _, _)| *p).collect();
+        .collect::<crate::Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    let missing_tags: BTreeMap<&Path, Vec<String>> = songs
+        .par_iter()
+        .map(|(p, t)| (*p, required.missing(p, t)))
+        .filter(|(_, missing)| !missing.is_empty())
+        .collect();
+    let is_tagged = |p: &Path| !missing_tags.contains_key(p);
+    // songs are placed as sort places them, by the tags their album agrees on
+    let tracks = songs
+        .iter()
+        .zip(&formats)
+        .map(|((p, t), f)| Track::new(p, t.clone(), f.codec, f.duration))
+        .collect();
+    let targets: HashMap<&Path, PathBuf> = track_destinations(
+        tracks,
+        |p| Some(root_of(roots, p)?.path.as_path()),
+        template,
+        sanitizer,
+    )
+    .into_iter()
+    .filter_map(|(p, target)| Some((p, target?)))
+    .collect();
+    let target = |p: &Path| targets.get(p);
+    let total = songs.par_iter().map(|(p, _)| *p).collect();
     let tagged: Vec<&Path> = songs
         .par_iter()
-        .filter(|(_, a, t)| a.is_some() && t.is_some())
-        .map(|(p, _, _)| *p)
+        .filter(|(p, _)| is_tagged(p))
+        .map(|(p, _)| *p)
         .collect();
     let untagged = songs
         .par_iter()
-        .filter(|(_, a, t)| a.is_none() || t.is_none())
-        .map(|(p, _, _)| *p)
+        .filter(|(p, _)| !is_tagged(p))
+        .map(|(p, _)| *p)
         .collect();
 
     let sorted = songs
         .par_iter()
-        .filter(|(p, a, t)| {
-            if let (Some(a), Some(t)) = (a, t) {
-                return *p
-                    == target_location(
-                        prefix,
-                        a,
-                        t,
-                        &p.extension()
-                            .expect("All songs have an extention")
-                            .to_string_lossy(),
-                    );
-            }
-            false
-        })
-        .map(|(p, _, _)| *p)
+        .filter(|(p, _)| is_tagged(p) && target(p).is_some_and(|target| p == target))
+        .map(|(p, _)| *p)
         .collect();
     let unsorted = songs
         .par_iter()
-        .filter(|(p, a, t)| {
-            if let (Some(a), Some(t)) = (a, t) {
-                return *p
-                    != target_location(
-                        prefix,
-                        a,
-                        t,
-                        &p.extension()
-                            .expect("All songs have an extention")
-                            .to_string_lossy(),
-                    );
-            }
-            false
-        })
-        .map(|(p, _, _)| *p)
+        .filter(|(p, _)| is_tagged(p) && target(p).is_some_and(|target| p != target))
+        .map(|(p, _)| *p)
+        .collect();
+
+    let inconsistent_gain = inconsistent_gain_albums(&songs);
+
+    let mut stats = Stats::new(
+        total,
+        tagged,
+        untagged,
+        sorted,
+        unsorted,
+        inconsistent_gain,
+        quality,
+    );
+    let tracks = songs
+        .iter()
+        .zip(&formats)
+        .flat_map(|((p, t), f)| Track::new(p, t.clone(), f.codec, f.duration).split_cue())
         .collect();
+    let (albums, _) = group_albums(tracks);
+    stats.albums = albums.iter().map(Album::title).collect();
+    stats.incomplete_albums = albums
+        .iter()
+        .filter(|a| !a.is_complete())
+        .map(Album::title)
+        .collect();
+    stats.missing_tags = missing_tags;
+    (stats.lyrics, stats.no_lyrics) = songs
+        .par_iter()
+        .map(|(p, t)| (*p, has_lyrics(p, t)))
+        .partition_map(|(p, has)| match has {
+            true => Either::Left(p),
+            false => Either::Right(p),
+        });
+    stats.update_numbers();
+    if roots.len() > 1 {
+        stats.roots = roots
+            .iter()
+            .map(|root| {
+                let within = |p: &Path| root_of(roots, p) == Some(root);
+                let count = |songs: &[&Path]| songs.iter().filter(|p| within(p)).count();
+                let root_albums: Vec<_> = albums.iter().filter(|a| within(a.dir)).collect();
+                let root_formats = songs.iter().zip(&formats).filter(|((p, _), _)| within(p));
+                let numbers = RootNumbers {
+                    path: root.path.clone(),
+                    total: count(&stats.total),
+                    tagged: count(&stats.tagged),
+                    untagged: count(&stats.untagged),
+                    sorted: count(&stats.sorted),
+                    unsorted: count(&stats.unsorted),
+                    albums: root_albums.len(),
+                    incomplete_albums: root_albums.iter().filter(|a| !a.is_complete()).count(),
+                    lyrics: count(&stats.lyrics),
+                    no_lyrics: count(&stats.no_lyrics),
+                    playtime: root_formats.clone().filter_map(|(_, f)| f.duration).sum(),
+                    bytes: root_formats.map(|(_, f)| f.bytes).sum(),
+                };
+                (root.name.clone(), numbers)
+            })
+            .collect();
+    }
+    stats.breakdown = Breakdown::new(&songs, &formats);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        get_songs,
+        sort::{Mode, SortOptions, sort_songs_transactions},
+        test_util::write_song,
+    };
 
-    Ok(Stats::new(total, tagged, untagged, sorted, unsorted))
+    #[test]
+    fn songs_sort_placed_are_sorted() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        // the odd album artist is placed with the rest of the album
+        for (n, album_artist) in ["Band", "Band", "The Band"].iter().enumerate() {
+            let title = format!("Song {n}");
+            let tags = [
+                ("Artist", "Band"),
+                ("AlbumArtist", album_artist),
+                ("Album", "X"),
+                ("TrackTitle", title.as_str()),
+            ];
+            write_song(&root.join(format!("rip/{n}.flac")), n as i32 + 1, &tags);
+        }
+        let template: Template = "{albumartist}/{album}/{title}".parse().unwrap();
+        let sanitizer = Sanitizer::default();
+        let options = SortOptions {
+            mode: Mode::Move,
+            template: &template,
+            sanitizer: &sanitizer,
+            skip_existing: false,
+        };
+        let songs = get_songs(root.clone()).unwrap();
+        let transactions =
+            sort_songs_transactions(&root, &root, &songs, &options, &mut HashMap::new()).unwrap();
+        for transaction in &transactions {
+            transaction.apply().unwrap();
+        }
+
+        let roots = [LibraryRoot::resolve(&root, &BTreeMap::new())];
+        let songs = get_songs(root.clone()).unwrap();
+        let required = RequiredTags::default();
+        let stats = get_stats(&roots, &songs, &template, &sanitizer, &required, false).unwrap();
+        assert_eq!(stats.sorted.len(), 3);
+        assert!(stats.unsorted.is_empty(), "{:?}", stats.unsorted);
+    }
+
+    #[test]
+    fn breakdowns_count_files_and_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        // a FLAC stream named as Ogg Vorbis
+        let songs = vec![root.join("a.flac"), root.join("b.ogg")];
+        let tags = [("Artist", "A"), ("TrackTitle", "T")];
+        write_song(
+            &songs[0],
+            1,
+            &[tags[0], tags[1], ("Album", "X"), ("Date", "1999")],
+        );
+        write_song(
+            &root.join("b.flac"),
+            2,
+            &[tags[0], tags[1], ("Date", "2003")],
+        );
+        std::fs::rename(root.join("b.flac"), &songs[1]).unwrap();
+        let bytes: Vec<u64> = songs.iter().map(|s| s.metadata().unwrap().len()).collect();
+
+        let roots = [LibraryRoot::resolve(&root, &BTreeMap::new())];
+        let template: Template = "{artist}/{title}".parse().unwrap();
+        let (sanitizer, required) = (Sanitizer::default(), RequiredTags::default());
+        let stats = get_stats(&roots, &songs, &template, &sanitizer, &required, false).unwrap();
+        let breakdown = stats.breakdown;
+
+        let files = |distribution: &BTreeMap<String, Share>| -> Vec<(String, usize)> {
+            distribution
+                .iter()
+                .map(|(key, share)| (key.clone(), share.files))
+                .collect()
+        };
+        let both = |key: &str| vec![(key.to_string(), 2)];
+        assert_eq!(files(&breakdown.codec), both("flac"));
+        assert_eq!(files(&breakdown.container), both("flac"));
+        assert_eq!(breakdown.container["flac"].bytes, bytes.iter().sum::<u64>());
+        assert_eq!(files(&breakdown.sample_rate), both("44100"));
+        assert_eq!(files(&breakdown.bit_depth), both("16"));
+        assert_eq!(files(&breakdown.channels), both("1"));
+        assert_eq!(
+            files(&breakdown.decade),
+            [("1990s".to_string(), 1), ("2000s".to_string(), 1)]
+        );
+        // 0.1 s songs of a few kilobytes
+        let banded: usize = breakdown.bitrate.values().map(|s| s.files).sum();
+        assert_eq!(banded, 2);
+        for (band, share) in &breakdown.bitrate {
+            let (low, high) = band.split_once('-').unwrap();
+            let (low, high): (u64, u64) = (low.parse().unwrap(), high.parse().unwrap());
+            assert_eq!(high - low + 1, BITRATE_BAND);
+            let kbps: Vec<u64> = bytes.iter().map(|b| b * 8 * 10 / 1000).collect();
+            let inside = kbps.iter().filter(|k| (low..=high).contains(*k)).count();
+            assert_eq!(share.files, inside, "{band}: {kbps:?}");
+        }
+        assert!(
+            (breakdown.playtime - 0.2).abs() < 1e-9,
+            "{}",
+            breakdown.playtime
+        );
+
+        let names = |entries: &[Entry]| -> Vec<(String, usize)> {
+            entries.iter().map(|e| (e.name.clone(), e.songs)).collect()
+        };
+        assert_eq!(names(&breakdown.artists.by_count), both("A"));
+        assert_eq!(
+            names(&breakdown.albums.by_count),
+            [("A - X".to_string(), 1)]
+        );
+        assert_eq!(breakdown.coverage["album"], 50.0);
+        assert_eq!(breakdown.coverage["title"], 100.0);
+    }
 }
@@ -0,0 +1,231 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use symphonia::{core::io::MediaSourceStream, default::get_probe};
+use tracing::{debug, warn};
+
+use crate::{
+    duplicates::hash_stream,
+    filter::Filter,
+    get_songs,
+    sanitize::{Profile, Sanitizer},
+    sort::{Transaction, mkdir_transactions},
+    tags::read_tags,
+    template::Template,
+};
+
+/// Parse a size such as `32G`, `700M` or `1024` (bytes), using binary units
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let digits = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let number: f64 = number.parse().map_err(|_| format!("Invalid size '{s}'"))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let shift = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("Unknown size unit '{unit}'")),
+    };
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+/// Plan making `dest` hold exactly the songs matching `filter`, up to `max_size` bytes.
+/// Songs already on `dest` with the same audio stream are left alone and songs on `dest`
+/// that are no longer selected are removed.
+pub fn sync_transactions(
+    dest: &Path,
+    songs: &[PathBuf],
+    filter: &Filter,
+    max_size: Option<u64>,
+    template: &Template,
+    sanitizer: &Sanitizer,
+) -> crate::Result<Vec<Transaction>> {
+    let mut candidates = songs
+        .par_iter()
+        .map(|s| -> crate::Result<_> {
+            let mut probed = get_probe().format(
+                &Default::default(),
+                MediaSourceStream::new(Box::new(File::open(s)?), Default::default()),
+                &Default::default(),
+                &Default::default(),
+            )?;
+            let tags = read_tags(&mut probed, true);
+            if !filter.matches(s, &tags) {
+                return Ok(None);
+            }
+            let extension = s.extension().expect("All songs have an extension");
+            let Some(target) =
+                template.render(dest, &tags, &extension.to_string_lossy(), sanitizer)
+            else {
+                warn!(
+                    "Skipping {}: missing metadata for '{template}'",
+                    s.display()
+                );
+                return Ok(None);
+            };
+            Ok(Some((s.as_path(), target, s.metadata()?.len())))
+        })
+        .filter_map(Result::transpose)
+        .collect::<crate::Result<Vec<_>>>()?;
+    candidates.sort_unstable();
+
+    let mut selected = Vec::new();
+    let mut targets = HashMap::new();
+    let mut size = 0;
+    for (src, target, len) in candidates {
+        if max_size.is_some_and(|max| size + len > max) {
+            debug!("Skipping {}: selection is full", src.display());
+            continue;
+        }
+        if let Some(other) = targets.insert(device_key(&target, sanitizer.profile), src) {
+            warn!(
+                "Skipping {}: {} is synced to the same destination",
+                src.display(),
+                other.display()
+            );
+            continue;
+        }
+        size += len;
+        selected.push((src, target));
+    }
+
+    let kept: HashSet<PathBuf> = targets.into_keys().collect();
+    let stale: HashSet<PathBuf> = if dest.exists() {
+        get_songs(dest.to_path_buf())?
+            .into_iter()
+            .filter(|s| !kept.contains(&device_key(s, sanitizer.profile)))
+            .collect()
+    } else {
+        HashSet::new()
+    };
+    let mut rmdirs = rmdir_transactions(dest, &stale, &kept, sanitizer.profile)?;
+
+    let mut transactions = selected
+        .into_par_iter()
+        .map(|(src, target)| -> crate::Result<Vec<Transaction>> {
+            let mut transactions = Vec::new();
+            if target.symlink_metadata().is_ok() {
+                if hash_stream(src)? == hash_stream(&target)? {
+                    return Ok(transactions);
+                }
+                transactions.push(Transaction::Remove(target.clone()));
+            } else {
+                transactions.append(&mut mkdir_transactions(&target));
+            }
+            transactions.push(Transaction::Copy {
+                src: src.to_path_buf(),
+                dest: target,
+            });
+            Ok(transactions)
+        })
+        .collect::<crate::Result<Vec<_>>>()?
+        .concat();
+    transactions.extend(stale.iter().cloned().map(Transaction::Remove));
+    transactions.append(&mut rmdirs);
+    transactions.sort();
+    transactions.dedup();
+    Ok(transactions)
+}
+
+/// The path `path` is told apart by on the device. FAT and SMB do not tell apart names
+/// differing only in case, so an existing `Artist/song.mp3` is the target `artist/Song.mp3`.
+fn device_key(path: &Path, profile: Profile) -> PathBuf {
+    match profile {
+        Profile::Posix => path.to_path_buf(),
+        _ => PathBuf::from(path.to_string_lossy().to_lowercase()),
+    }
+}
+
+/// Directories below `dest` left empty once `removed` is gone, `kept` holding the
+/// [`device_key`]s of the synced songs
+fn rmdir_transactions(
+    dest: &Path,
+    removed: &HashSet<PathBuf>,
+    kept: &HashSet<PathBuf>,
+    profile: Profile,
+) -> std::io::Result<Vec<Transaction>> {
+    let candidates: HashSet<&Path> = removed
+        .iter()
+        .flat_map(|p| p.ancestors().skip(1))
+        .filter(|dir| dir.starts_with(dest) && *dir != dest)
+        .collect();
+    let mut transactions = Vec::new();
+    for dir in candidates {
+        let key = device_key(dir, profile);
+        if !kept.iter().any(|k| k.starts_with(&key)) && only_contains(dir, removed)? {
+            transactions.push(Transaction::Rmdir(dir.to_path_buf()));
+        }
+    }
+    Ok(transactions)
+}
+
+fn only_contains(dir: &Path, removed: &HashSet<PathBuf>) -> std::io::Result<bool> {
+    for child in dir.read_dir()? {
+        let path = child?.path();
+        let removable = if path.is_dir() {
+            only_contains(&path, removed)?
+        } else {
+            removed.contains(&path)
+        };
+        if !removable {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_song;
+
+    #[test]
+    fn sizes_use_binary_units() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size(" 700M "), Ok(700 << 20));
+        assert_eq!(parse_size("32G"), Ok(32 << 30));
+        assert_eq!(parse_size("1.5 KiB"), Ok(1536));
+        assert_eq!(parse_size("2tb"), Ok(2 << 40));
+        assert_eq!(parse_size("12B"), Ok(12));
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("3X").is_err());
+    }
+
+    #[test]
+    fn case_only_rename_keeps_the_device_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let song = dir.path().join("library/song.flac");
+        let tags = [("Artist", "artist"), ("TrackTitle", "Song")];
+        write_song(&song, 3, &tags);
+        let device = dir.path().join("device");
+        let existing = device.join("Artist/song.flac");
+        std::fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        std::fs::copy(&song, &existing).unwrap();
+
+        let transactions = sync_transactions(
+            &device,
+            &[song],
+            &Filter::All,
+            None,
+            &Template::default(),
+            &Sanitizer::new(Profile::Windows),
+        )
+        .unwrap();
+        assert!(
+            !transactions
+                .iter()
+                .any(|t| matches!(t, Transaction::Remove(_))),
+            "{transactions:?}"
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use std::{collections::BTreeMap, fs::File, path::Path};
+
+use lofty::{
+    config::WriteOptions,
+    file::{AudioFile, TaggedFileExt},
+    picture::{MimeType, Picture, PictureType},
+    tag::{ItemKey, ItemValue, Tag, TagItem},
+};
+use symphonia::{
+    core::{
+        io::MediaSourceStream,
+        meta::{Metadata, StandardTagKey, StandardVisualKey, Visual},
+        probe::ProbeResult,
+    },
+    default::get_probe,
+};
+
+/// Tags of a song, standard tags keyed by their symphonia name (`Artist`, `TrackTitle`, ...)
+/// and nonstandard tags keyed verbatim. Of a tag with several values only the first is kept.
+pub type Tags = BTreeMap<String, String>;
+
+/// Every value of each tag of a song, keyed like [`Tags`]
+pub type TagValues = BTreeMap<String, Vec<String>>;
+
+/// Changes to the tags of a song, `None` removes the tag
+pub type TagChanges = BTreeMap<String, Option<String>>;
+
+/// Symphonia's standard keys and the lofty key they are written as
+const KEYS: &[(StandardTagKey, ItemKey)] = &[
+    (StandardTagKey::Album, ItemKey::AlbumTitle),
+    (StandardTagKey::AlbumArtist, ItemKey::AlbumArtist),
+    (StandardTagKey::Arranger, ItemKey::Arranger),
+    (StandardTagKey::Artist, ItemKey::TrackArtist),
+    (StandardTagKey::Bpm, ItemKey::Bpm),
+    (StandardTagKey::Comment, ItemKey::Comment),
+    (StandardTagKey::Compilation, ItemKey::FlagCompilation),
+    (StandardTagKey::Composer, ItemKey::Composer),
+    (StandardTagKey::Conductor, ItemKey::Conductor),
+    (StandardTagKey::Copyright, ItemKey::CopyrightMessage),
+    (StandardTagKey::Date, ItemKey::RecordingDate),
+    (StandardTagKey::Description, ItemKey::Description),
+    (StandardTagKey::DiscNumber, ItemKey::DiscNumber),
+    (StandardTagKey::DiscSubtitle, ItemKey::SetSubtitle),
+    (StandardTagKey::DiscTotal, ItemKey::DiscTotal),
+    (StandardTagKey::EncodedBy, ItemKey::EncodedBy),
+    (StandardTagKey::Encoder, ItemKey::EncoderSoftware),
+    (StandardTagKey::Genre, ItemKey::Genre),
+    (StandardTagKey::IdentBarcode, ItemKey::Barcode),
+    (StandardTagKey::IdentCatalogNumber, ItemKey::CatalogNumber),
+    (StandardTagKey::IdentIsrc, ItemKey::Isrc),
+    (StandardTagKey::Label, ItemKey::Label),
+    (StandardTagKey::Language, ItemKey::Language),
+    (StandardTagKey::Lyricist, ItemKey::Lyricist),
+    (StandardTagKey::Lyrics, ItemKey::Lyrics),
+    (StandardTagKey::MediaFormat, ItemKey::OriginalMediaType),
+    (StandardTagKey::Mood, ItemKey::Mood),
+    (
+        StandardTagKey::MusicBrainzAlbumArtistId,
+        ItemKey::MusicBrainzReleaseArtistId,
+    ),
+    (
+        StandardTagKey::MusicBrainzAlbumId,
+        ItemKey::MusicBrainzReleaseId,
+    ),
+    (
+        StandardTagKey::MusicBrainzArtistId,
+        ItemKey::MusicBrainzArtistId,
+    ),
+    (
+        StandardTagKey::MusicBrainzRecordingId,
+        ItemKey::MusicBrainzRecordingId,
+    ),
+    (
+        StandardTagKey::MusicBrainzReleaseGroupId,
+        ItemKey::MusicBrainzReleaseGroupId,
+    ),
+    // like Picard, symphonia reads the recording id as the track id, lofty names the
+    // same fields after what they hold
+    (
+        StandardTagKey::MusicBrainzTrackId,
+        ItemKey::MusicBrainzRecordingId,
+    ),
+    (
+        StandardTagKey::MusicBrainzReleaseTrackId,
+        ItemKey::MusicBrainzTrackId,
+    ),
+    (
+        StandardTagKey::MusicBrainzWorkId,
+        ItemKey::MusicBrainzWorkId,
+    ),
+    (StandardTagKey::OriginalAlbum, ItemKey::OriginalAlbumTitle),
+    (StandardTagKey::OriginalArtist, ItemKey::OriginalArtist),
+    (StandardTagKey::OriginalDate, ItemKey::OriginalReleaseDate),
+    (StandardTagKey::Performer, ItemKey::Performer),
+    (StandardTagKey::Producer, ItemKey::Producer),
+    (StandardTagKey::ReleaseDate, ItemKey::ReleaseDate),
+    (StandardTagKey::Remixer, ItemKey::Remixer),
+    (
+        StandardTagKey::ReplayGainAlbumGain,
+        ItemKey::ReplayGainAlbumGain,
+    ),
+    (
+        StandardTagKey::ReplayGainAlbumPeak,
+        ItemKey::ReplayGainAlbumPeak,
+    ),
+    (
+        StandardTagKey::ReplayGainTrackGain,
+        ItemKey::ReplayGainTrackGain,
+    ),
+    (
+        StandardTagKey::ReplayGainTrackPeak,
+        ItemKey::ReplayGainTrackPeak,
+    ),
+    (StandardTagKey::Script, ItemKey::Script),
+    (StandardTagKey::SortAlbum, ItemKey::AlbumTitleSortOrder),
+    (
+        StandardTagKey::SortAlbumArtist,
+        ItemKey::AlbumArtistSortOrder,
+    ),
+    (StandardTagKey::SortArtist, ItemKey::TrackArtistSortOrder),
+    (StandardTagKey::SortComposer, ItemKey::ComposerSortOrder),
+    (StandardTagKey::SortTrackTitle, ItemKey::TrackTitleSortOrder),
+    (StandardTagKey::TrackNumber, ItemKey::TrackNumber),
+    (StandardTagKey::TrackSubtitle, ItemKey::TrackSubtitle),
+    (StandardTagKey::TrackTitle, ItemKey::TrackTitle),
+    (StandardTagKey::TrackTotal, ItemKey::TrackTotal),
+    (StandardTagKey::Writer, ItemKey::Writer),
+];
+
+/// The name a standard tag is keyed by in [`Tags`]
+pub fn key_name(key: StandardTagKey) -> String {
+    format!("{key:?}")
+}
+
+/// Probe the song at `path` and read its latest tags
+pub fn read_file_tags(path: &Path, nonstandard: bool) -> crate::Result<Tags> {
+    let mut probed = get_probe().format(
+        &Default::default(),
+        MediaSourceStream::new(Box::new(File::open(path)?), Default::default()),
+        &Default::default(),
+        &Default::default(),
+    )?;
+    Ok(read_tags(&mut probed, nonstandard))
+}
+
+/// Read the latest tags of a probed song
+pub fn read_tags(probed: &mut ProbeResult, nonstandard: bool) -> Tags {
+    read_tag_values(probed, nonstandard)
+        .into_iter()
+        .filter_map(|(key, values)| Some((key, values.into_iter().next()?)))
+        .collect()
+}
+
+/// Probe the song at `path` and read every value of its latest tags
+pub fn read_file_tag_values(path: &Path, nonstandard: bool) -> crate::Result<TagValues> {
+    let mut probed = get_probe().format(
+        &Default::default(),
+        MediaSourceStream::new(Box::new(File::open(path)?), Default::default()),
+        &Default::default(),
+        &Default::default(),
+    )?;
+    Ok(read_tag_values(&mut probed, nonstandard))
+}
+
+/// Read every value of the latest tags of a probed song
+pub fn read_tag_values(probed: &mut ProbeResult, nonstandard: bool) -> TagValues {
+    let mut tags = TagValues::new();
+    if let Some(mut md) = probed.metadata.get() {
+        add_metadata(&mut md, &mut tags, nonstandard);
+    }
+    add_metadata(&mut probed.format.metadata(), &mut tags, nonstandard);
+    tags
+}
+
+fn add_metadata(metadata: &mut Metadata, tags: &mut TagValues, nonstandard: bool) {
+    let Some(md) = metadata.skip_to_latest() else {
+        return;
+    };
+
+    // values repeated within a revision add up, later revisions replace them
+    let mut revision = TagValues::new();
+    for tag in md.tags() {
+        let key = match tag.std_key {
+            Some(key) => key_name(key),
+            None if nonstandard => tag.key.to_string(),
+            None => continue,
+        };
+        revision.entry(key).or_default().push(tag.value.to_string());
+    }
+    tags.extend(revision);
+}
+
+/// Read the embedded pictures of a probed song
+pub fn read_visuals(probed: &mut ProbeResult) -> Vec<Visual> {
+    if let Some(mut md) = probed.metadata.get()
+        && let Some(rev) = md.skip_to_latest()
+        && !rev.visuals().is_empty()
+    {
+        return rev.visuals().to_vec();
+    }
+    probed
+        .format
+        .metadata()
+        .skip_to_latest()
+        .map(|rev| rev.visuals().to_vec())
+        .unwrap_or_default()
+}
+
+/// Files beside a song holding its album's cover, in order of preference
+const COVER_FILES: &[&str] = &["cover", "folder", "front", "album"];
+
+/// A picture of a song and its media type, such as `image/jpeg`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cover {
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
+/// The cover of the song at `path`: its embedded front cover, any embedded picture, or a
+/// `cover.jpg` style file in its directory
+pub fn read_cover(path: &Path) -> crate::Result<Option<Cover>> {
+    let mut probed = get_probe().format(
+        &Default::default(),
+        MediaSourceStream::new(Box::new(File::open(path)?), Default::default()),
+        &Default::default(),
+        &Default::default(),
+    )?;
+    let visuals = read_visuals(&mut probed);
+    let embedded = visuals
+        .iter()
+        .find(|v| v.usage == Some(StandardVisualKey::FrontCover))
+        .or(visuals.first());
+    if let Some(visual) = embedded {
+        return Ok(Some(Cover {
+            media_type: visual.media_type.clone(),
+            data: visual.data.to_vec(),
+        }));
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let file = entry?.path();
+        let lowercase = |s: Option<&std::ffi::OsStr>| s.map(|s| s.to_string_lossy().to_lowercase());
+        let media_type = match lowercase(file.extension()).as_deref() {
+            Some("jpg" | "jpeg") => "image/jpeg",
+            Some("png") => "image/png",
+            _ => continue,
+        };
+        let stem = lowercase(file.file_stem()).unwrap_or_default();
+        if let Some(rank) = COVER_FILES.iter().position(|n| *n == stem) {
+            files.push((rank, file, media_type));
+        }
+    }
+    match files.into_iter().min() {
+        Some((_, file, media_type)) => Ok(Some(Cover {
+            media_type: media_type.to_string(),
+            data: std::fs::read(file)?,
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Apply `changes` to the tags of the song at `path`
+pub fn write_tags(path: &Path, changes: &TagChanges) -> crate::Result<()> {
+    let values: TagValues = changes
+        .iter()
+        .map(|(key, value)| (key.clone(), value.iter().cloned().collect()))
+        .collect();
+    write_tag_values(path, &values)
+}
+
+/// Replace every value of each tag in `values` of the song at `path`, no values remove it
+pub fn write_tag_values(path: &Path, values: &TagValues) -> crate::Result<()> {
+    edit_tag(path, |tag| {
+        for (key, values) in values {
+            let item_key = item_key(key, tag.tag_type());
+            tag.remove_key(&item_key);
+            for value in values {
+                // `insert` drops unknown keys, lofty still validates them when saving
+                tag.push_unchecked(TagItem::new(
+                    item_key.clone(),
+                    ItemValue::Text(value.clone()),
+                ));
+            }
+        }
+    })
+}
+
+/// Replace the embedded pictures of the song at `path`
+pub fn write_visuals(path: &Path, visuals: &[Visual]) -> crate::Result<()> {
+    edit_tag(path, |tag| {
+        while !tag.pictures().is_empty() {
+            tag.remove_picture(0);
+        }
+        for visual in visuals {
+            let picture_type = match visual.usage {
+                Some(StandardVisualKey::FrontCover) => PictureType::CoverFront,
+                Some(StandardVisualKey::BackCover) => PictureType::CoverBack,
+                Some(StandardVisualKey::Media) => PictureType::Media,
+                Some(StandardVisualKey::LeadArtistPerformerSoloist) => PictureType::LeadArtist,
+                Some(StandardVisualKey::ArtistPerformer) => PictureType::Artist,
+                _ => PictureType::Other,
+            };
+            tag.push_picture(Picture::new_unchecked(
+                picture_type,
+                Some(MimeType::from_str(&visual.media_type)),
+                None,
+                visual.data.to_vec(),
+            ));
+        }
+    })
+}
+
+fn edit_tag(path: &Path, edit: impl FnOnce(&mut Tag)) -> crate::Result<()> {
+    let mut file = lofty::read_from_path(path)?;
+    let tag_type = file.primary_tag_type();
+    if file.tag(tag_type).is_none() {
+        file.insert_tag(Tag::new(tag_type));
+    }
+    edit(file.tag_mut(tag_type).expect("Tag was just inserted"));
+    file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+fn item_key(key: &str, tag_type: lofty::tag::TagType) -> ItemKey {
+    KEYS.iter()
+        .find(|(std_key, _)| key_name(*std_key) == key)
+        .map(|(_, item_key)| item_key.clone())
+        .unwrap_or_else(|| ItemKey::from_key(tag_type, key))
+}
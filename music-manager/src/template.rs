@@ -0,0 +1,297 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, sanitize::Sanitizer, tags::Tags};
+
+/// The layout sort has always used
+pub const DEFAULT_TEMPLATE: &str = "{artist}/{title}";
+
+/// A path layout such as `{albumartist|artist}/{album}/{track:2} - {title}`.
+/// Components are separated by `/`, `a|b` falls back to `b` when `a` is missing and
+/// `:N` zero-pads numbers to `N` digits. The extension is appended to the last component.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    source: String,
+    components: Vec<Vec<Segment>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Literal(String),
+    Field { fields: Vec<Field>, width: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Artist,
+    AlbumArtist,
+    Album,
+    Title,
+    Track,
+    Disc,
+    Date,
+    Year,
+    Genre,
+    Composer,
+}
+
+impl Field {
+    pub const ALL: &[Field] = &[
+        Self::Artist,
+        Self::AlbumArtist,
+        Self::Album,
+        Self::Title,
+        Self::Track,
+        Self::Disc,
+        Self::Date,
+        Self::Year,
+        Self::Genre,
+        Self::Composer,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Artist => "artist",
+            Self::AlbumArtist => "albumartist",
+            Self::Album => "album",
+            Self::Title => "title",
+            Self::Track => "track",
+            Self::Disc => "disc",
+            Self::Date => "date",
+            Self::Year => "year",
+            Self::Genre => "genre",
+            Self::Composer => "composer",
+        }
+    }
+
+    /// The key of the tag the field is read from
+    pub fn tag(self) -> &'static str {
+        match self {
+            Self::Artist => "Artist",
+            Self::AlbumArtist => "AlbumArtist",
+            Self::Album => "Album",
+            Self::Title => "TrackTitle",
+            Self::Track => "TrackNumber",
+            Self::Disc => "DiscNumber",
+            Self::Date | Self::Year => "Date",
+            Self::Genre => "Genre",
+            Self::Composer => "Composer",
+        }
+    }
+
+    pub fn value(self, tags: &Tags) -> Option<String> {
+        let value = tags.get(self.tag())?.trim();
+        let value = match self {
+            // "3/12" style numbering
+            Self::Track | Self::Disc => value.split('/').next().unwrap_or_default().trim(),
+            Self::Year => value
+                .get(..4)
+                .filter(|y| y.chars().all(|c| c.is_ascii_digit()))?,
+            _ => value,
+        };
+        (!value.is_empty()).then(|| value.to_string())
+    }
+}
+
+impl FromStr for Field {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|f| f.name() == s.trim().to_ascii_lowercase())
+            .copied()
+            .ok_or_else(|| Error::InvalidTemplate(format!("Unknown field '{s}'")))
+    }
+}
+
+impl Template {
+    pub fn components(&self) -> &[Vec<Segment>] {
+        &self.components
+    }
+
+    /// Where a song with `tags` belongs below `prefix`, `None` if a field is missing
+    pub fn render(
+        &self,
+        prefix: &Path,
+        tags: &Tags,
+        extension: &str,
+        sanitizer: &Sanitizer,
+    ) -> Option<PathBuf> {
+        let mut path = prefix.to_path_buf();
+        let (file, dirs) = self
+            .components
+            .split_last()
+            .expect("Templates are non-empty");
+        for component in dirs {
+            path.push(sanitizer.component(&render_component(component, tags)?));
+        }
+        path.push(sanitizer.filename(&render_component(file, tags)?, extension));
+        Some(path)
+    }
+}
+
+fn render_component(segments: &[Segment], tags: &Tags) -> Option<String> {
+    let mut out = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Literal(s) => out.push_str(s),
+            Segment::Field { fields, width } => {
+                let value = fields.iter().find_map(|f| f.value(tags))?;
+                match value.parse::<u64>() {
+                    Ok(n) if *width > 0 => out.push_str(&format!("{n:0width$}")),
+                    _ => out.push_str(&value),
+                }
+            }
+        }
+    }
+    Some(out)
+}
+
+fn parse_component(s: &str) -> crate::Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].to_string()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| Error::InvalidTemplate(format!("Unclosed '{{' in '{s}'")))?
+            + start;
+        let (fields, width) = match rest[start + 1..end].split_once(':') {
+            Some((fields, width)) => (
+                fields,
+                width
+                    .parse()
+                    .map_err(|_| Error::InvalidTemplate(format!("Invalid width '{width}'")))?,
+            ),
+            None => (&rest[start + 1..end], 0),
+        };
+        let fields = fields
+            .split('|')
+            .map(Field::from_str)
+            .collect::<crate::Result<_>>()?;
+        segments.push(Segment::Field { fields, width });
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest.to_string()));
+    }
+    Ok(segments)
+}
+
+impl FromStr for Template {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let components = s
+            .trim_matches('/')
+            .split('/')
+            .map(parse_component)
+            .collect::<crate::Result<Vec<_>>>()?;
+        if components.iter().any(|c| c.is_empty()) {
+            return Err(Error::InvalidTemplate(format!("Empty component in '{s}'")));
+        }
+        Ok(Self {
+            source: s.to_string(),
+            components,
+        })
+    }
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        DEFAULT_TEMPLATE.parse().expect("Default template is valid")
+    }
+}
+
+impl std::fmt::Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = Error;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Template> for String {
+    fn from(value: Template) -> Self {
+        value.source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[(&str, &str)]) -> Tags {
+        tags.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn render(template: &str, tags: &Tags) -> Option<PathBuf> {
+        let template: Template = template.parse().unwrap();
+        template.render(Path::new("lib"), tags, "flac", &Sanitizer::default())
+    }
+
+    #[test]
+    fn fields_fall_back_and_pad() {
+        let song = tags(&[
+            ("Artist", "Artist"),
+            ("Album", "Album"),
+            ("TrackTitle", "Title"),
+            ("TrackNumber", "3/12"),
+            ("Date", "1999-04-01"),
+        ]);
+        assert_eq!(
+            render(
+                "{albumartist|artist}/{year} {album}/{track:2} - {title}",
+                &song
+            ),
+            Some(PathBuf::from("lib/Artist/1999 Album/03 - Title.flac"))
+        );
+        assert_eq!(
+            render(DEFAULT_TEMPLATE, &song),
+            Some(PathBuf::from("lib/Artist/Title.flac"))
+        );
+    }
+
+    #[test]
+    fn missing_fields_render_nothing() {
+        let song = tags(&[("Artist", "Artist"), ("TrackTitle", " ")]);
+        assert_eq!(render("{artist}/{title}", &song), None);
+        assert_eq!(render("{artist}/{album|title}", &song), None);
+        assert_eq!(render("{year}", &tags(&[("Date", "April")])), None);
+    }
+
+    #[test]
+    fn values_are_sanitized_per_component() {
+        let song = tags(&[("Artist", "AC/DC"), ("TrackTitle", "T.N.T.")]);
+        assert_eq!(
+            render("{artist}/{title}", &song),
+            Some(PathBuf::from("lib/AC_DC/T.N.T..flac"))
+        );
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        for template in ["{artist", "{artst}", "{track:x}", "{artist}//{title}", ""] {
+            assert!(
+                matches!(template.parse::<Template>(), Err(Error::InvalidTemplate(_))),
+                "{template}"
+            );
+        }
+        let template: Template = "/{artist}/{title}/".parse().unwrap();
+        assert_eq!(template.components().len(), 2);
+        assert_eq!(template.to_string(), "/{artist}/{title}/");
+    }
+}
@@ -0,0 +1,18 @@
+use std::path::Path;
+
+use crate::{
+    cue::write_flac,
+    tags::{TagChanges, write_tags},
+};
+
+/// Write a short mono FLAC song tagged with `tags` at `path`. Songs written with different
+/// `seed`s have different streams.
+pub fn write_song(path: &Path, seed: i32, tags: &[(&str, &str)]) {
+    let samples: Vec<i32> = (0..4410).map(|i| (i * seed) % 2000 - 1000).collect();
+    write_flac(path, &samples, 1, 16, 44100).expect("The song is written");
+    let changes: TagChanges = tags
+        .iter()
+        .map(|(key, value)| (key.to_string(), Some(value.to_string())))
+        .collect();
+    write_tags(path, &changes).expect("The tags are written");
+}
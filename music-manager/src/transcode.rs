@@ -0,0 +1,391 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use symphonia::{core::io::MediaSourceStream, default::get_probe};
+use tracing::{info, warn};
+
+use crate::{
+    Error,
+    decode::{StreamInfo, decode_samples},
+    sanitize::Sanitizer,
+    tags::{read_tag_values, read_tags, read_visuals, write_tag_values, write_visuals},
+    template::Template,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Opus,
+    Vorbis,
+    Mp3,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Opus => "opus",
+            Self::Vorbis => "ogg",
+            Self::Mp3 => "mp3",
+        }
+    }
+
+    /// The external encoder used when none is configured
+    pub fn default_encoder(self) -> &'static str {
+        match self {
+            Self::Opus => "opusenc",
+            Self::Vorbis => "oggenc",
+            Self::Mp3 => "lame",
+        }
+    }
+
+    /// Arguments making the encoder read WAV from stdin and write to `dest`
+    fn encoder_args(self, bitrate: u32, dest: &Path) -> Vec<std::ffi::OsString> {
+        let bitrate = bitrate.to_string();
+        let args: Vec<&std::ffi::OsStr> = match self {
+            Self::Opus => vec![
+                "--quiet".as_ref(),
+                "--bitrate".as_ref(),
+                bitrate.as_ref(),
+                "-".as_ref(),
+                dest.as_os_str(),
+            ],
+            Self::Vorbis => vec![
+                "--quiet".as_ref(),
+                "--bitrate".as_ref(),
+                bitrate.as_ref(),
+                "--output".as_ref(),
+                dest.as_os_str(),
+                "-".as_ref(),
+            ],
+            Self::Mp3 => vec![
+                "--quiet".as_ref(),
+                "-b".as_ref(),
+                bitrate.as_ref(),
+                "-".as_ref(),
+                dest.as_os_str(),
+            ],
+        };
+        args.into_iter().map(ToOwned::to_owned).collect()
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "opus" => Ok(Self::Opus),
+            "vorbis" | "ogg" => Ok(Self::Vorbis),
+            "mp3" => Ok(Self::Mp3),
+            _ => Err(format!("Unknown transcode format '{s}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Opus => write!(f, "opus"),
+            Self::Vorbis => write!(f, "vorbis"),
+            Self::Mp3 => write!(f, "mp3"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encoder {
+    pub format: Format,
+    /// Target bitrate in kbit/s
+    pub bitrate: u32,
+    /// Encoder binary, [`Format::default_encoder`] when `None`
+    pub program: Option<PathBuf>,
+}
+
+impl Encoder {
+    fn program(&self) -> PathBuf {
+        self.program
+            .clone()
+            .unwrap_or_else(|| self.format.default_encoder().into())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Job {
+    pub src: PathBuf,
+    pub dest: PathBuf,
+}
+
+impl std::fmt::Display for Job {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Transcode '{}' to '{}'",
+            self.src.to_string_lossy(),
+            self.dest.to_string_lossy()
+        )
+    }
+}
+
+/// Plan transcoding `songs` into `dest`, skipping songs whose transcode is up to date and
+/// songs rendering to the target of an earlier one
+pub fn transcode_jobs(
+    dest: &Path,
+    songs: &[impl AsRef<Path> + Sync],
+    format: Format,
+    template: &Template,
+    sanitizer: &Sanitizer,
+) -> crate::Result<Vec<Job>> {
+    let targets = songs
+        .par_iter()
+        .map(|s| s.as_ref())
+        .filter_map(|song| -> Option<crate::Result<(&Path, PathBuf)>> {
+            let mut probed = match File::open(song).map_err(Error::from).and_then(|f| {
+                Ok(get_probe().format(
+                    &Default::default(),
+                    MediaSourceStream::new(Box::new(f), Default::default()),
+                    &Default::default(),
+                    &Default::default(),
+                )?)
+            }) {
+                Ok(probed) => probed,
+                Err(e) => return Some(Err(e)),
+            };
+            let tags = read_tags(&mut probed, false);
+            let Some(target) = template.render(dest, &tags, format.extension(), sanitizer) else {
+                warn!(
+                    "Skipping {}: missing metadata for '{template}'",
+                    song.display()
+                );
+                return None;
+            };
+            Some(Ok((song, target)))
+        })
+        .collect::<crate::Result<Vec<_>>>()?;
+    let mut planned: HashMap<&Path, &Path> = HashMap::new();
+    let mut jobs = Vec::new();
+    for (song, target) in &targets {
+        if let Some(other) = planned.get(target.as_path()) {
+            warn!(
+                "Skipping {}: {} is transcoded to {} too",
+                song.display(),
+                other.display(),
+                target.display()
+            );
+            continue;
+        }
+        planned.insert(target, song);
+        if !is_up_to_date(song, target)? {
+            jobs.push(Job {
+                src: song.to_path_buf(),
+                dest: target.clone(),
+            });
+        }
+    }
+    Ok(jobs)
+}
+
+fn is_up_to_date(src: &Path, dest: &Path) -> std::io::Result<bool> {
+    let Ok(dest_metadata) = dest.metadata() else {
+        return Ok(false);
+    };
+    Ok(dest_metadata.modified()? >= src.metadata()?.modified()?)
+}
+
+/// Run `jobs` in parallel, returning the outcome of each
+pub fn transcode_all<'a>(jobs: &'a [Job], encoder: &Encoder) -> Vec<(&'a Job, crate::Result<()>)> {
+    jobs.par_iter()
+        .map(|job| (job, transcode(job, encoder)))
+        .collect()
+}
+
+/// Transcode a single song, copying its tags and cover art
+pub fn transcode(job: &Job, encoder: &Encoder) -> crate::Result<()> {
+    info!("{job}");
+    let parent = job.dest.parent().expect("Destination has a parent");
+    std::fs::create_dir_all(parent)?;
+    // encode beside the destination so an interrupted run never leaves a stale transcode
+    let partial = parent.join(format!(
+        ".{}",
+        job.dest
+            .file_name()
+            .expect("Destination has a file name")
+            .to_string_lossy()
+    ));
+
+    let result = encode(&job.src, &partial, encoder).and_then(|_| copy_tags(&job.src, &partial));
+    match result {
+        Ok(()) => Ok(std::fs::rename(&partial, &job.dest)?),
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+fn encode(src: &Path, dest: &Path, encoder: &Encoder) -> crate::Result<()> {
+    let program = encoder.program();
+    let mut child = Command::new(&program)
+        .args(encoder.format.encoder_args(encoder.bitrate, dest))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Encoder {
+            program: program.to_string_lossy().to_string(),
+            message: e.to_string(),
+        })?;
+    // read while writing stdin, an encoder filling the pipe would otherwise block both of us
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr = std::thread::spawn(move || {
+        let mut message = Vec::new();
+        let _ = stderr.read_to_end(&mut message);
+        message
+    });
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let mut header_written = false;
+    let mut pcm = Vec::new();
+    let decoded = decode_samples(src, |info, samples| {
+        if !header_written {
+            stdin.write_all(&wav_header(info))?;
+            header_written = true;
+        }
+        pcm.clear();
+        pcm.extend(
+            samples
+                .iter()
+                .flat_map(|s| ((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes()),
+        );
+        stdin.write_all(&pcm)?;
+        Ok(())
+    });
+    drop(stdin);
+    let status = child.wait()?;
+    let stderr = stderr.join().expect("reading stderr does not panic");
+    if !status.success() {
+        return Err(Error::Encoder {
+            program: program.to_string_lossy().to_string(),
+            message: String::from_utf8_lossy(&stderr).trim().to_string(),
+        });
+    }
+    decoded?.ok_or(Error::NoAudio)?;
+    Ok(())
+}
+
+/// Header of a 16 bit PCM WAV stream, with an open ended length if the frame count is unknown
+fn wav_header(info: &StreamInfo) -> Vec<u8> {
+    let channels = info.channels as u16;
+    let block_align = channels * 2;
+    let data_len = info
+        .frames
+        .and_then(|f| u32::try_from(f * block_align as u64).ok())
+        .filter(|len| *len < u32::MAX - 36)
+        .unwrap_or(u32::MAX - 36);
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(data_len + 36).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&info.sample_rate.to_le_bytes());
+    header.extend_from_slice(&(info.sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    header
+}
+
+fn copy_tags(src: &Path, dest: &Path) -> crate::Result<()> {
+    let mut probed = get_probe().format(
+        &Default::default(),
+        MediaSourceStream::new(Box::new(File::open(src)?), Default::default()),
+        &Default::default(),
+        &Default::default(),
+    )?;
+    write_tag_values(dest, &read_tag_values(&mut probed, true))?;
+    let visuals = read_visuals(&mut probed);
+    if !visuals.is_empty() {
+        write_visuals(dest, &visuals)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::{cue::write_flac, test_util::write_song};
+
+    #[test]
+    fn songs_rendering_to_one_target_are_planned_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let songs = [
+            ("a/intro.flac", "Intro"),
+            ("b/intro.flac", "Intro"),
+            ("b/outro.flac", "Outro"),
+        ]
+        .map(|(path, title)| {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            write_song(&path, 1, &[("Artist", "A"), ("TrackTitle", title)]);
+            path
+        });
+        let dest = dir.path().join("transcoded");
+        let template: Template = "{artist}/{title}".parse().unwrap();
+        let plan = || {
+            transcode_jobs(
+                &dest,
+                &songs,
+                Format::Opus,
+                &template,
+                &Sanitizer::default(),
+            )
+            .unwrap()
+        };
+
+        let jobs = plan();
+        let planned: Vec<_> = jobs.iter().map(|j| (&j.src, &j.dest)).collect();
+        let (intro, outro) = (dest.join("A/Intro.opus"), dest.join("A/Outro.opus"));
+        assert_eq!(planned, [(&songs[0], &intro), (&songs[2], &outro)]);
+
+        // an up to date target still belongs to the first song
+        std::fs::create_dir_all(intro.parent().unwrap()).unwrap();
+        std::fs::write(&intro, "").unwrap();
+        let jobs = plan();
+        let planned: Vec<_> = jobs.iter().map(|j| (&j.src, &j.dest)).collect();
+        assert_eq!(planned, [(&songs[2], &outro)]);
+    }
+
+    #[test]
+    fn chatty_failing_encoders_report_their_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("song.flac");
+        // more than a pipe holds, both ways
+        write_flac(&src, &vec![0; 44100 * 4], 2, 16, 44100).unwrap();
+        let program = dir.path().join("encoder");
+        std::fs::write(
+            &program,
+            "#!/bin/sh\nhead -c 200000 /dev/zero | tr '\\0' x >&2\ncat >/dev/null\nexit 1\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let encoder = Encoder {
+            format: Format::Opus,
+            bitrate: 128,
+            program: Some(program),
+        };
+        let result = encode(&src, &dir.path().join("song.opus"), &encoder);
+        assert!(matches!(result, Err(Error::Encoder { message, .. }) if message.len() == 200000));
+    }
+}
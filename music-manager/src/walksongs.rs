@@ -1,43 +1,119 @@
 use std::path::{Path, PathBuf};
 
+use serde::{Deserialize, Serialize};
 use tracing::debug;
 
+/// How the library is walked for songs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WalkOptions {
+    /// Walk hidden files and directories
+    pub hidden: bool,
+    /// Walk into symlinked directories
+    pub follow_symlinks: bool,
+    /// Names of files and directories to skip, `*` matching any run of characters
+    pub exclude: Vec<String>,
+    /// Extensions songs are recognized by, the supported formats if empty
+    pub extensions: Vec<String>,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            hidden: true,
+            follow_symlinks: true,
+            exclude: Vec::new(),
+            extensions: Vec::new(),
+        }
+    }
+}
+
+/// Every song below `root`, or `root` itself when it is a song
 pub fn get_songs(root: PathBuf) -> crate::Result<Vec<PathBuf>> {
+    walk_songs(root, &WalkOptions::default())
+}
+
+/// Like [`get_songs`], walking as `options` say
+pub fn walk_songs(root: PathBuf, options: &WalkOptions) -> crate::Result<Vec<PathBuf>> {
     let mut songs = Vec::new();
-    add_dir(root, &mut songs)?;
+    if root.is_file() {
+        add_file(root, options, &mut songs)?;
+    } else {
+        add_dir(root, options, &mut songs)?;
+    }
     Ok(songs)
 }
 
-fn add_path(path: PathBuf, song_map: &mut Vec<PathBuf>) -> Result<(), crate::Error> {
+fn add_path(
+    path: PathBuf,
+    options: &WalkOptions,
+    song_map: &mut Vec<PathBuf>,
+) -> Result<(), crate::Error> {
+    if is_skipped(&path, options) {
+        debug!("Skipping {}", path.to_string_lossy());
+        return Ok(());
+    }
     if path.is_file() {
-        add_file(path, song_map)?;
-    } else if path.is_dir() {
-        add_dir(path, song_map)?;
+        add_file(path, options, song_map)?;
+    } else if path.is_dir() && (options.follow_symlinks || !path.is_symlink()) {
+        add_dir(path, options, song_map)?;
     }
     Ok(())
 }
 
-fn add_dir(path: PathBuf, songs: &mut Vec<PathBuf>) -> crate::Result<()> {
+fn add_dir(path: PathBuf, options: &WalkOptions, songs: &mut Vec<PathBuf>) -> crate::Result<()> {
     for child in path.read_dir()? {
         let child = child?;
-        add_path(child.path(), songs)?
+        add_path(child.path(), options, songs)?
     }
     Ok(())
 }
 
-fn add_file(path: PathBuf, songs: &mut Vec<PathBuf>) -> Result<(), crate::Error> {
-    if !is_song(&path) {
+fn add_file(
+    path: PathBuf,
+    options: &WalkOptions,
+    songs: &mut Vec<PathBuf>,
+) -> Result<(), crate::Error> {
+    if !is_song(&path, options) {
         return Ok(());
     }
     songs.push(path);
     Ok(())
 }
 
+fn is_skipped(path: &Path, options: &WalkOptions) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    let name = name.to_string_lossy();
+    (!options.hidden && name.starts_with('.'))
+        || options.exclude.iter().any(|p| glob_matches(p, &name))
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| glob_matches(rest, &name[i..]))
+        }
+    }
+}
+
 const EXTENTIONS: &[&str] = &["mp1", "mp2", "mp3", "ogg", "opus", "flac", "aac"];
-fn is_song(path: &Path) -> bool {
+fn is_song(path: &Path, options: &WalkOptions) -> bool {
     if let Some(extension) = path.extension() {
         let extension = extension.to_string_lossy();
-        if !EXTENTIONS.contains(&extension.as_ref()) {
+        let supported = match options.extensions.is_empty() {
+            true => EXTENTIONS.contains(&extension.as_ref()),
+            false => options.extensions.iter().any(|e| *e == extension),
+        };
+        if !supported {
             debug!("Skipping unsupported extention {}", extension);
             return false;
         }
@@ -46,3 +122,64 @@ fn is_song(path: &Path) -> bool {
     };
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stars_match_any_run_of_characters() {
+        assert!(glob_matches("Thumbs.db", "Thumbs.db"));
+        assert!(!glob_matches("Thumbs.db", "thumbs.db"));
+        assert!(glob_matches("*.tmp", ".tmp"));
+        assert!(glob_matches("*.tmp", "song.flac.tmp"));
+        assert!(!glob_matches("*.tmp", "song.tmp.flac"));
+        assert!(glob_matches("Disc *", "Disc 1"));
+        assert!(glob_matches("a*b*c", "abbbc"));
+        assert!(!glob_matches("a*b*c", "acb"));
+        assert!(glob_matches("*ö*", "Motörhead"));
+        assert!(glob_matches("*", ""));
+    }
+
+    #[test]
+    fn walking_skips_excluded_hidden_and_unknown_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "a.flac",
+            "b.txt",
+            ".c.flac",
+            "incoming/d.mp3",
+            "e.wav",
+            "f.flac.part",
+        ] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, []).unwrap();
+        }
+        let walk = |options: &WalkOptions| -> Vec<String> {
+            let mut songs: Vec<String> = walk_songs(dir.path().to_path_buf(), options)
+                .unwrap()
+                .iter()
+                .map(|s| {
+                    s.strip_prefix(dir.path())
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect();
+            songs.sort();
+            songs
+        };
+        assert_eq!(
+            walk(&WalkOptions::default()),
+            [".c.flac", "a.flac", "incoming/d.mp3"]
+        );
+        let options = WalkOptions {
+            hidden: false,
+            exclude: vec!["incom*".to_string()],
+            extensions: vec!["flac".to_string(), "wav".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(walk(&options), ["a.flac", "e.wav"]);
+    }
+}
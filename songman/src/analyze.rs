@@ -0,0 +1,13 @@
+use music_manager::analysis::analyze_songs;
+
+use crate::{cli, config::get_songs, output::Output};
+
+pub fn show_analysis(args: cli::Analyze, out: &Output) -> anyhow::Result<()> {
+    let songs = get_songs(args.root.clone())?;
+    let mut report = analyze_songs(&songs, args.lossless_check)?;
+    if args.problems {
+        report.songs.retain(|s| !s.problems.is_empty());
+    }
+    out.document(&report)?;
+    Ok(())
+}
@@ -1,6 +1,22 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf};
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use music_manager::{
+    cue::DEFAULT_SPLIT_TEMPLATE,
+    filter::Filter,
+    import::DuplicateAction,
+    lint::Severity,
+    loudness::GainTags,
+    normalize::{Language, Transform},
+    sanitize::{Profile, Sanitizer},
+    sheet::SheetFormat,
+    sort::Mode,
+    sync::parse_size,
+    template::{DEFAULT_TEMPLATE, Template},
+    transcode::Format,
+};
+
+use crate::output;
 
 #[derive(Parser, Debug, Clone)]
 pub struct Cli {
@@ -8,10 +24,23 @@ pub struct Cli {
     #[arg(short, long, default_value_t = tracing::Level::INFO)]
     pub log_level: tracing::Level,
 
-    /// Output json
+    /// Output format { table, toml, json, ndjson, csv, yaml }, defaults to tables or TOML
+    /// documents and a line per item
+    #[arg(short, long, conflicts_with_all = ["json", "ndjson"])]
+    pub format: Option<output::Format>,
+
+    /// Output a versioned JSON document, short for `--format json`
     #[arg(short, long)]
     pub json: bool,
 
+    /// Output a JSON record per line as results are known, short for `--format ndjson`
+    #[arg(long, conflicts_with = "json")]
+    pub ndjson: bool,
+
+    /// Config profile to apply, defaults to the `profile` key of the config files
+    #[arg(short = 'P', long = "profile", global = true)]
+    pub config_profile: Option<String>,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -23,26 +52,45 @@ pub enum Command {
     Stats(Stats),
     Sort(Sort),
     Hash(Hash),
+    Transcode(Transcode),
+    Sync(Sync),
+    Loudness(Loudness),
+    Analyze(Analyze),
+    Lint(Lint),
+    Ls(Ls),
+    Tag(Tag),
+    Match(Match),
+    Cue(Cue),
+    Lyrics(Lyrics),
+    Config(Config),
+    Import(ImportFolder),
+    Serve(Serve),
+    Schema(Schema),
 }
 
 #[derive(Parser, Debug, Clone)]
 /// Detect duplicate music files
 pub struct DetectDupe {
-    /// Detect duplicate title metadata
+    /// Detect duplicate title metadata, comparing each track of a CUE image on its own
     #[arg(short, long)]
     pub metadata: bool,
 
-    /// Detect duplicate file names
+    /// Detect duplicate file names. CUE images are compared as a whole.
     #[arg(short, long)]
     pub filename: bool,
 
-    /// Detect duplicate music streams
+    /// Detect duplicate music streams. CUE images are compared as a whole, so their tracks
+    /// are not found among split copies.
     #[arg(short, long)]
     pub stream: bool,
 
-    /// Root music directory
-    #[arg()]
-    pub root: PathBuf,
+    /// Only show duplicates found in more than one root
+    #[arg(short, long)]
+    pub across: bool,
+
+    /// Root music directories, or the names of configured roots
+    #[arg(required = true)]
+    pub roots: Vec<PathBuf>,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -68,7 +116,7 @@ pub struct Stats {
     #[arg(short, long)]
     pub tagged: bool,
 
-    /// Show untagged songs
+    /// Show untagged songs and the required tags they lack
     #[arg(short = 'T', long)]
     pub untagged: bool,
 
@@ -80,9 +128,44 @@ pub struct Stats {
     #[arg(short = 'S', long)]
     pub unsorted: bool,
 
-    /// Root music directory
-    #[arg()]
-    pub root: PathBuf,
+    /// Show albums
+    #[arg(long)]
+    pub albums: bool,
+
+    /// Show albums missing tracks or track numbers
+    #[arg(short, long)]
+    pub incomplete: bool,
+
+    /// Show albums with inconsistent gain tags
+    #[arg(short = 'g', long)]
+    pub inconsistent_gain: bool,
+
+    /// Show songs with lyrics
+    #[arg(long)]
+    pub lyrics: bool,
+
+    /// Show songs without lyrics
+    #[arg(long)]
+    pub no_lyrics: bool,
+
+    /// Decode songs to count audio problems
+    #[arg(short = 'A', long)]
+    pub analyze: bool,
+
+    /// Show songs with audio problems, implies --analyze
+    #[arg(short, long)]
+    pub quality: bool,
+
+    /// Path template songs are sorted by
+    #[arg(long, default_value = DEFAULT_TEMPLATE)]
+    pub template: Template,
+
+    #[command(flatten)]
+    pub sanitize: Sanitize,
+
+    /// Root music directories, or the names of configured roots
+    #[arg(required = true)]
+    pub roots: Vec<PathBuf>,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -92,9 +175,28 @@ pub struct Sort {
     #[arg(long)]
     pub apply: bool,
 
-    /// Root music directory
-    #[arg()]
-    pub root: PathBuf,
+    /// How songs are placed in the destination { move, copy, hardlink, symlink, reflink }
+    #[arg(short, long, default_value_t = Mode::Move)]
+    pub mode: Mode,
+
+    /// Destination directory, defaults to sorting each root within itself
+    #[arg(short, long)]
+    pub dest: Option<PathBuf>,
+
+    /// Path template songs are sorted by
+    #[arg(long, default_value = DEFAULT_TEMPLATE)]
+    pub template: Template,
+
+    #[command(flatten)]
+    pub sanitize: Sanitize,
+
+    /// Skip songs whose destination already exists instead of failing
+    #[arg(long)]
+    pub skip_existing: bool,
+
+    /// Root music directories, or the names of configured roots
+    #[arg(required = true)]
+    pub roots: Vec<PathBuf>,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -104,3 +206,478 @@ pub struct Hash {
     #[arg()]
     pub song: PathBuf,
 }
+
+#[derive(Parser, Debug, Clone)]
+/// Transcode the library into a portable mirror
+pub struct Transcode {
+    /// Target format { opus, vorbis, mp3 }
+    #[arg(short, long, default_value_t = Format::Opus)]
+    pub format: Format,
+
+    /// Target bitrate in kbit/s
+    #[arg(short, long, default_value_t = 128)]
+    pub bitrate: u32,
+
+    /// Encoder program, defaults to opusenc, oggenc or lame
+    #[arg(short, long)]
+    pub encoder: Option<PathBuf>,
+
+    /// Path template of the mirror
+    #[arg(long, default_value = DEFAULT_TEMPLATE)]
+    pub template: Template,
+
+    #[command(flatten)]
+    pub sanitize: Sanitize,
+
+    /// Root music directory
+    #[arg()]
+    pub root: PathBuf,
+
+    /// Directory to mirror the library into
+    #[arg()]
+    pub dest: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Sync a filtered selection of the library to a device directory
+pub struct Sync {
+    /// Apply the sync POTENTIAL LOSS OF DATA MAY OCCUR
+    #[arg(long)]
+    pub apply: bool,
+
+    /// Only sync songs matching a filter such as "genre~rock and year>=1990"
+    #[arg(short, long = "where")]
+    pub filter: Option<Filter>,
+
+    /// Maximum total size of the selection, such as 32G
+    #[arg(long, value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Path template on the device
+    #[arg(long, default_value = DEFAULT_TEMPLATE)]
+    pub template: Template,
+
+    /// Filename sanitization profile { posix, windows, ascii }
+    #[arg(long = "sanitize", default_value_t = Profile::Windows)]
+    pub profile: Profile,
+
+    /// Maximum length of a path component in bytes
+    #[arg(long, default_value_t = 255)]
+    pub max_length: usize,
+
+    /// Root music directory
+    #[arg()]
+    pub root: PathBuf,
+
+    /// Device directory
+    #[arg()]
+    pub dest: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Measure the loudness of songs and albums
+pub struct Loudness {
+    /// Write gain tags { replaygain, r128 }
+    #[arg(short, long)]
+    pub write: Option<GainTags>,
+
+    /// Apply the tag writes
+    #[arg(long)]
+    pub apply: bool,
+
+    /// Root music directory
+    #[arg()]
+    pub root: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Find silence, clipping and other audio problems
+pub struct Analyze {
+    /// Only show songs with problems
+    #[arg(short, long)]
+    pub problems: bool,
+
+    /// Check the spectrum of lossless songs for signs of a lossy source
+    #[arg(short, long)]
+    pub lossless_check: bool,
+
+    /// Root music directory
+    #[arg()]
+    pub root: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Check songs and albums against the library's lint rules
+pub struct Lint {
+    /// Only run these rules
+    #[arg(short, long = "rule")]
+    pub rules: Vec<String>,
+
+    /// Hide diagnostics below this severity { info, warning, error }
+    #[arg(short, long, default_value_t = Severity::Info)]
+    pub severity: Severity,
+
+    /// Show the tag writes fixing the diagnostics
+    #[arg(long)]
+    pub fix: bool,
+
+    /// Apply the fixes, implies --fix
+    #[arg(long)]
+    pub apply: bool,
+    /// Root music directories, or the names of configured roots
+    #[arg(required = true)]
+    pub roots: Vec<PathBuf>,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Import a drop folder into the library, quarantining songs failing its checks
+pub struct ImportFolder {
+    /// Apply the import POTENTIAL LOSS OF DATA MAY OCCUR
+    #[arg(long)]
+    pub apply: bool,
+
+    /// How songs are placed in the library and quarantine { move, copy, hardlink, symlink, reflink }
+    #[arg(short, long, default_value_t = Mode::Move)]
+    pub mode: Mode,
+
+    /// What happens to songs already in the library { skip, quarantine, flag }
+    #[arg(short, long, default_value_t = DuplicateAction::Skip)]
+    pub duplicates: DuplicateAction,
+
+    /// Quarantine directory, defaults to `quarantine` in the incoming folder
+    #[arg(short, long)]
+    pub quarantine: Option<PathBuf>,
+
+    /// Library to import into, a path or the name of a configured root, defaults to the configured root
+    #[arg(short, long)]
+    pub into: Option<PathBuf>,
+
+    /// Path template songs are sorted by
+    #[arg(long, default_value = DEFAULT_TEMPLATE)]
+    pub template: Template,
+
+    #[command(flatten)]
+    pub sanitize: Sanitize,
+
+    /// Incoming folder
+    #[arg()]
+    pub incoming: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Serve the library as a JSON API and a Subsonic API subset under /rest, streaming songs
+/// to HTTP clients
+pub struct Serve {
+    /// Address to listen on. On addresses other than loopback the JSON API needs a login
+    /// of `[serve.users]` and sort plans are not applied.
+    #[arg(short, long, default_value = "127.0.0.1:8080")]
+    pub bind: SocketAddr,
+
+    /// Host names the server is also reached by, such as `nas.local`. Requests naming
+    /// any other host than the address listened on are refused.
+    #[arg(long = "allow-host")]
+    pub allowed_hosts: Vec<String>,
+
+    /// How songs are placed by applied sort plans { move, copy, hardlink, symlink, reflink }
+    #[arg(short, long, default_value_t = Mode::Move)]
+    pub mode: Mode,
+
+    /// Path template songs are sorted by
+    #[arg(long, default_value = DEFAULT_TEMPLATE)]
+    pub template: Template,
+
+    #[command(flatten)]
+    pub sanitize: Sanitize,
+
+    /// Root music directories, or the names of configured roots
+    #[arg(required = true)]
+    pub roots: Vec<PathBuf>,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Print the JSON Schema of a command's JSON output
+pub struct Schema {
+    /// Describe the NDJSON records instead of the JSON document
+    #[arg(long)]
+    pub ndjson: bool,
+
+    /// The command, such as `stats` or `tag normalize`
+    #[arg(num_args = 1.., required = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// List albums with their tracks
+pub struct Ls {
+    /// Only show albums with missing tracks
+    #[arg(short, long)]
+    pub incomplete: bool,
+
+    /// Root music directories, or the names of configured roots
+    #[arg(required = true)]
+    pub roots: Vec<PathBuf>,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Match albums against a local MusicBrainz JSON dump
+pub struct Match {
+    /// MusicBrainz JSON dump, a release per line or a JSON array of releases
+    #[arg(short, long)]
+    pub database: PathBuf,
+
+    /// Only tag albums at most this distance from their release, from 0 to 1
+    #[arg(short, long, default_value_t = 0.3)]
+    pub max_distance: f64,
+
+    /// Show the canonical tags written to close matches
+    #[arg(short, long)]
+    pub write: bool,
+
+    /// Write the canonical tags, implies --write
+    #[arg(long)]
+    pub apply: bool,
+
+    /// Root music directory
+    #[arg()]
+    pub root: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Work with CUE sheets
+pub struct Cue {
+    #[command(subcommand)]
+    pub command: CueCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum CueCommand {
+    Split(Split),
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Split a lossless CUE image into a FLAC file per track, tagged from the sheet
+pub struct Split {
+    /// Apply the split
+    #[arg(long)]
+    pub apply: bool,
+
+    /// Destination directory, defaults to the image's directory
+    #[arg(short, long)]
+    pub dest: Option<PathBuf>,
+
+    /// Path template of the tracks below the destination
+    #[arg(long, default_value = DEFAULT_SPLIT_TEMPLATE)]
+    pub template: Template,
+
+    #[command(flatten)]
+    pub sanitize: Sanitize,
+
+    /// The image or its CUE sheet
+    #[arg()]
+    pub path: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Move lyrics between tags and `.lrc` files
+pub struct Lyrics {
+    #[command(subcommand)]
+    pub command: LyricsCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum LyricsCommand {
+    Embed(Embed),
+    Extract(Extract),
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Embed the `.lrc` file beside each song into its tags
+pub struct Embed {
+    /// Replace lyrics already embedded
+    #[arg(short, long)]
+    pub overwrite: bool,
+
+    /// Apply the tag writes
+    #[arg(long)]
+    pub apply: bool,
+
+    /// Root music directory
+    #[arg()]
+    pub root: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Write embedded lyrics to an `.lrc` file beside each song
+pub struct Extract {
+    /// Replace existing `.lrc` files
+    #[arg(short, long)]
+    pub overwrite: bool,
+
+    /// Write the files
+    #[arg(long)]
+    pub apply: bool,
+
+    /// Root music directory
+    #[arg()]
+    pub root: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Inspect the configuration read from /etc/songman/config.toml,
+/// $XDG_CONFIG_HOME/songman/config.toml and <root>/.songman.toml
+pub struct Config {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    Show(Show),
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Show the effective configuration and where each value comes from
+pub struct Show {
+    /// Library root whose config file applies
+    #[arg()]
+    pub root: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Read and write song tags
+pub struct Tag {
+    #[command(subcommand)]
+    pub command: TagCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TagCommand {
+    Infer(Infer),
+    Export(Export),
+    Import(Import),
+    Edit(Edit),
+    Normalize(Normalize),
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Infer tags from file paths
+pub struct Infer {
+    /// Path pattern matched against the end of each song's path, such as
+    /// `{artist}/{album}/{track} - {title}`. Patterns are tried in order
+    #[arg(short, long = "pattern", required = true)]
+    pub patterns: Vec<Template>,
+
+    /// Replace tags songs already have
+    #[arg(short, long)]
+    pub overwrite: bool,
+
+    /// Write the inferred tags
+    #[arg(long)]
+    pub apply: bool,
+
+    /// Root music directory
+    #[arg()]
+    pub root: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Export tags to a sheet for bulk editing
+pub struct Export {
+    /// Only export songs matching a filter such as "genre~rock and year>=1990"
+    #[arg(short, long)]
+    pub filter: Option<Filter>,
+
+    /// Sheet format { csv, tsv, json }, defaults to the output's extension or csv
+    #[arg(short = 'F', long)]
+    pub format: Option<SheetFormat>,
+
+    /// File to write the sheet to instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Root music directory
+    #[arg()]
+    pub root: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Import tags from an edited sheet, showing the changes
+pub struct Import {
+    /// Sheet format { csv, tsv, json }, defaults to the sheet's extension or csv
+    #[arg(short = 'F', long)]
+    pub format: Option<SheetFormat>,
+
+    /// Write the changes, all songs are updated or none are
+    #[arg(long)]
+    pub apply: bool,
+
+    /// Sheet written by `tag export`
+    #[arg()]
+    pub sheet: PathBuf,
+
+    /// Root music directory
+    #[arg()]
+    pub root: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Edit the tags of songs as TOML in $VISUAL or $EDITOR
+pub struct Edit {
+    /// Songs or directories of songs to edit
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Parser, Debug, Clone)]
+/// Clean up tags with a chain of transforms, showing the changes
+pub struct Normalize {
+    /// Transforms applied in order { trim, nfc, strip-track-number, feat, split-artists,
+    /// title-case }, defaults to all of them in that order
+    #[arg(short, long, value_delimiter = ',')]
+    pub rules: Vec<Transform>,
+
+    /// Language of title case small words for songs without a language tag
+    /// { en, de, fr, es, it }
+    #[arg(short = 'L', long, default_value_t = Language::English)]
+    pub language: Language,
+
+    /// Write the changes, all songs are updated or none are
+    #[arg(long)]
+    pub apply: bool,
+
+    /// Root music directory
+    #[arg()]
+    pub root: PathBuf,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct Sanitize {
+    /// Filename sanitization profile { posix, windows, ascii }
+    #[arg(long = "sanitize", default_value_t = Profile::Posix)]
+    pub profile: Profile,
+
+    /// Maximum length of a path component in bytes
+    #[arg(long, default_value_t = 255)]
+    pub max_length: usize,
+}
+
+impl Cli {
+    pub fn format(&self) -> output::Format {
+        match (self.format, self.json, self.ndjson) {
+            (Some(format), ..) => format,
+            (None, true, _) => output::Format::Json,
+            (None, _, true) => output::Format::Ndjson,
+            (None, false, false) => output::Format::Text,
+        }
+    }
+}
+
+impl From<Sanitize> for Sanitizer {
+    fn from(value: Sanitize) -> Self {
+        Self {
+            profile: value.profile,
+            max_length: value.max_length,
+            ..Default::default()
+        }
+    }
+}
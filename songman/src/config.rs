@@ -0,0 +1,218 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use music_manager::{
+    config::{DEFAULT_ORIGIN, LayeredConfig, LibraryConfig},
+    roots::LibraryRoot,
+    walk_songs,
+};
+
+use crate::{
+    cli::{self, Cli},
+    output::Output,
+};
+
+static CONFIG: OnceLock<LayeredConfig> = OnceLock::new();
+
+/// The configuration of this run, loaded by [`parse`]
+pub fn config() -> &'static LibraryConfig {
+    &layered().config
+}
+
+fn layered() -> &'static LayeredConfig {
+    CONFIG
+        .get()
+        .expect("The config is loaded before commands run")
+}
+
+/// Load the config unit tests share, once: the defaults walking WAV songs too, with the
+/// user `alice` whose password is `sesame`
+#[cfg(test)]
+pub fn load_test_config() {
+    CONFIG.get_or_init(|| {
+        let dir = tempfile::tempdir().unwrap();
+        let config = "[walk]\nextensions = [\"flac\", \"wav\"]\n\n\
+                      [serve.users]\nalice = \"sesame\"\n";
+        std::fs::write(dir.path().join(music_manager::config::CONFIG_FILE), config).unwrap();
+        LayeredConfig::load(Some(dir.path()), None).unwrap()
+    });
+}
+
+/// Every song below `root`, walking the library as configured
+pub fn get_songs(root: PathBuf) -> music_manager::Result<Vec<PathBuf>> {
+    walk_songs(root, &config().walk)
+}
+
+/// The roots of the command line, by name or path
+pub fn roots(args: &[PathBuf]) -> Vec<LibraryRoot> {
+    let mut roots: Vec<LibraryRoot> = Vec::new();
+    for root in args
+        .iter()
+        .map(|a| LibraryRoot::resolve(a, &config().roots))
+    {
+        if !roots.contains(&root) {
+            roots.push(root);
+        }
+    }
+    roots
+}
+
+/// Every song below any of `roots`, each once even if roots are nested
+pub fn get_root_songs(roots: &[LibraryRoot]) -> music_manager::Result<Vec<PathBuf>> {
+    let mut songs = Vec::new();
+    for root in roots {
+        songs.append(&mut get_songs(root.path.clone())?);
+    }
+    songs.sort_unstable();
+    songs.dedup();
+    Ok(songs)
+}
+
+/// Parse the command line, the config files providing the defaults of flags not given.
+/// The name of the subcommand, such as `tag normalize`, comes with it.
+pub fn parse() -> anyhow::Result<(Cli, String)> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    // a lenient first pass finds the profile and the root whose config file applies
+    let partial = Cli::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+        .ok();
+    let profile = partial
+        .as_ref()
+        .and_then(|m| m.get_one::<String>("config_profile").cloned());
+    let root = partial.as_ref().and_then(root_arg);
+    let mut layered = LayeredConfig::load(root.as_deref(), profile.as_deref())?;
+    // a root given by name has its config file read once the names are known
+    if let Some(root) = &root {
+        let resolved = LibraryRoot::resolve(root, &layered.config.roots).path;
+        if resolved != *root {
+            layered = LayeredConfig::load(Some(&resolved), profile.as_deref())?;
+        }
+    }
+    let matches = with_defaults(Cli::command(), &layered).get_matches_from(&args);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    CONFIG
+        .set(layered)
+        .expect("The command line is parsed once");
+    Ok((cli, command_name(&matches)))
+}
+
+fn command_name(matches: &ArgMatches) -> String {
+    let mut names = Vec::new();
+    let mut matches = matches;
+    while let Some((name, sub)) = matches.subcommand() {
+        names.push(name);
+        matches = sub;
+    }
+    names.join(" ")
+}
+
+fn root_arg(matches: &ArgMatches) -> Option<PathBuf> {
+    match matches.subcommand() {
+        Some((_, sub)) => root_arg(sub),
+        None => matches
+            .try_get_one::<PathBuf>("root")
+            .ok()
+            .flatten()
+            .or_else(|| matches.try_get_many::<PathBuf>("roots").ok()??.next())
+            .cloned(),
+    }
+}
+
+/// Replace the built-in defaults of flags with the configured values. Flags with a default
+/// of their own, such as the Windows sanitization of `sync`, keep it.
+fn with_defaults(cmd: clap::Command, layered: &LayeredConfig) -> clap::Command {
+    let (config, builtin) = (&layered.config, LibraryConfig::default());
+    let overrides: Vec<(&str, String, String)> = [
+        (
+            "log_level",
+            "log_level",
+            builtin.log_level,
+            config.log_level.clone(),
+        ),
+        (
+            "template",
+            "template",
+            builtin.template.to_string(),
+            config.template.to_string(),
+        ),
+        (
+            "profile",
+            "sanitize",
+            builtin.sanitize.to_string(),
+            config.sanitize.to_string(),
+        ),
+        (
+            "max_length",
+            "max_length",
+            builtin.max_length.to_string(),
+            config.max_length.to_string(),
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, key, ..)| layered.origin(key) != DEFAULT_ORIGIN)
+    .map(|(id, _, builtin, value)| (id, builtin, value))
+    .collect();
+    override_defaults(cmd, &overrides, config.root.as_deref())
+}
+
+fn override_defaults(
+    mut cmd: clap::Command,
+    overrides: &[(&str, String, String)],
+    root: Option<&Path>,
+) -> clap::Command {
+    for (id, builtin, value) in overrides {
+        let has_builtin = cmd.get_arguments().any(|a| {
+            a.get_id() == id
+                && a.get_default_values()
+                    .iter()
+                    .any(|d| d.to_string_lossy().eq_ignore_ascii_case(builtin))
+        });
+        if has_builtin {
+            cmd = cmd.mut_arg(*id, |a| a.default_value(value.clone()));
+        }
+    }
+    // an optional root can only come last
+    let last_root = cmd
+        .get_positionals()
+        .last()
+        .map(|a| a.get_id().to_string())
+        .filter(|id| id == "root" || id == "roots");
+    if let Some(root) = root
+        && let Some(id) = last_root
+    {
+        let root = root.to_string_lossy().into_owned();
+        cmd = cmd.mut_arg(id, |a| a.required(false).default_value(root));
+    }
+    let names: Vec<String> = cmd
+        .get_subcommands()
+        .map(|s| s.get_name().to_string())
+        .collect();
+    for name in names {
+        cmd = cmd.mut_subcommand(name, |s| override_defaults(s, overrides, root));
+    }
+    cmd
+}
+
+pub fn config_command(args: cli::Config, out: &Output) -> anyhow::Result<()> {
+    match args.command {
+        cli::ConfigCommand::Show(_) => show(out),
+    }
+}
+
+fn show(out: &Output) -> anyhow::Result<()> {
+    let layered = layered();
+    let settings = layered.settings();
+    if let Some(profile) = &layered.profile {
+        out.status(format!("# profile {profile}"));
+    }
+    out.section("settings");
+    for setting in &settings {
+        out.item("settings", setting)?;
+    }
+    Ok(())
+}
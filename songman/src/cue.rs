@@ -0,0 +1,52 @@
+use music_manager::{
+    cue::{find_cue, find_image, read_cue, split, split_tracks},
+    tags::read_file_tags,
+};
+
+use crate::{cli, output::Output};
+
+pub fn cue(args: cli::Cue, out: &Output) -> anyhow::Result<()> {
+    match args.command {
+        cli::CueCommand::Split(s) => split_image(s, out),
+    }
+}
+
+fn split_image(args: cli::Split, out: &Output) -> anyhow::Result<()> {
+    let is_cue = args
+        .path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("cue"));
+    let (image, sheet) = if is_cue {
+        let sheet = read_cue(&args.path)?;
+        let Some(image) = find_image(&args.path, &sheet) else {
+            anyhow::bail!("No image of {} found", args.path.to_string_lossy());
+        };
+        (image, sheet)
+    } else {
+        let Some((_, sheet)) = find_cue(&args.path) else {
+            anyhow::bail!("No CUE sheet splits {}", args.path.to_string_lossy());
+        };
+        (args.path.clone(), sheet)
+    };
+    let dest = match &args.dest {
+        Some(dest) => dest.clone(),
+        None => image.parent().unwrap_or(".".as_ref()).to_path_buf(),
+    };
+    let tracks = split_tracks(
+        &image,
+        &sheet,
+        &read_file_tags(&image, false)?,
+        &dest,
+        &args.template,
+        &args.sanitize.into(),
+    )?;
+    out.section("tracks");
+    for track in &tracks {
+        out.item("tracks", track)?;
+    }
+    if args.apply {
+        split(&tracks)?;
+        out.status("Success");
+    }
+    Ok(())
+}
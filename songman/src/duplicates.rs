@@ -1,22 +1,39 @@
-use music_manager::get_songs;
+use music_manager::config::DuplicatePolicy;
 
-pub fn show_duplicates(args: crate::cli::DetectDupe, json: bool) -> anyhow::Result<()> {
-    if !args.metadata && !args.filename && !args.stream {
+use crate::{
+    config::{config, get_root_songs, roots},
+    output::Output,
+};
+
+pub fn show_duplicates(args: crate::cli::DetectDupe, out: &Output) -> anyhow::Result<()> {
+    let mut policy = DuplicatePolicy {
+        metadata: args.metadata,
+        filename: args.filename,
+        stream: args.stream,
+    };
+    if policy.is_empty() {
+        policy = config().duplicates;
+    }
+    if policy.is_empty() {
         anyhow::bail!(
             "Please supply one of either --metadata, --filename, or --stream".to_string()
         );
     }
-    let songs = get_songs(args.root)?;
-    let duplicates = music_manager::duplicates::detect_duplicates(
+    let roots = roots(&args.roots);
+    let songs = get_root_songs(&roots)?;
+    let mut duplicates = music_manager::duplicates::detect_duplicates(
         &songs,
-        args.metadata,
-        args.filename,
-        args.stream,
+        policy.metadata,
+        policy.filename,
+        policy.stream,
     )?;
-    if json {
-        println!("{}", serde_json::to_string_pretty(&duplicates).unwrap());
-    } else {
-        println!("{}", toml::to_string_pretty(&duplicates).unwrap());
+    if args.across {
+        duplicates.retain_across(&roots);
+    }
+    // songs are tagged with their root once there are several
+    match roots.len() > 1 {
+        true => out.table(&duplicates.rooted(&roots))?,
+        false => out.table(&duplicates)?,
     }
     Ok(())
 }
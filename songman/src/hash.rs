@@ -1,16 +1,9 @@
 use music_manager::duplicates::hash_stream;
 
-use crate::cli;
+use crate::{cli, output::Output};
 
-pub fn show_hash(args: cli::Hash, json: bool) -> anyhow::Result<()> {
+pub fn show_hash(args: cli::Hash, out: &Output) -> anyhow::Result<()> {
     let hash = hash_stream(&args.song)?.expect("hash_stream never exits with None");
-    if json {
-        println!(
-            "{}",
-            serde_json::to_string_pretty(&hash.to_string()).unwrap()
-        );
-    } else {
-        println!("{hash}");
-    }
+    out.value("hash", &hash.to_string())?;
     Ok(())
 }
@@ -0,0 +1,41 @@
+use music_manager::{
+    import::{ImportOptions, plan_import},
+    roots::LibraryRoot,
+};
+
+use crate::{
+    cli,
+    config::{config, get_songs},
+    output::Output,
+    sort::run_transactions,
+};
+
+pub fn import(args: cli::ImportFolder, out: &Output) -> anyhow::Result<()> {
+    let config = config();
+    let Some(into) = args.into.as_ref().or(config.root.as_ref()) else {
+        anyhow::bail!("Please supply the library to import into with --into");
+    };
+    let library = LibraryRoot::resolve(into, &config.roots).path;
+    let incoming = LibraryRoot::resolve(&args.incoming, &config.roots).path;
+    let quarantine = args
+        .quarantine
+        .clone()
+        .unwrap_or_else(|| incoming.join("quarantine"));
+    let songs = get_songs(incoming.clone())?;
+    let library_songs = get_songs(library.clone())?;
+    let options = ImportOptions {
+        library: &library,
+        quarantine: &quarantine,
+        mode: args.mode,
+        template: &args.template,
+        sanitizer: &args.sanitize.into(),
+        required: &config.required_tags,
+        duplicates: args.duplicates,
+    };
+    let import = plan_import(&incoming, &songs, &library_songs, &options)?;
+    out.section("songs");
+    for decision in &import.songs {
+        out.item("songs", decision)?;
+    }
+    run_transactions(import.transactions, args.apply, out)
+}
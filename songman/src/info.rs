@@ -1,13 +1,9 @@
 use music_manager::info::get_info;
 
-use crate::cli;
+use crate::{cli, output::Output};
 
-pub fn show_info(args: cli::Info, json: bool) -> anyhow::Result<()> {
+pub fn show_info(args: cli::Info, out: &Output) -> anyhow::Result<()> {
     let info = get_info(&args.song, args.nonstandard)?;
-    if json {
-        println!("{}", serde_json::to_string_pretty(&info).unwrap());
-    } else {
-        println!("{}", toml::to_string_pretty(&info).unwrap());
-    }
+    out.table(&info)?;
     Ok(())
 }
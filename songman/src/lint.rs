@@ -0,0 +1,42 @@
+use music_manager::lint::{all_rules, default_rules, fix_transactions};
+
+use crate::{
+    cli,
+    config::{config, get_root_songs, roots},
+    output::Output,
+    sort::run_transactions,
+};
+
+pub fn lint(args: cli::Lint, out: &Output) -> anyhow::Result<()> {
+    let config = config();
+    let names: Vec<_> = all_rules(&config.required_tags, &config.lint)
+        .iter()
+        .map(|r| r.name())
+        .collect();
+    for name in &args.rules {
+        if !names.contains(&name.as_str()) {
+            anyhow::bail!(
+                "Unknown rule '{name}', expected one of {}",
+                names.join(", ")
+            );
+        }
+        if config.lint.disable.contains(name) {
+            anyhow::bail!("Rule '{name}' is disabled in the config");
+        }
+    }
+    let rules: Vec<_> = default_rules(&config.required_tags, &config.lint)
+        .into_iter()
+        .filter(|r| args.rules.is_empty() || args.rules.iter().any(|n| n == r.name()))
+        .collect();
+    let songs = get_root_songs(&roots(&args.roots))?;
+    let mut diagnostics = music_manager::lint::lint(&songs, &rules)?;
+    diagnostics.retain(|d| d.severity >= args.severity);
+    out.section("diagnostics");
+    for diagnostic in &diagnostics {
+        out.item("diagnostics", diagnostic)?;
+    }
+    if args.fix || args.apply {
+        run_transactions(fix_transactions(&diagnostics), args.apply, out)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,13 @@
+use music_manager::loudness::{analyze_loudness, gain_transactions};
+
+use crate::{cli, config::get_songs, output::Output, sort::run_transactions};
+
+pub fn show_loudness(args: cli::Loudness, out: &Output) -> anyhow::Result<()> {
+    let songs = get_songs(args.root.clone())?;
+    let report = analyze_loudness(&songs)?;
+    out.document(&report)?;
+    if let Some(gain_tags) = args.write {
+        run_transactions(gain_transactions(&report, gain_tags), args.apply, out)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use music_manager::album::{Library, read_tracks};
+
+use crate::{
+    cli,
+    config::{get_root_songs, roots},
+    output::Output,
+};
+
+pub fn show_albums(args: cli::Ls, out: &Output) -> anyhow::Result<()> {
+    let songs = get_root_songs(&roots(&args.roots))?;
+    let mut library = Library::new(read_tracks(&songs)?);
+    if args.incomplete {
+        library.albums.retain(|a| !a.is_complete());
+        library.loose.clear();
+    }
+    out.document(&library)?;
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use music_manager::lyrics::{embed_diffs, extract_transactions};
+
+use crate::{cli, config::get_songs, output::Output, sort::run_transactions, tag::run_diffs};
+
+pub fn lyrics(args: cli::Lyrics, out: &Output) -> anyhow::Result<()> {
+    match args.command {
+        cli::LyricsCommand::Embed(e) => embed(e, out),
+        cli::LyricsCommand::Extract(e) => extract(e, out),
+    }
+}
+
+fn embed(args: cli::Embed, out: &Output) -> anyhow::Result<()> {
+    let songs = get_songs(args.root)?;
+    let diffs = embed_diffs(&songs, args.overwrite)?;
+    run_diffs(&diffs, args.apply, out)
+}
+
+fn extract(args: cli::Extract, out: &Output) -> anyhow::Result<()> {
+    let songs = get_songs(args.root)?;
+    let transactions = extract_transactions(&songs, args.overwrite)?;
+    run_transactions(transactions, args.apply, out)
+}
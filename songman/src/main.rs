@@ -3,44 +3,84 @@
 #![warn(clippy::style)]
 #![warn(clippy::correctness)]
 
-use clap::Parser;
-use cli::{Cli, Command};
+use cli::Command;
 use duplicates::show_duplicates;
 use hash::show_hash;
 use info::show_info;
+use output::Output;
 use stats::show_stats;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{filter::FilterFn, layer::SubscriberExt, util::SubscriberInitExt};
 
+mod analyze;
 mod cli;
+mod config;
+mod cue;
 mod duplicates;
 mod hash;
+mod import;
 mod info;
+mod lint;
+mod loudness;
+mod ls;
+mod lyrics;
+mod musicbrainz;
+mod output;
+mod schema;
+mod serve;
 mod sort;
 mod stats;
+mod sync;
+mod table;
+mod tag;
+mod transcode;
 
 fn setup_tracing(max_level: tracing::Level) {
     let crate_filter = FilterFn::new(|s| {
-        !(s.target().starts_with("symphonia") && s.level() >= &tracing::Level::INFO)
+        let noisy = s.target().starts_with("symphonia") || s.target().starts_with("flacenc");
+        !(noisy && s.level() >= &tracing::Level::INFO)
     });
 
     tracing_subscriber::registry()
         .with(crate_filter)
         .with(LevelFilter::from_level(max_level))
-        .with(tracing_subscriber::fmt::layer())
+        // stdout is kept for results, such as JSON documents
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = Cli::parse();
+    let (args, command) = config::parse()?;
     setup_tracing(args.log_level);
+    if let Command::Schema(s) = args.command {
+        return schema::show_schema(s);
+    }
+    let out = Output::new(args.format(), command);
+    let result = run(args.command, &out);
+    let finished = out.finish(result.as_ref().err());
+    result.and(finished)
+}
 
-    match args.command {
-        Command::Sort(s) => sort::sort(s, args.json)?,
-        Command::DetectDupe(d) => show_duplicates(d, args.json)?,
-        Command::Info(i) => show_info(i, args.json)?,
-        Command::Stats(s) => show_stats(s, args.json)?,
-        Command::Hash(h) => show_hash(h, args.json)?,
+fn run(command: Command, out: &Output) -> anyhow::Result<()> {
+    match command {
+        Command::Sort(s) => sort::sort(s, out),
+        Command::DetectDupe(d) => show_duplicates(d, out),
+        Command::Info(i) => show_info(i, out),
+        Command::Stats(s) => show_stats(s, out),
+        Command::Hash(h) => show_hash(h, out),
+        Command::Transcode(t) => transcode::transcode(t, out),
+        Command::Sync(s) => sync::sync(s, out),
+        Command::Loudness(l) => loudness::show_loudness(l, out),
+        Command::Analyze(a) => analyze::show_analysis(a, out),
+        Command::Lint(l) => lint::lint(l, out),
+        Command::Ls(l) => ls::show_albums(l, out),
+        Command::Tag(t) => tag::tag(t, out),
+        Command::Match(m) => musicbrainz::show_matches(m, out),
+        Command::Cue(c) => cue::cue(c, out),
+        Command::Lyrics(l) => lyrics::lyrics(l, out),
+        Command::Config(c) => config::config_command(c, out),
+        Command::Import(i) => import::import(i, out),
+        Command::Serve(s) => serve::serve(s, out),
+        Command::Schema(s) => schema::show_schema(s),
     }
-    Ok(())
 }
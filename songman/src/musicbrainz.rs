@@ -0,0 +1,25 @@
+use music_manager::{
+    album::{Library, read_tracks},
+    musicbrainz::{match_albums, release_diffs},
+};
+
+use crate::{cli, config::get_songs, output::Output, tag::run_diffs};
+
+pub fn show_matches(args: cli::Match, out: &Output) -> anyhow::Result<()> {
+    let songs = get_songs(args.root.clone())?;
+    let library = Library::new(read_tracks(&songs)?);
+    let report = match_albums(&args.database, &library.albums)?;
+    out.document(&report)?;
+    if args.write || args.apply {
+        let mut diffs = Vec::new();
+        for (album, matched) in library.albums.iter().zip(&report.albums) {
+            if let Some(candidate) = &matched.candidate
+                && candidate.distance <= args.max_distance
+            {
+                diffs.append(&mut release_diffs(album, &candidate.release)?);
+            }
+        }
+        run_diffs(&diffs, args.apply, out)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,348 @@
+use std::{cell::RefCell, fmt::Display, time::SystemTime};
+
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use crate::table;
+
+/// Version of the JSON output, bumped whenever its shape changes incompatibly
+pub const OUTPUT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Tables or TOML documents, and a line per item
+    Text,
+    /// The results laid out as tables once the command is done
+    Table,
+    /// The results as a TOML document once the command is done
+    Toml,
+    /// A single [`Envelope`] once the command is done
+    Json,
+    /// A [`Record`] per line as results are known
+    Ndjson,
+    /// The results as a CSV table once the command is done
+    Csv,
+    /// A single [`Envelope`] as YAML once the command is done
+    Yaml,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "table" => Ok(Self::Table),
+            "toml" => Ok(Self::Toml),
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            "csv" => Ok(Self::Csv),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            _ => Err(format!("Unknown output format '{s}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Text => write!(f, "text"),
+            Self::Table => write!(f, "table"),
+            Self::Toml => write!(f, "toml"),
+            Self::Json => write!(f, "json"),
+            Self::Ndjson => write!(f, "ndjson"),
+            Self::Csv => write!(f, "csv"),
+            Self::Yaml => write!(f, "yaml"),
+        }
+    }
+}
+
+/// The JSON document every command prints
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Envelope<T> {
+    pub version: u32,
+    /// The subcommand, such as `stats` or `tag normalize`
+    pub command: String,
+    /// RFC 3339 time the command started
+    pub timestamp: String,
+    /// The command's results, `null` if it failed before having any
+    pub results: Option<T>,
+    pub errors: Vec<String>,
+}
+
+impl<T> Envelope<T> {
+    /// An envelope stamped with the current time
+    pub fn new(command: &str, results: Option<T>, errors: Vec<String>) -> Self {
+        Self {
+            version: OUTPUT_VERSION,
+            command: command.to_string(),
+            timestamp: timestamp(),
+            results,
+            errors,
+        }
+    }
+}
+
+/// A line of NDJSON output
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Record<T> {
+    Start {
+        version: u32,
+        command: String,
+        timestamp: String,
+    },
+    Result {
+        /// The results key the result is listed under in the JSON document
+        #[serde(skip_serializing_if = "Option::is_none")]
+        section: Option<String>,
+        result: T,
+    },
+    End {
+        errors: Vec<String>,
+    },
+}
+
+/// Prints the results of a command in the chosen [`Format`]
+#[derive(Debug)]
+pub struct Output {
+    pub format: Format,
+    command: String,
+    timestamp: String,
+    /// The command's document, results added by section are merged into it
+    document: RefCell<Option<Value>>,
+    sections: RefCell<Map<String, Value>>,
+    errors: RefCell<Vec<String>>,
+}
+
+impl Output {
+    pub fn new(format: Format, command: String) -> Self {
+        let output = Self {
+            format,
+            command,
+            timestamp: timestamp(),
+            document: RefCell::default(),
+            sections: RefCell::default(),
+            errors: RefCell::default(),
+        };
+        if format == Format::Ndjson {
+            output
+                .record(&Record::<()>::Start {
+                    version: OUTPUT_VERSION,
+                    command: output.command.clone(),
+                    timestamp: output.timestamp.clone(),
+                })
+                .expect("The start record serializes");
+        }
+        output
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.format == Format::Text
+    }
+
+    /// Whether results are kept until the command is done
+    fn collects(&self) -> bool {
+        !matches!(self.format, Format::Text | Format::Ndjson)
+    }
+
+    /// The whole result of a command, printed as TOML in text
+    pub fn document(&self, results: &impl Serialize) -> anyhow::Result<()> {
+        match self.format {
+            Format::Text => println!("{}", to_toml(results)?),
+            Format::Ndjson => self.result(None, results)?,
+            _ => *self.document.borrow_mut() = Some(to_value(results)?),
+        }
+        Ok(())
+    }
+
+    /// The whole result of a command, printed as tables in text
+    pub fn table(&self, results: &impl Serialize) -> anyhow::Result<()> {
+        match self.format {
+            Format::Text => {
+                print_tables(&to_value(results)?);
+                Ok(())
+            }
+            _ => self.document(results),
+        }
+    }
+
+    /// A result listed under `section`, printed as soon as it is known
+    pub fn item(&self, section: &str, item: &(impl Serialize + Display)) -> anyhow::Result<()> {
+        match self.format {
+            Format::Text => println!("{item}"),
+            Format::Ndjson => self.result(Some(section), item)?,
+            _ => {
+                let mut sections = self.sections.borrow_mut();
+                let list = sections
+                    .entry(section)
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                if let Value::Array(list) = list {
+                    list.push(to_value(item)?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// List `section` in the results even if no item is added to it
+    pub fn section(&self, section: &str) {
+        if self.collects() {
+            self.sections
+                .borrow_mut()
+                .entry(section)
+                .or_insert_with(|| Value::Array(Vec::new()));
+        }
+    }
+
+    /// A single result stored under `key`
+    pub fn value(&self, key: &str, value: &(impl Serialize + Display)) -> anyhow::Result<()> {
+        match self.format {
+            Format::Text => println!("{value}"),
+            Format::Ndjson => self.result(Some(key), value)?,
+            _ => {
+                self.sections
+                    .borrow_mut()
+                    .insert(key.to_string(), to_value(value)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// A message for people, such as `Success`, left out of every other format
+    pub fn status(&self, message: impl Display) {
+        if self.is_text() {
+            println!("{message}");
+        }
+    }
+
+    /// An error the command carries on after, already logged
+    pub fn error(&self, error: impl Display) {
+        self.errors.borrow_mut().push(error.to_string());
+    }
+
+    /// Print the kept results, or the closing record, with the error that ended the command.
+    /// Fails if the format can't represent the results.
+    pub fn finish(&self, error: Option<&anyhow::Error>) -> anyhow::Result<()> {
+        let carried = self.errors.take();
+        let mut errors = carried.clone();
+        errors.extend(error.map(|e| format!("{e:#}")));
+        let envelope = || Envelope {
+            version: OUTPUT_VERSION,
+            command: self.command.clone(),
+            timestamp: self.timestamp.clone(),
+            results: self.results(),
+            errors: errors.clone(),
+        };
+        match self.format {
+            Format::Text => {}
+            Format::Ndjson => self.record(&Record::<()>::End {
+                errors: errors.clone(),
+            })?,
+            Format::Json => println!("{}", serde_json::to_string_pretty(&envelope())?),
+            Format::Yaml => print!("{}", serde_norway::to_string(&envelope())?),
+            // errors are listed after the results
+            Format::Toml => {
+                if let Some(results) = with_errors(self.results(), &errors) {
+                    println!("{}", to_toml(&results)?);
+                }
+            }
+            Format::Table => {
+                if let Some(results) = with_errors(self.results(), &errors) {
+                    print_tables(&results);
+                }
+            }
+            // a CSV holds a single table, so errors the command carried on after go to
+            // stderr, where the one ending it is reported too
+            Format::Csv => {
+                if let Some(results) = self.results() {
+                    print!("{}", to_csv(&results)?);
+                }
+                for error in &carried {
+                    eprintln!("error: {error}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn results(&self) -> Option<Value> {
+        let sections = self.sections.take();
+        match self.document.take() {
+            Some(Value::Object(mut document)) => {
+                document.extend(sections);
+                Some(Value::Object(document))
+            }
+            Some(document) => Some(document),
+            None if sections.is_empty() => None,
+            None => Some(Value::Object(sections)),
+        }
+    }
+
+    fn result(&self, section: Option<&str>, result: &impl Serialize) -> anyhow::Result<()> {
+        self.record(&Record::Result {
+            section: section.map(str::to_string),
+            result,
+        })
+    }
+
+    fn record<T: Serialize>(&self, record: &Record<T>) -> anyhow::Result<()> {
+        println!("{}", serde_json::to_string(record)?);
+        Ok(())
+    }
+}
+
+/// The current time in RFC 3339
+fn timestamp() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+}
+
+fn to_value(value: &impl Serialize) -> anyhow::Result<Value> {
+    serde_json::to_value(value).context("The results can't be written as JSON")
+}
+
+/// The results with `errors` added under an `errors` key
+fn with_errors(results: Option<Value>, errors: &[String]) -> Option<Value> {
+    if errors.is_empty() {
+        return results;
+    }
+    let errors = Value::from(errors.to_vec());
+    match results {
+        Some(Value::Object(mut results)) => {
+            results.insert("errors".to_string(), errors);
+            Some(Value::Object(results))
+        }
+        Some(results) => Some(json!({ "results": results, "errors": errors })),
+        None => Some(json!({ "errors": errors })),
+    }
+}
+
+fn to_toml(value: &impl Serialize) -> anyhow::Result<String> {
+    toml::to_string_pretty(value)
+        .context("The results can't be written as TOML, try another --format such as json")
+}
+
+fn to_csv(results: &Value) -> anyhow::Result<String> {
+    match table::tables(results).as_slice() {
+        [] => Ok(String::new()),
+        [table] => table::to_csv(table),
+        tables => {
+            let titles: Vec<&str> = tables.iter().map(|t| t.title.as_str()).collect();
+            anyhow::bail!(
+                "CSV holds a single table but the results have {} ({}), \
+                try another --format such as table or json",
+                tables.len(),
+                titles.join(", ")
+            )
+        }
+    }
+}
+
+fn print_tables(results: &Value) {
+    let tables: Vec<String> = table::tables(results)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    print!("{}", tables.join("\n"));
+}
@@ -0,0 +1,179 @@
+// the results types only describe the JSON output, nothing builds them
+#![allow(dead_code)]
+
+use music_manager::{
+    album::Library,
+    analysis::AnalysisReport,
+    config::Setting,
+    cue::SplitTrack,
+    duplicates::{Duplicates, RootedDuplicates},
+    import::Decision,
+    info::Info,
+    lint::Diagnostic,
+    loudness::LoudnessReport,
+    musicbrainz::MatchReport,
+    sheet::TagDiff,
+    sort::Transaction,
+    stats::Stats,
+    transcode::Job,
+};
+use schemars::{JsonSchema, generate::SchemaSettings};
+
+use crate::{
+    cli,
+    output::{Envelope, Record},
+};
+
+#[derive(JsonSchema)]
+struct Transactions {
+    transactions: Vec<Transaction>,
+}
+
+#[derive(JsonSchema)]
+struct Diffs {
+    diffs: Vec<TagDiff>,
+}
+
+/// Songs are tagged with their root when there are several
+#[derive(JsonSchema)]
+#[serde(untagged)]
+enum DuplicateResults {
+    Duplicates(Duplicates<'static>),
+    Rooted(RootedDuplicates<'static>),
+}
+
+#[derive(JsonSchema)]
+struct Hash {
+    hash: String,
+}
+
+#[derive(JsonSchema)]
+struct Jobs {
+    jobs: Vec<Job>,
+}
+
+#[derive(JsonSchema)]
+struct LoudnessResults {
+    #[serde(flatten)]
+    report: LoudnessReport<'static>,
+    /// With `--write`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transactions: Option<Vec<Transaction>>,
+}
+
+#[derive(JsonSchema)]
+struct LintResults {
+    diagnostics: Vec<Diagnostic<'static>>,
+    /// With `--fix` or `--apply`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transactions: Option<Vec<Transaction>>,
+}
+
+#[derive(JsonSchema)]
+struct MatchResults {
+    #[serde(flatten)]
+    report: MatchReport<'static>,
+    /// With `--write` or `--apply`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diffs: Option<Vec<TagDiff>>,
+}
+
+#[derive(JsonSchema)]
+struct Tracks {
+    tracks: Vec<SplitTrack<'static>>,
+}
+
+#[derive(JsonSchema)]
+struct Settings {
+    settings: Vec<Setting>,
+}
+
+#[derive(JsonSchema)]
+struct ImportResults {
+    songs: Vec<Decision<'static>>,
+    transactions: Vec<Transaction>,
+}
+
+/// The results of commands listing several kinds of results, as NDJSON records
+#[derive(JsonSchema)]
+#[serde(untagged)]
+enum Either<A, B> {
+    A(A),
+    B(B),
+}
+
+pub fn show_schema(args: cli::Schema) -> anyhow::Result<()> {
+    let schema = command_schema(&args.command.join(" "), args.ndjson)?;
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
+
+/// The schema of the output of `command`, such as `tag normalize`
+fn command_schema(command: &str, ndjson: bool) -> anyhow::Result<schemars::Schema> {
+    let schema = match command {
+        "sort" | "sync" | "tag infer" | "tag edit" | "lyrics extract" => {
+            schema::<Transactions, Transaction>(ndjson)
+        }
+        "detect-dupe" => schema::<DuplicateResults, DuplicateResults>(ndjson),
+        "info" => schema::<Info, Info>(ndjson),
+        "stats" => schema::<Stats, Stats>(ndjson),
+        "hash" => schema::<Hash, String>(ndjson),
+        "transcode" => schema::<Jobs, Job>(ndjson),
+        "loudness" => schema::<LoudnessResults, Either<LoudnessReport, Transaction>>(ndjson),
+        "analyze" => schema::<AnalysisReport, AnalysisReport>(ndjson),
+        "lint" => schema::<LintResults, Either<Diagnostic, Transaction>>(ndjson),
+        "ls" => schema::<Library, Library>(ndjson),
+        "tag import" | "tag normalize" | "lyrics embed" => schema::<Diffs, TagDiff>(ndjson),
+        "match" => schema::<MatchResults, Either<MatchReport, TagDiff>>(ndjson),
+        "cue split" => schema::<Tracks, SplitTrack>(ndjson),
+        "config show" => schema::<Settings, Setting>(ndjson),
+        "import" => schema::<ImportResults, Either<Decision, Transaction>>(ndjson),
+        "serve" => anyhow::bail!("serve answers each route with the output of its command"),
+        "tag export" => anyhow::bail!("tag export writes a sheet in the format asked for"),
+        "schema" => anyhow::bail!("schema prints a JSON Schema"),
+        command => anyhow::bail!("Unknown command '{command}'"),
+    };
+    Ok(schema)
+}
+
+/// The schema of the JSON document with `Results`, or of the NDJSON records with `Item`s
+fn schema<Results: JsonSchema, Item: JsonSchema>(ndjson: bool) -> schemars::Schema {
+    // fields skipped when empty are optional in what is printed
+    let generator = SchemaSettings::default().for_serialize().into_generator();
+    match ndjson {
+        true => generator.into_root_schema_for::<Record<Item>>(),
+        false => generator.into_root_schema_for::<Envelope<Results>>(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    /// Every command without subcommands, such as `tag normalize`
+    fn commands(command: &clap::Command, prefix: &str, out: &mut Vec<String>) {
+        for sub in command.get_subcommands().filter(|s| s.get_name() != "help") {
+            let name = format!("{prefix}{}", sub.get_name());
+            match sub.has_subcommands() {
+                true => commands(sub, &format!("{name} "), out),
+                false => out.push(name),
+            }
+        }
+    }
+
+    #[test]
+    fn every_command_is_described() {
+        let mut names = Vec::new();
+        commands(&cli::Cli::command(), "", &mut names);
+        assert!(names.contains(&"tag normalize".to_string()));
+        for name in names {
+            for ndjson in [false, true] {
+                if let Err(e) = command_schema(&name, ndjson) {
+                    assert!(!e.to_string().starts_with("Unknown command"), "{e}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,734 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    fs::File,
+    hash::{BuildHasher, RandomState},
+    io::{Read, Seek, SeekFrom},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{Mutex, MutexGuard, RwLock},
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use music_manager::{
+    config::DuplicatePolicy,
+    duplicates::detect_duplicates,
+    filter::Filter,
+    index::LibraryIndex,
+    info::get_info,
+    roots::{LibraryRoot, root_of},
+    sanitize::Sanitizer,
+    sort::{Mode, SortOptions, Transaction, sort_songs_transactions},
+    stats::get_stats,
+};
+use serde::Serialize;
+use serde_json::{Value, json};
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server, StatusCode};
+use tracing::{info, warn};
+
+use crate::{
+    cli,
+    config::{config, get_root_songs, roots},
+    output::{Envelope, Output},
+};
+
+mod subsonic;
+
+/// What every request shares
+struct State {
+    bind: SocketAddr,
+    allowed_hosts: Vec<String>,
+    roots: Vec<LibraryRoot>,
+    mode: Mode,
+    template: music_manager::template::Template,
+    sanitizer: Sanitizer,
+    index: RwLock<LibraryIndex>,
+    /// Sort plans waiting to be applied with their confirmation token, oldest first
+    plans: Mutex<VecDeque<(String, Vec<Transaction>)>>,
+    /// Held while planning, applying or rebuilding, so plans are applied to the library
+    /// they were planned from
+    library: Mutex<()>,
+}
+
+/// Requests answered at once, such as songs streamed, others wait for a worker
+const WORKERS: usize = 16;
+/// Sort plans kept waiting, older ones are forgotten
+const MAX_PLANS: usize = 8;
+
+/// An error answered with an HTTP status
+#[derive(Debug)]
+pub(crate) struct ApiError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: u16, message: impl Display) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(error: E) -> Self {
+        Self::new(500, format!("{:#}", error.into()))
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+pub fn serve(args: cli::Serve, out: &Output) -> anyhow::Result<()> {
+    let roots = roots(&args.roots);
+    let index = LibraryIndex::build(get_root_songs(&roots)?)?;
+    let server =
+        Server::http(args.bind).map_err(|e| anyhow!("Can't listen on {}: {e}", args.bind))?;
+    out.status(format!(
+        "Serving {} tracks on http://{}",
+        index.tracks.len(),
+        args.bind
+    ));
+    let state = State {
+        bind: args.bind,
+        allowed_hosts: args.allowed_hosts,
+        roots,
+        mode: args.mode,
+        template: args.template,
+        sanitizer: args.sanitize.into(),
+        index: RwLock::new(index),
+        plans: Mutex::default(),
+        library: Mutex::default(),
+    };
+    std::thread::scope(|scope| {
+        for _ in 0..WORKERS {
+            scope.spawn(|| {
+                for request in server.incoming_requests() {
+                    handle(&state, request);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle(state: &State, request: Request) {
+    let (method, url) = (request.method().clone(), request.url().to_string());
+    let response = route(state, &request);
+    info!("{method} {url} {}", response.status_code().0);
+    if let Err(e) = request.respond(response) {
+        warn!("Failed answering {method} {url}: {e}");
+    }
+}
+
+fn route(state: &State, request: &Request) -> ResponseBox {
+    let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+    let query: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let api = segments[0] == "api";
+    if let Err(e) = check_origin(state, request, api) {
+        return error_response("serve", e);
+    }
+    if api && !state.bind.ip().is_loopback() && !authorized(request) {
+        let error = ApiError::new(401, "Please log in as one of the users of [serve.users]");
+        return error_response("serve", error)
+            .with_header(header("WWW-Authenticate", r#"Basic realm="songman""#))
+            .boxed();
+    }
+    match (request.method(), segments.as_slice()) {
+        (Method::Get | Method::Head, ["api", "songs"]) => json("songs", songs(state, &query)),
+        (Method::Get | Method::Head, ["api", "songs", id]) => {
+            json("info", song_info(state, id, &query))
+        }
+        (Method::Get | Method::Head, ["api", "songs", id, "stream"]) => {
+            let path = song_path(state, id);
+            path.and_then(|p| stream(&p, request))
+                .unwrap_or_else(|e| error_response("stream", e))
+        }
+        (Method::Get | Method::Head, ["api", "albums"]) => json("ls", albums(state, &query)),
+        (Method::Get | Method::Head, ["api", "stats"]) => json("stats", stats(state)),
+        (Method::Get | Method::Head, ["api", "duplicates"]) => {
+            json("detect-dupe", duplicates(state, &query))
+        }
+        (Method::Get | Method::Head, ["api", "sort"]) => json("sort", plan_sort(state, &query)),
+        (Method::Post, ["api", "sort", "apply"]) => json("sort", apply_sort(state, &query)),
+        (Method::Post, ["api", "refresh"]) => json("refresh", refresh(state)),
+        (Method::Get | Method::Head | Method::Post, ["rest", method]) => {
+            subsonic::answer(state, method, &query, request)
+        }
+        (method, _) => error_response(
+            "serve",
+            ApiError::new(404, format!("No route for {method} {path}")),
+        ),
+    }
+}
+
+/// Refuse requests naming another host than the server, such as those of a page rebinding
+/// its own name to the server's address, and cross-origin requests to the JSON API
+fn check_origin(state: &State, request: &Request, api: bool) -> ApiResult<()> {
+    let host = request_header(request, "Host").unwrap_or_default();
+    if !allowed_host(state, host) {
+        return Err(ApiError::new(403, format!("Host '{host}' is not served")));
+    }
+    if api && let Some(origin) = request_header(request, "Origin") {
+        let origin_host = origin.split_once("://").map(|(_, host)| host);
+        if !origin_host.is_some_and(|h| allowed_host(state, h)) {
+            return Err(ApiError::new(
+                403,
+                format!("Origin '{origin}' is not allowed"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `host`, as in a `Host` header, names the server: its own address, any IP
+/// address when listening on all of them, `localhost` or an allowed host name
+fn allowed_host(state: &State, host: &str) -> bool {
+    let (name, port) = match host.rsplit_once(':') {
+        Some((name, port)) if !port.ends_with(']') => (name, port.parse().ok()),
+        _ => (host, Some(80)),
+    };
+    if port != Some(state.bind.port()) {
+        return false;
+    }
+    let ip = state.bind.ip();
+    match name
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(name) => name == ip || ip.is_unspecified(),
+        Err(_) => {
+            let local = name.eq_ignore_ascii_case("localhost");
+            let allowed = state
+                .allowed_hosts
+                .iter()
+                .any(|h| h.eq_ignore_ascii_case(name));
+            allowed || local && (ip.is_loopback() || ip.is_unspecified())
+        }
+    }
+}
+
+/// Whether the request logs in as one of the configured users with HTTP basic
+/// authentication
+fn authorized(request: &Request) -> bool {
+    let Some(credentials) = request_header(request, "Authorization")
+        .and_then(|a| a.strip_prefix("Basic "))
+        .and_then(|c| BASE64_STANDARD.decode(c.trim()).ok())
+        .and_then(|c| String::from_utf8(c).ok())
+    else {
+        return false;
+    };
+    credentials
+        .split_once(':')
+        .is_some_and(|(user, password)| valid_password(user, password))
+}
+
+/// Whether `password` is the configured password of `user`
+pub(crate) fn valid_password(user: &str, password: &str) -> bool {
+    config()
+        .serve
+        .users
+        .get(user)
+        .is_some_and(|p| constant_time_eq(p.as_bytes(), password.as_bytes()))
+}
+
+/// Compare secrets in a time independent of where they differ
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn request_header<'r>(request: &'r Request, field: &'static str) -> Option<&'r str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(field))
+        .map(|h| h.value.as_str())
+}
+
+/// The results of `command` in the envelope of its JSON output
+fn json(command: &str, results: ApiResult<Value>) -> ResponseBox {
+    match results {
+        Ok(results) => {
+            let envelope = Envelope::new(command, Some(results), Vec::new());
+            json_response(200, &envelope)
+        }
+        Err(e) => error_response(command, e),
+    }
+}
+
+pub(crate) fn error_response(command: &str, error: ApiError) -> ResponseBox {
+    let envelope = Envelope::<()>::new(command, None, vec![error.message]);
+    json_response(error.status, &envelope)
+}
+
+fn json_response(status: u16, body: &impl Serialize) -> ResponseBox {
+    let body = serde_json::to_vec(body).expect("Envelopes serialize to JSON");
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+        .boxed()
+}
+
+pub(crate) fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("Header names are valid")
+}
+
+/// A query flag, given as `flag`, `flag=true` or `flag=1`
+fn flag(query: &HashMap<String, String>, name: &str) -> bool {
+    query
+        .get(name)
+        .is_some_and(|v| matches!(v.as_str(), "" | "true" | "1"))
+}
+
+/// The query parameter `name` parsed, `None` if it is not given
+fn param<T: std::str::FromStr<Err: Display>>(
+    query: &HashMap<String, String>,
+    name: &str,
+) -> ApiResult<Option<T>> {
+    query
+        .get(name)
+        .map(|v| v.parse())
+        .transpose()
+        .map_err(|e| ApiError::new(400, format!("Invalid {name}: {e}")))
+}
+
+fn index(state: &State) -> std::sync::RwLockReadGuard<'_, LibraryIndex> {
+    state
+        .index
+        .read()
+        .expect("No request panics holding the index")
+}
+
+fn songs(state: &State, query: &HashMap<String, String>) -> ApiResult<Value> {
+    let filter: Filter = param(query, "filter")?.unwrap_or_default();
+    let index = index(state);
+    let songs: Vec<_> = index.filter(&filter).collect();
+    Ok(json!({ "songs": songs }))
+}
+
+fn song_path(state: &State, id: &str) -> ApiResult<std::path::PathBuf> {
+    index(state)
+        .get(id)
+        .map(|t| t.path.clone())
+        .ok_or_else(|| ApiError::new(404, format!("No song with id '{id}'")))
+}
+
+fn song_info(state: &State, id: &str, query: &HashMap<String, String>) -> ApiResult<Value> {
+    let info = get_info(&song_path(state, id)?, flag(query, "nonstandard"))?;
+    Ok(serde_json::to_value(info)?)
+}
+
+fn albums(state: &State, query: &HashMap<String, String>) -> ApiResult<Value> {
+    let index = index(state);
+    let mut library = index.library();
+    if flag(query, "incomplete") {
+        library.albums.retain(|a| !a.is_complete());
+        library.loose.clear();
+    }
+    Ok(serde_json::to_value(library)?)
+}
+
+fn stats(state: &State) -> ApiResult<Value> {
+    let index = index(state);
+    let mut stats = get_stats(
+        &state.roots,
+        &index.songs,
+        &state.template,
+        &state.sanitizer,
+        &config().required_tags,
+        false,
+    )?;
+    stats.clear_lists();
+    Ok(serde_json::to_value(stats)?)
+}
+
+fn duplicates(state: &State, query: &HashMap<String, String>) -> ApiResult<Value> {
+    let mut policy = DuplicatePolicy {
+        metadata: flag(query, "metadata"),
+        filename: flag(query, "filename"),
+        stream: flag(query, "stream"),
+    };
+    if policy.is_empty() {
+        policy = config().duplicates;
+    }
+    if policy.is_empty() {
+        return Err(ApiError::new(
+            400,
+            "Please ask for one of either metadata, filename or stream duplicates",
+        ));
+    }
+    let index = index(state);
+    let mut duplicates = detect_duplicates(
+        &index.songs,
+        policy.metadata,
+        policy.filename,
+        policy.stream,
+    )?;
+    if flag(query, "across") {
+        duplicates.retain_across(&state.roots);
+    }
+    // songs are tagged with their root once there are several
+    let duplicates = match state.roots.len() > 1 {
+        true => serde_json::to_value(duplicates.rooted(&state.roots))?,
+        false => serde_json::to_value(duplicates)?,
+    };
+    Ok(duplicates)
+}
+
+/// Plan sorting each root within itself, answering the plan with the token applying it
+fn plan_sort(state: &State, query: &HashMap<String, String>) -> ApiResult<Value> {
+    let mode = param(query, "mode")?.unwrap_or(state.mode);
+    let _library = lock_library(state);
+    let index = index(state);
+    let options = SortOptions {
+        mode,
+        template: &state.template,
+        sanitizer: &state.sanitizer,
+        skip_existing: flag(query, "skip_existing"),
+    };
+    let mut planned = HashMap::new();
+    let mut transactions = Vec::new();
+    for root in &state.roots {
+        let songs: Vec<&Path> = index
+            .songs
+            .iter()
+            .map(|s| s.as_path())
+            .filter(|s| root_of(&state.roots, s) == Some(root))
+            .collect();
+        transactions.append(
+            &mut sort_songs_transactions(&root.path, &root.path, &songs, &options, &mut planned)
+                .map_err(|e| match e {
+                    music_manager::Error::AlreadyExists { .. }
+                    | music_manager::Error::SameDestination { .. } => {
+                        ApiError::new(409, format!("{e}, plan with skip_existing to leave it"))
+                    }
+                    e => e.into(),
+                })?,
+        );
+    }
+    transactions.sort();
+    transactions.dedup();
+    let token = token();
+    let mut plans = plans(state);
+    if plans.len() == MAX_PLANS {
+        plans.pop_front();
+    }
+    plans.push_back((token.clone(), transactions.clone()));
+    Ok(json!({ "token": token, "transactions": transactions }))
+}
+
+/// Apply the sort plan of the `token` query parameter, once. Only clients on the same
+/// machine may move songs.
+fn apply_sort(state: &State, query: &HashMap<String, String>) -> ApiResult<Value> {
+    if !state.bind.ip().is_loopback() {
+        let message = "Sort plans are only applied when listening on a loopback address";
+        return Err(ApiError::new(403, message));
+    }
+    let token = query
+        .get("token")
+        .ok_or_else(|| ApiError::new(400, "Missing the token of the plan to apply"))?;
+    let _library = lock_library(state);
+    let mut plans = plans(state);
+    let transactions = plans
+        .iter()
+        .position(|(t, _)| t == token)
+        .and_then(|planned| plans.remove(planned))
+        .map(|(_, transactions)| transactions)
+        .ok_or_else(|| ApiError::new(409, "Unknown or outdated plan token, plan the sort again"))?;
+    // every other plan is outdated once this one is applied
+    plans.clear();
+    drop(plans);
+    let mut applied = Vec::new();
+    let result = transactions.iter().try_for_each(|t| {
+        t.apply()?;
+        applied.push(t);
+        Ok::<_, music_manager::Error>(())
+    });
+    // the library changed even if a transaction failed
+    rebuild(state)?;
+    if let Err(e) = result {
+        let failed = &transactions[applied.len()];
+        let message = format!("{failed} failed after {} applied: {e}", applied.len());
+        return Err(ApiError::new(500, message));
+    }
+    Ok(json!({ "transactions": applied }))
+}
+
+fn refresh(state: &State) -> ApiResult<Value> {
+    let _library = lock_library(state);
+    rebuild(state)?;
+    Ok(json!({ "tracks": index(state).tracks.len() }))
+}
+
+fn lock_library(state: &State) -> MutexGuard<'_, ()> {
+    state
+        .library
+        .lock()
+        .expect("No request panics holding the library")
+}
+
+fn plans(state: &State) -> MutexGuard<'_, VecDeque<(String, Vec<Transaction>)>> {
+    state
+        .plans
+        .lock()
+        .expect("No request panics holding the plans")
+}
+
+/// Read the library again, outdating every plan. Callers hold the library lock.
+fn rebuild(state: &State) -> anyhow::Result<()> {
+    let index = LibraryIndex::build(get_root_songs(&state.roots)?)?;
+    *state
+        .index
+        .write()
+        .expect("No request panics holding the index") = index;
+    plans(state).clear();
+    Ok(())
+}
+
+/// A token unlikely to be guessed, confirming a plan was reviewed before it is applied
+fn token() -> String {
+    let time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let halves = [RandomState::new(), RandomState::new()].map(|s| s.hash_one(time));
+    format!("{:016x}{:016x}", halves[0], halves[1])
+}
+
+/// Answer with the song at `path`, or the byte range the request asks for
+pub(crate) fn stream(path: &Path, request: &Request) -> ApiResult<ResponseBox> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let range = request_header(request, "Range");
+    let mut headers = vec![
+        header("Content-Type", content_type(path)),
+        header("Accept-Ranges", "bytes"),
+    ];
+    let (status, start, end) = match range.map(|r| parse_range(r, size)) {
+        None => (200, 0, size),
+        Some(Some((start, end))) => {
+            let content_range = format!("bytes {start}-{}/{size}", end - 1);
+            headers.push(header("Content-Range", &content_range));
+            (206, start, end)
+        }
+        Some(None) => {
+            let response = Response::empty(416)
+                .with_header(header("Content-Range", &format!("bytes */{size}")))
+                .boxed();
+            return Ok(response);
+        }
+    };
+    file.seek(SeekFrom::Start(start))?;
+    let length = end - start;
+    Ok(Response::new(
+        StatusCode(status),
+        headers,
+        file.take(length),
+        Some(length as usize),
+        None,
+    )
+    .boxed())
+}
+
+/// The bytes `start..end` of a `Range: bytes=...` header, `None` if they are not within the
+/// file. Only the first of several ranges is served.
+fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let spec = range.trim().strip_prefix("bytes=")?.split(',').next()?;
+    let (start, end) = spec.trim().split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => (size.saturating_sub(suffix.parse().ok()?), size),
+        (start, "") => (start.parse().ok()?, size),
+        (start, end) => {
+            let end: u64 = end.parse().ok()?;
+            (start.parse().ok()?, end.saturating_add(1).min(size))
+        }
+    };
+    (start < end).then_some((start, end))
+}
+
+pub(crate) fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("flac") => "audio/flac",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg" | "oga" | "opus") => "audio/ogg",
+        Some("m4a" | "mp4" | "aac") => "audio/mp4",
+        Some("wav") => "audio/wav",
+        Some("aif" | "aiff") => "audio/aiff",
+        Some("wv") => "audio/x-wavpack",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::config::load_test_config;
+
+    fn state(bind: &str, allowed_hosts: &[&str]) -> State {
+        State {
+            bind: bind.parse().unwrap(),
+            allowed_hosts: allowed_hosts.iter().map(|h| h.to_string()).collect(),
+            roots: Vec::new(),
+            mode: Mode::Copy,
+            template: Default::default(),
+            sanitizer: Sanitizer::default(),
+            index: RwLock::default(),
+            plans: Mutex::default(),
+            library: Mutex::default(),
+        }
+    }
+
+    /// Write a tenth of a second of silence as 16 bit mono WAV, tagged with the RIFF INFO
+    /// chunks `info` such as `INAM`
+    fn write_song(path: &Path, info: &[(&str, &str)]) {
+        let mut list = b"INFO".to_vec();
+        for (id, value) in info {
+            let mut value = value.as_bytes().to_vec();
+            list.extend_from_slice(id.as_bytes());
+            list.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            value.resize(value.len().next_multiple_of(2), 0);
+            list.append(&mut value);
+        }
+        let (rate, data_len) = (44100u32, 8820u32);
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len + 8 + list.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&rate.to_le_bytes());
+        wav.extend_from_slice(&(rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"LIST");
+        wav.extend_from_slice(&(list.len() as u32).to_le_bytes());
+        wav.append(&mut list);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        wav.resize(wav.len() + data_len as usize, 0);
+        std::fs::write(path, wav).unwrap();
+    }
+
+    /// The state of a server listening on `bind` to serve `root`, holding an unsorted
+    /// album of two songs
+    pub(super) fn library_state(bind: &str, root: &Path) -> State {
+        load_test_config();
+        let unsorted = root.join("unsorted");
+        std::fs::create_dir_all(&unsorted).unwrap();
+        for (number, title) in [("1", "One"), ("2", "Two & Three")] {
+            let info = [
+                ("IART", "The Band"),
+                ("IPRD", "Album"),
+                ("INAM", title),
+                ("IPRT", number),
+            ];
+            write_song(&unsorted.join(format!("{number}.wav")), &info);
+        }
+        let mut state = state(bind, &[]);
+        state.roots = vec![LibraryRoot {
+            name: "library".to_string(),
+            path: root.to_path_buf(),
+        }];
+        let songs = get_root_songs(&state.roots).unwrap();
+        state.index = RwLock::new(LibraryIndex::build(songs).unwrap());
+        state
+    }
+
+    #[test]
+    fn plans_are_applied_once_by_their_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = library_state("127.0.0.1:8080", dir.path());
+        let token = |plan: &Value| plan["token"].as_str().unwrap().to_string();
+        let apply = |token: &str| {
+            let query = HashMap::from([("token".to_string(), token.to_string())]);
+            apply_sort(&state, &query)
+        };
+        let status = |result: ApiResult<Value>| result.unwrap_err().status;
+
+        let plan = plan_sort(&state, &HashMap::new()).unwrap();
+        let newer = plan_sort(&state, &HashMap::new()).unwrap();
+        assert_eq!(plan["transactions"], newer["transactions"]);
+        assert!(!plan["transactions"].as_array().unwrap().is_empty());
+        assert_ne!(token(&plan), token(&newer));
+
+        assert_eq!(status(apply_sort(&state, &HashMap::new())), 400);
+        assert_eq!(status(apply("unknown")), 409);
+        let applied = apply(&token(&plan)).unwrap();
+        assert_eq!(applied["transactions"], plan["transactions"]);
+        assert_eq!(index(&state).tracks.len(), 4);
+
+        assert_eq!(status(apply(&token(&plan))), 409);
+        assert_eq!(status(apply(&token(&newer))), 409);
+    }
+
+    #[test]
+    fn plans_are_only_applied_on_loopback() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = library_state("192.168.1.2:8080", dir.path());
+        let plan = plan_sort(&state, &HashMap::new()).unwrap();
+        let token = plan["token"].as_str().unwrap().to_string();
+        let query = HashMap::from([("token".to_string(), token)]);
+        assert_eq!(apply_sort(&state, &query).unwrap_err().status, 403);
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_file() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 100)));
+        assert_eq!(parse_range("bytes=500-", 1000), Some((500, 1000)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 1000)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some((900, 1000)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some((0, 1000)));
+        assert_eq!(parse_range(" bytes=0-0, 5-9", 1000), Some((0, 1)));
+        for range in [
+            "bytes=1000-",
+            "bytes=5-2",
+            "bytes=-0",
+            "bytes=a-b",
+            "items=0-9",
+            "0-9",
+        ] {
+            assert_eq!(parse_range(range, 1000), None, "{range}");
+        }
+    }
+
+    #[test]
+    fn hosts_must_name_the_server() {
+        let loopback = state("127.0.0.1:8080", &[]);
+        assert!(allowed_host(&loopback, "127.0.0.1:8080"));
+        assert!(allowed_host(&loopback, "LOCALHOST:8080"));
+        assert!(!allowed_host(&loopback, "localhost:8081"));
+        assert!(!allowed_host(&loopback, "localhost"));
+        assert!(!allowed_host(&loopback, "evil.example:8080"));
+        assert!(!allowed_host(&loopback, "192.168.1.2:8080"));
+        assert!(!allowed_host(&loopback, ""));
+
+        let everywhere = state("[::]:80", &["music.lan"]);
+        assert!(allowed_host(&everywhere, "192.168.1.2"));
+        assert!(allowed_host(&everywhere, "[fe80::1]:80"));
+        assert!(allowed_host(&everywhere, "localhost"));
+        assert!(allowed_host(&everywhere, "Music.LAN"));
+        assert!(!allowed_host(&everywhere, "evil.example"));
+
+        let lan = state("192.168.1.2:8080", &[]);
+        assert!(allowed_host(&lan, "192.168.1.2:8080"));
+        assert!(!allowed_host(&lan, "localhost:8080"));
+    }
+
+    #[test]
+    fn secrets_compare_whole() {
+        assert!(constant_time_eq(b"sesame", b"sesame"));
+        assert!(!constant_time_eq(b"sesame", b"sesamE"));
+        assert!(!constant_time_eq(b"sesame", b"sesam"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
@@ -0,0 +1,804 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    time::SystemTime,
+};
+
+use music_manager::{
+    album::{Album, Library, Track},
+    index::{IndexedTrack, LibraryIndex, album_id, artist_id},
+    roots::{LibraryRoot, root_of},
+    tags::read_cover,
+    template::Field,
+};
+use serde_json::{Map, Value, json};
+use tiny_http::{Request, Response, ResponseBox};
+
+use super::{State, constant_time_eq, content_type, header, index, stream, valid_password};
+use crate::config::config;
+
+/// The version of the Subsonic API answered
+const API_VERSION: &str = "1.16.1";
+/// Articles left out when sorting and indexing artists
+const IGNORED_ARTICLES: &[&str] = &["The", "El", "La", "Los", "Las", "Le", "Les"];
+const UNKNOWN_ARTIST: &str = "Unknown Artist";
+
+/// A request answered with a Subsonic error code
+#[derive(Debug)]
+struct Failure {
+    code: u32,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: u32, message: impl std::fmt::Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn missing(parameter: &str) -> Self {
+        Self::new(10, format!("Required parameter is missing: {parameter}"))
+    }
+
+    fn not_found(what: &str) -> Self {
+        Self::new(70, format!("{what} not found"))
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for Failure {
+    fn from(error: E) -> Self {
+        Self::new(0, format!("{:#}", error.into()))
+    }
+}
+
+type Payload = Result<Value, Failure>;
+
+enum Format {
+    Xml,
+    Json,
+    Jsonp(String),
+}
+
+/// Answer the Subsonic API `method`, such as `getAlbum` or `getAlbum.view`
+pub(super) fn answer(
+    state: &State,
+    method: &str,
+    query: &HashMap<String, String>,
+    request: &Request,
+) -> ResponseBox {
+    let method = method.strip_suffix(".view").unwrap_or(method);
+    let format = match query.get("f").map(String::as_str) {
+        Some("json") => Format::Json,
+        Some("jsonp") => match query.get("callback") {
+            Some(callback) if is_callback(callback) => Format::Jsonp(callback.clone()),
+            Some(_) => return respond(&Format::Json, Err(Failure::new(0, "Invalid callback"))),
+            None => return respond(&Format::Json, Err(Failure::missing("callback"))),
+        },
+        _ => Format::Xml,
+    };
+    if let Err(failure) = authenticate(query) {
+        return respond(&format, Err(failure));
+    }
+    let payload = match method {
+        "stream" | "getCoverArt" => {
+            let file = match method {
+                "stream" => stream_song(state, query, request),
+                _ => cover_art(state, query),
+            };
+            match file {
+                Ok(response) => return response,
+                Err(failure) => Err(failure),
+            }
+        }
+        "ping" => Ok(json!({})),
+        "getLicense" => Ok(json!({ "license": { "valid": true } })),
+        "getMusicFolders" => Ok(music_folders(&state.roots)),
+        "getIndexes" => indexes(state, query),
+        "getArtists" => artists(state, query),
+        "getArtist" => artist(state, query),
+        "getAlbum" => album(state, query),
+        "getSong" => song(state, query),
+        "search3" => search(state, query),
+        method => Err(Failure::new(0, format!("Unsupported method '{method}'"))),
+    };
+    respond(&format, payload)
+}
+
+/// Whether a JSONP `callback` is a plain, possibly dotted, JavaScript name rather than
+/// script of the caller's choosing
+fn is_callback(callback: &str) -> bool {
+    let mut chars = callback.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '.'))
+}
+
+/// Check the user's password, given as `p` or as the token `t`, the MD5 of the password
+/// followed by the salt `s`
+fn authenticate(query: &HashMap<String, String>) -> Result<(), Failure> {
+    let user = query.get("u").ok_or_else(|| Failure::missing("u"))?;
+    let valid = match (query.get("t"), query.get("s"), query.get("p")) {
+        (Some(token), Some(salt), _) => config().serve.users.get(user).is_some_and(|p| {
+            let digest = format!("{:x}", md5::compute(format!("{p}{salt}")));
+            constant_time_eq(digest.as_bytes(), token.to_ascii_lowercase().as_bytes())
+        }),
+        (.., Some(given)) => decode_password(given).is_some_and(|p| valid_password(user, &p)),
+        _ => return Err(Failure::missing("t")),
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(Failure::new(40, "Wrong username or password")),
+    }
+}
+
+/// A password given in the clear or hex encoded as `enc:...`
+fn decode_password(password: &str) -> Option<String> {
+    let Some(hex) = password.strip_prefix("enc:") else {
+        return Some(password.to_string());
+    };
+    let bytes: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+    String::from_utf8(bytes?).ok()
+}
+
+fn respond(format: &Format, payload: Payload) -> ResponseBox {
+    let mut response = Map::new();
+    response.insert("status".to_string(), json!("ok"));
+    response.insert("version".to_string(), json!(API_VERSION));
+    response.insert("type".to_string(), json!("songman"));
+    response.insert(
+        "serverVersion".to_string(),
+        json!(env!("CARGO_PKG_VERSION")),
+    );
+    match payload {
+        Ok(Value::Object(payload)) => response.extend(payload),
+        Ok(_) => unreachable!("Payloads are objects"),
+        Err(failure) => {
+            response.insert("status".to_string(), json!("failed"));
+            let error = json!({ "code": failure.code, "message": failure.message });
+            response.insert("error".to_string(), error);
+        }
+    }
+    let mut response = Value::Object(response);
+    strip_nulls(&mut response);
+    let (body, content_type) = match format {
+        Format::Xml => {
+            if let Value::Object(map) = &mut response {
+                map.insert("xmlns".to_string(), json!("http://subsonic.org/restapi"));
+            }
+            let mut xml = r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string();
+            write_xml("subsonic-response", &response, &mut xml);
+            (xml, "text/xml; charset=utf-8")
+        }
+        Format::Json => {
+            let body = json!({ "subsonic-response": response });
+            (body.to_string(), "application/json")
+        }
+        Format::Jsonp(callback) => {
+            let body = json!({ "subsonic-response": response });
+            (format!("{callback}({body});"), "application/javascript")
+        }
+    };
+    Response::from_string(body)
+        .with_header(header("Content-Type", content_type))
+        .boxed()
+}
+
+/// Leave out what is unknown rather than answering `null`
+fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+/// The XML form of a JSON answer: scalars become attributes, objects child elements and
+/// arrays repeated child elements
+fn write_xml(name: &str, value: &Value, xml: &mut String) {
+    let Value::Object(map) = value else {
+        xml.push_str(&format!("<{name}>{}</{name}>", escape(&scalar(value))));
+        return;
+    };
+    xml.push_str(&format!("<{name}"));
+    for (key, value) in map.iter().filter(|(_, v)| !v.is_object() && !v.is_array()) {
+        xml.push_str(&format!(" {key}=\"{}\"", escape(&scalar(value))));
+    }
+    let children: Vec<(&String, &Value)> = map
+        .iter()
+        .filter(|(_, v)| v.is_object() || v.is_array())
+        .collect();
+    if children.is_empty() {
+        xml.push_str("/>");
+        return;
+    }
+    xml.push('>');
+    for (key, value) in children {
+        match value {
+            Value::Array(items) => items.iter().for_each(|i| write_xml(key, i, xml)),
+            value => write_xml(key, value, xml),
+        }
+    }
+    xml.push_str(&format!("</{name}>"));
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn required<'q>(query: &'q HashMap<String, String>, name: &str) -> Result<&'q str, Failure> {
+    query
+        .get(name)
+        .map(String::as_str)
+        .ok_or_else(|| Failure::missing(name))
+}
+
+/// The number parameter `name`, `default` if it is not given
+fn number(query: &HashMap<String, String>, name: &str, default: usize) -> Result<usize, Failure> {
+    match query.get(name) {
+        Some(n) => n
+            .parse()
+            .map_err(|_| Failure::new(0, format!("Invalid {name} '{n}'"))),
+        None => Ok(default),
+    }
+}
+
+/// Each root is a music folder, numbered in order
+fn music_folders(roots: &[LibraryRoot]) -> Value {
+    let folders: Vec<Value> = roots
+        .iter()
+        .enumerate()
+        .map(|(id, root)| json!({ "id": id, "name": root.name }))
+        .collect();
+    json!({ "musicFolders": { "musicFolder": folders } })
+}
+
+/// The index as Subsonic clients browse it: artists with albums, and albums with songs
+struct Catalog<'a> {
+    roots: &'a [LibraryRoot],
+    tracks: HashMap<&'a str, &'a IndexedTrack>,
+    library: Library<'a>,
+}
+
+impl<'a> Catalog<'a> {
+    fn new(roots: &'a [LibraryRoot], index: &'a LibraryIndex) -> Self {
+        Self {
+            roots,
+            tracks: index.tracks.iter().map(|t| (t.id.as_str(), t)).collect(),
+            library: index.library(),
+        }
+    }
+
+    /// The albums of the music folder `folder`, or of every folder
+    fn albums(&self, folder: Option<usize>) -> impl Iterator<Item = &Album<'a>> {
+        let root = folder.and_then(|f| self.roots.get(f));
+        self.library
+            .albums
+            .iter()
+            .filter(move |a| root.is_none_or(|r| root_of(self.roots, a.dir) == Some(r)))
+    }
+
+    /// Artists by sort name, with their albums
+    fn artists(&self, folder: Option<usize>) -> BTreeMap<String, (String, Vec<&Album<'a>>)> {
+        let mut artists: BTreeMap<String, (String, Vec<&Album>)> = BTreeMap::new();
+        for album in self.albums(folder) {
+            let name = album_artist(album);
+            artists
+                .entry(sort_name(name).to_lowercase())
+                .or_insert_with(|| (name.to_string(), Vec::new()))
+                .1
+                .push(album);
+        }
+        artists
+    }
+
+    fn album(&self, id: &str) -> Option<&Album<'a>> {
+        self.library.albums.iter().find(|a| album_id(a) == id)
+    }
+
+    /// Every track, with the album holding it
+    fn tracks(&self) -> impl Iterator<Item = (&Track<'a>, Option<&Album<'a>>)> {
+        let albums = self
+            .library
+            .albums
+            .iter()
+            .flat_map(|a| a.tracks.iter().map(move |t| (t, Some(a))));
+        albums.chain(self.library.loose.iter().map(|t| (t, None)))
+    }
+
+    fn track(&self, id: &str) -> Option<(&Track<'a>, Option<&Album<'a>>)> {
+        self.tracks().find(|(t, _)| t.id.as_deref() == Some(id))
+    }
+
+    fn album_json(&self, album: &Album) -> Value {
+        let id = album_id(album);
+        let artist = album_artist(album);
+        let created = album
+            .dir
+            .metadata()
+            .and_then(|m| m.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        json!({
+            "id": id,
+            "name": album.name,
+            "artist": artist,
+            "artistId": artist_id(artist),
+            "coverArt": id,
+            "songCount": album.tracks.len(),
+            "duration": album.duration.round() as u64,
+            "created": humantime::format_rfc3339_seconds(created).to_string(),
+            "year": album.year.as_deref().and_then(year),
+            "genre": album.tracks.first().and_then(|t| t.field(Field::Genre)),
+        })
+    }
+
+    /// A song as a Subsonic child. The virtual tracks of CUE images stream their whole image.
+    fn song_json(&self, track: &Track, album: Option<&Album>) -> Value {
+        let id = track.id.clone().unwrap_or_default();
+        let album_id = album.map(album_id);
+        let artist = track
+            .field(Field::Artist)
+            .or_else(|| album.map(|a| album_artist(a).to_string()))
+            .unwrap_or_else(|| UNKNOWN_ARTIST.to_string());
+        let title = track.title.clone().unwrap_or_else(|| {
+            let stem = track.path.file_stem().unwrap_or_default();
+            stem.to_string_lossy().into_owned()
+        });
+        let root = root_of(self.roots, track.path);
+        let path = root
+            .and_then(|r| track.path.strip_prefix(&r.path).ok())
+            .unwrap_or(track.path);
+        json!({
+            "id": id,
+            "parent": album_id,
+            "isDir": false,
+            "title": title,
+            "album": album.map(|a| a.name.clone()).or_else(|| track.field(Field::Album)),
+            "artist": artist,
+            "track": track.number,
+            "discNumber": track.disc,
+            "year": track.field(Field::Year).as_deref().and_then(year),
+            "genre": track.field(Field::Genre),
+            "coverArt": album_id.clone().unwrap_or_else(|| id.clone()),
+            "size": self.tracks.get(id.as_str()).map(|t| t.size),
+            "contentType": content_type(track.path),
+            "suffix": track.path.extension().map(|e| e.to_string_lossy().to_lowercase()),
+            "duration": track.duration.map(|d| d.round() as u64),
+            "path": path.to_string_lossy(),
+            "isVideo": false,
+            "type": "music",
+            "albumId": album_id,
+            "artistId": artist_id(&artist),
+        })
+    }
+
+    fn artist_json(&self, name: &str, albums: &[&Album]) -> Value {
+        json!({
+            "id": artist_id(name),
+            "name": name,
+            "albumCount": albums.len(),
+            "coverArt": albums.first().map(|a| album_id(a)),
+        })
+    }
+
+    /// Artists grouped by the first letter of their sort name
+    fn artist_index(&self, folder: Option<usize>) -> Vec<Value> {
+        let mut index: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+        for (name, albums) in self.artists(folder).values() {
+            let letter = match sort_name(name).chars().next() {
+                Some(c) if c.is_alphabetic() => c.to_uppercase().collect(),
+                _ => "#".to_string(),
+            };
+            let artist = self.artist_json(name, albums);
+            index.entry(letter).or_default().push(artist);
+        }
+        index
+            .into_iter()
+            .map(|(name, artists)| json!({ "name": name, "artist": artists }))
+            .collect()
+    }
+}
+
+fn album_artist<'a>(album: &'a Album) -> &'a str {
+    album.artist.as_deref().unwrap_or(UNKNOWN_ARTIST)
+}
+
+/// `name` without a leading article
+fn sort_name(name: &str) -> &str {
+    IGNORED_ARTICLES
+        .iter()
+        .find_map(|article| {
+            let rest = name.get(article.len()..)?;
+            let matches = name[..article.len()].eq_ignore_ascii_case(article);
+            rest.strip_prefix(' ').filter(|_| matches)
+        })
+        .unwrap_or(name)
+}
+
+/// The year of a `2001` or `2001-05-04` style date
+fn year(date: &str) -> Option<u32> {
+    date.get(..4)?.parse().ok()
+}
+
+fn indexes(state: &State, query: &HashMap<String, String>) -> Payload {
+    let folder = query.get("musicFolderId").and_then(|f| f.parse().ok());
+    let index = index(state);
+    let catalog = Catalog::new(&state.roots, &index);
+    let modified = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    Ok(json!({
+        "indexes": {
+            "lastModified": modified,
+            "ignoredArticles": IGNORED_ARTICLES.join(" "),
+            "index": catalog.artist_index(folder),
+        }
+    }))
+}
+
+fn artists(state: &State, query: &HashMap<String, String>) -> Payload {
+    let folder = query.get("musicFolderId").and_then(|f| f.parse().ok());
+    let index = index(state);
+    let catalog = Catalog::new(&state.roots, &index);
+    Ok(json!({
+        "artists": {
+            "ignoredArticles": IGNORED_ARTICLES.join(" "),
+            "index": catalog.artist_index(folder),
+        }
+    }))
+}
+
+fn artist(state: &State, query: &HashMap<String, String>) -> Payload {
+    let id = required(query, "id")?;
+    let index = index(state);
+    let catalog = Catalog::new(&state.roots, &index);
+    let artists = catalog.artists(None);
+    let (name, albums) = artists
+        .values()
+        .find(|(name, _)| artist_id(name) == id)
+        .ok_or_else(|| Failure::not_found("Artist"))?;
+    let mut artist = catalog.artist_json(name, albums);
+    let albums: Vec<Value> = albums.iter().map(|a| catalog.album_json(a)).collect();
+    artist["album"] = json!(albums);
+    Ok(json!({ "artist": artist }))
+}
+
+fn album(state: &State, query: &HashMap<String, String>) -> Payload {
+    let id = required(query, "id")?;
+    let index = index(state);
+    let catalog = Catalog::new(&state.roots, &index);
+    let album = catalog
+        .album(id)
+        .ok_or_else(|| Failure::not_found("Album"))?;
+    let mut json = catalog.album_json(album);
+    let songs: Vec<Value> = album
+        .tracks
+        .iter()
+        .map(|t| catalog.song_json(t, Some(album)))
+        .collect();
+    json["song"] = json!(songs);
+    Ok(json!({ "album": json }))
+}
+
+fn song(state: &State, query: &HashMap<String, String>) -> Payload {
+    let id = required(query, "id")?;
+    let index = index(state);
+    let catalog = Catalog::new(&state.roots, &index);
+    let (track, album) = catalog
+        .track(id)
+        .ok_or_else(|| Failure::not_found("Song"))?;
+    Ok(json!({ "song": catalog.song_json(track, album) }))
+}
+
+/// Artists, albums and songs whose names contain the query, ignoring case. An empty query
+/// matches everything, as clients syncing the whole library ask.
+fn search(state: &State, query: &HashMap<String, String>) -> Payload {
+    let text = query
+        .get("query")
+        .map(|q| q.trim().trim_matches('"').to_lowercase())
+        .unwrap_or_default();
+    let matches = |s: &str| s.to_lowercase().contains(&text);
+    let page = |kind: &str| -> Result<(usize, usize), Failure> {
+        let count = number(query, &format!("{kind}Count"), 20)?;
+        let offset = number(query, &format!("{kind}Offset"), 0)?;
+        Ok((offset, count))
+    };
+    let folder = query.get("musicFolderId").and_then(|f| f.parse().ok());
+    let index = index(state);
+    let catalog = Catalog::new(&state.roots, &index);
+
+    let (offset, count) = page("artist")?;
+    let artists: Vec<Value> = catalog
+        .artists(folder)
+        .values()
+        .filter(|(name, _)| matches(name))
+        .skip(offset)
+        .take(count)
+        .map(|(name, albums)| catalog.artist_json(name, albums))
+        .collect();
+    let (offset, count) = page("album")?;
+    let albums: Vec<Value> = catalog
+        .albums(folder)
+        .filter(|a| matches(&a.name) || matches(album_artist(a)))
+        .skip(offset)
+        .take(count)
+        .map(|a| catalog.album_json(a))
+        .collect();
+    let (offset, count) = page("song")?;
+    let root = folder.and_then(|f| state.roots.get(f));
+    let songs: Vec<Value> = catalog
+        .tracks()
+        .filter(|(t, _)| root.is_none_or(|r| root_of(&state.roots, t.path) == Some(r)))
+        .filter(|(t, a)| {
+            [Field::Title, Field::Artist, Field::Album]
+                .iter()
+                .filter_map(|f| t.field(*f))
+                .chain(a.map(|a| a.name.clone()))
+                .any(|v| matches(&v))
+        })
+        .skip(offset)
+        .take(count)
+        .map(|(t, a)| catalog.song_json(t, a))
+        .collect();
+    Ok(json!({
+        "searchResult3": { "artist": artists, "album": albums, "song": songs }
+    }))
+}
+
+fn song_path(state: &State, id: &str) -> Result<std::path::PathBuf, Failure> {
+    index(state)
+        .get(id)
+        .map(|t| t.path.clone())
+        .ok_or_else(|| Failure::not_found("Song"))
+}
+
+/// The song as is, transcoding options such as `maxBitRate` are not supported
+fn stream_song(
+    state: &State,
+    query: &HashMap<String, String>,
+    request: &Request,
+) -> Result<ResponseBox, Failure> {
+    let path = song_path(state, required(query, "id")?)?;
+    stream(&path, request).map_err(|e| Failure::new(0, e.message))
+}
+
+/// The cover of a song, or of the first song of an album or of an artist's first album
+fn cover_art(state: &State, query: &HashMap<String, String>) -> Result<ResponseBox, Failure> {
+    let id = required(query, "id")?;
+    let path = match id {
+        id if id.starts_with("al-") || id.starts_with("ar-") => {
+            let index = index(state);
+            let catalog = Catalog::new(&state.roots, &index);
+            let album = match id.starts_with("al-") {
+                true => catalog.album(id),
+                false => catalog
+                    .artists(None)
+                    .into_values()
+                    .find(|(name, _)| artist_id(name) == id)
+                    .and_then(|(_, albums)| albums.first().copied()),
+            };
+            album
+                .and_then(|a| a.tracks.first())
+                .map(|t| t.path.to_path_buf())
+                .ok_or_else(|| Failure::not_found("Cover art"))?
+        }
+        id => song_path(state, id)?,
+    };
+    let cover = read_cover(Path::new(&path))?.ok_or_else(|| Failure::not_found("Cover art"))?;
+    let response = Response::from_data(cover.data)
+        .with_header(header("Content-Type", &cover.media_type))
+        .boxed();
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpStream},
+        panic::{AssertUnwindSafe, catch_unwind, resume_unwind},
+    };
+
+    use tiny_http::Server;
+
+    use super::*;
+    use crate::{
+        config::load_test_config,
+        serve::{handle, tests::library_state},
+    };
+
+    /// `sesame`, the password of `alice`, hex encoded
+    const ENCODED: &str = "enc:736573616d65";
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn code(query: &HashMap<String, String>) -> Option<u32> {
+        authenticate(query).err().map(|f| f.code)
+    }
+
+    #[test]
+    fn tokens_are_the_salted_password_digest() {
+        load_test_config();
+        let salt = "c19b2d";
+        let token = format!("{:x}", md5::compute(format!("sesame{salt}")));
+        let upper = token.to_uppercase();
+        assert_eq!(
+            code(&query(&[("u", "alice"), ("t", &token), ("s", salt)])),
+            None
+        );
+        assert_eq!(
+            code(&query(&[("u", "alice"), ("t", &upper), ("s", salt)])),
+            None
+        );
+        let resalted = query(&[("u", "alice"), ("t", &token), ("s", "other")]);
+        assert_eq!(code(&resalted), Some(40));
+        assert_eq!(
+            code(&query(&[("u", "bob"), ("t", &token), ("s", salt)])),
+            Some(40)
+        );
+    }
+
+    #[test]
+    fn passwords_are_given_in_the_clear_or_hex_encoded() {
+        load_test_config();
+        assert_eq!(code(&query(&[("u", "alice"), ("p", "sesame")])), None);
+        assert_eq!(code(&query(&[("u", "alice"), ("p", ENCODED)])), None);
+        assert_eq!(
+            code(&query(&[("u", "alice"), ("p", "enc:736573616d6")])),
+            Some(40)
+        );
+        assert_eq!(code(&query(&[("u", "alice"), ("p", "enc:zz")])), Some(40));
+        assert_eq!(code(&query(&[("u", "bob"), ("p", "sesame")])), Some(40));
+        assert_eq!(code(&query(&[("u", "alice")])), Some(10));
+        assert_eq!(code(&query(&[("p", "sesame")])), Some(10));
+    }
+
+    #[test]
+    fn callbacks_are_plain_names() {
+        for callback in ["cb", "jQuery.cb_1", "$", "_private"] {
+            assert!(is_callback(callback), "{callback}");
+        }
+        for callback in ["", "1cb", "alert(1)", "a;b", "a b", "cb//"] {
+            assert!(!is_callback(callback), "{callback}");
+        }
+    }
+
+    #[test]
+    fn xml_has_scalar_attributes_and_child_elements() {
+        let value = json!({
+            "status": "ok",
+            "album": {
+                "id": "al-1",
+                "name": "Tom & \"Jerry\"",
+                "song": [{ "id": 1 }, { "id": 2 }],
+                "genre": ["<Rock>"],
+            },
+            "count": 2,
+        });
+        let mut xml = String::new();
+        write_xml("response", &value, &mut xml);
+        assert_eq!(
+            xml,
+            "<response status=\"ok\" count=\"2\">\
+             <album id=\"al-1\" name=\"Tom &amp; &quot;Jerry&quot;\">\
+             <song id=\"1\"/><song id=\"2\"/><genre>&lt;Rock&gt;</genre>\
+             </album></response>"
+        );
+        assert_eq!(
+            escape("'a' < b & c > \"d\""),
+            "&apos;a&apos; &lt; b &amp; c &gt; &quot;d&quot;"
+        );
+    }
+
+    #[test]
+    fn artists_sort_without_articles() {
+        assert_eq!(sort_name("The Beatles"), "Beatles");
+        assert_eq!(sort_name("the who"), "who");
+        assert_eq!(sort_name("Los Lobos"), "Lobos");
+        assert_eq!(sort_name("Theatre"), "Theatre");
+        assert_eq!(sort_name("The"), "The");
+        assert_eq!(sort_name("Élan"), "Élan");
+    }
+
+    #[test]
+    fn years_are_read_from_dates() {
+        assert_eq!(year("2001"), Some(2001));
+        assert_eq!(year("2001-05-04"), Some(2001));
+        assert_eq!(year("01"), None);
+        assert_eq!(year("May 2001"), None);
+    }
+
+    /// The body of the answer to a GET of `path`, failing on other statuses than 200
+    fn get(server: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(server).unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nHost: {server}\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200"), "{path}: {head}");
+        body.to_string()
+    }
+
+    #[test]
+    fn clients_ping_and_get_albums() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let state = library_state(&address.to_string(), dir.path());
+        let album = album_id(&index(&state).library().albums[0]);
+
+        let answers = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for request in server.incoming_requests() {
+                    handle(&state, request);
+                }
+            });
+            let answers = catch_unwind(AssertUnwindSafe(|| {
+                let auth = format!("u=alice&p={ENCODED}");
+                [
+                    get(address, &format!("/rest/ping.view?{auth}")),
+                    get(address, &format!("/rest/getAlbum?{auth}&f=json&id={album}")),
+                ]
+            }));
+            server.unblock();
+            answers
+        });
+        let [ping, album] = answers.unwrap_or_else(|e| resume_unwind(e));
+
+        assert_eq!(
+            ping,
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?><subsonic-response status=\"ok\" \
+                 version=\"{API_VERSION}\" type=\"songman\" serverVersion=\"{}\" \
+                 xmlns=\"http://subsonic.org/restapi\"/>",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        let album: Value = serde_json::from_str(&album).unwrap();
+        let album = &album["subsonic-response"];
+        assert_eq!(album["status"], "ok");
+        let album = &album["album"];
+        assert_eq!(album["name"], "Album");
+        assert_eq!(album["artist"], "The Band");
+        assert_eq!(album["songCount"], 2);
+        let songs: Vec<_> = album["song"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["track"].clone(), s["title"].clone(), s["path"].clone()))
+            .collect();
+        assert_eq!(
+            songs,
+            [
+                (json!(1), json!("One"), json!("unsorted/1.wav")),
+                (json!(2), json!("Two & Three"), json!("unsorted/2.wav")),
+            ]
+        );
+    }
+}
@@ -1,29 +1,60 @@
+use std::collections::HashMap;
+
 use music_manager::{
-    get_songs,
-    sort::{Transaction, sort_songs_transactions},
+    roots::LibraryRoot,
+    sort::{SortOptions, Transaction, sort_songs_transactions},
+};
+
+use crate::{
+    cli,
+    config::{config, get_songs, roots},
+    output::Output,
 };
 
-use crate::cli;
+pub fn sort(args: cli::Sort, out: &Output) -> anyhow::Result<()> {
+    let sanitizer = args.sanitize.into();
+    let dest = args
+        .dest
+        .as_ref()
+        .map(|d| LibraryRoot::resolve(d, &config().roots));
+    let options = SortOptions {
+        mode: args.mode,
+        template: &args.template,
+        sanitizer: &sanitizer,
+        skip_existing: args.skip_existing,
+    };
+    let mut planned = HashMap::new();
+    let mut transactions = Vec::new();
+    for root in roots(&args.roots) {
+        let songs = get_songs(root.path.clone())?;
+        let dest = dest.as_ref().unwrap_or(&root);
+        transactions.append(&mut sort_songs_transactions(
+            &root.path,
+            &dest.path,
+            &songs,
+            &options,
+            &mut planned,
+        )?);
+    }
+    // roots sorted into one destination share its directories
+    transactions.sort();
+    transactions.dedup();
+    run_transactions(transactions, args.apply, out)
+}
 
-pub fn sort(args: cli::Sort, json: bool) -> anyhow::Result<()> {
-    let songs = get_songs(args.root.clone())?;
-    let transactions = sort_songs_transactions(&args.root, &songs)?;
+/// Print each transaction, applying it if asked to
+pub fn run_transactions(
+    transactions: Vec<Transaction>,
+    apply: bool,
+    out: &Output,
+) -> anyhow::Result<()> {
+    out.section("transactions");
     for transaction in transactions {
-        println!("{}", fmt_transaction(&transaction, json)?);
-        if args.apply {
+        out.item("transactions", &transaction)?;
+        if apply {
             transaction.apply()?;
-            if !json {
-                println!("Success");
-            }
+            out.status("Success");
         }
     }
     Ok(())
 }
-
-fn fmt_transaction(transaction: &Transaction, json: bool) -> Result<String, serde_json::Error> {
-    if json {
-        serde_json::to_string(transaction)
-    } else {
-        Ok(transaction.to_string())
-    }
-}
@@ -1,10 +1,23 @@
-use music_manager::{get_songs, stats::get_stats};
+use music_manager::stats::get_stats;
 
-use crate::cli;
+use crate::{
+    cli,
+    config::{config, get_root_songs, roots},
+    output::Output,
+};
 
-pub fn show_stats(s: cli::Stats, json: bool) -> anyhow::Result<()> {
-    let songs = get_songs(s.root.clone())?;
-    let mut stats = get_stats(&s.root, &songs)?;
+pub fn show_stats(s: cli::Stats, out: &Output) -> anyhow::Result<()> {
+    let roots = roots(&s.roots);
+    let songs = get_root_songs(&roots)?;
+    let config = config();
+    let mut stats = get_stats(
+        &roots,
+        &songs,
+        &s.template,
+        &s.sanitize.clone().into(),
+        &config.required_tags,
+        s.analyze || s.quality,
+    )?;
 
     if !s.all {
         stats.total.clear();
@@ -16,6 +29,7 @@ pub fn show_stats(s: cli::Stats, json: bool) -> anyhow::Result<()> {
 
     if !s.untagged {
         stats.untagged.clear();
+        stats.missing_tags.clear();
     }
 
     if !s.sorted {
@@ -25,10 +39,32 @@ pub fn show_stats(s: cli::Stats, json: bool) -> anyhow::Result<()> {
     if !s.unsorted {
         stats.unsorted.clear();
     }
-    if json {
-        println!("{}", serde_json::to_string_pretty(&stats).unwrap());
-    } else {
-        println!("{}", toml::to_string_pretty(&stats).unwrap());
+
+    if !s.albums {
+        stats.albums.clear();
+    }
+
+    if !s.incomplete {
+        stats.incomplete_albums.clear();
+    }
+
+    if !s.inconsistent_gain {
+        stats.inconsistent_gain.clear();
+    }
+
+    if !s.lyrics {
+        stats.lyrics.clear();
+    }
+
+    if !s.no_lyrics {
+        stats.no_lyrics.clear();
+    }
+
+    if !s.quality
+        && let Some(quality) = &mut stats.quality
+    {
+        quality.clear();
     }
+    out.table(&stats)?;
     Ok(())
 }
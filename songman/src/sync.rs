@@ -0,0 +1,21 @@
+use music_manager::{sanitize::Sanitizer, sync::sync_transactions};
+
+use crate::{cli, config::get_songs, output::Output, sort::run_transactions};
+
+pub fn sync(args: cli::Sync, out: &Output) -> anyhow::Result<()> {
+    let songs = get_songs(args.root.clone())?;
+    let sanitizer = Sanitizer {
+        profile: args.profile,
+        max_length: args.max_length,
+        ..Default::default()
+    };
+    let transactions = sync_transactions(
+        &args.dest,
+        &songs,
+        &args.filter.unwrap_or_default(),
+        args.max_size,
+        &args.template,
+        &sanitizer,
+    )?;
+    run_transactions(transactions, args.apply, out)
+}
@@ -0,0 +1,331 @@
+use std::fmt::Display;
+
+use serde_json::{Map, Value};
+
+/// Part of a command's results laid out in rows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    /// Dotted path of the results the table lists, empty for the top level
+    pub title: String,
+    /// Column names, `None` for key value pairs and plain lists
+    pub header: Option<Vec<String>>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Lay out results as tables: scalars as key value pairs, lists as a row per item and maps of
+/// records as a row per key
+pub fn tables(results: &Value) -> Vec<Table> {
+    let mut tables = Vec::new();
+    collect(String::new(), results, &mut tables);
+    tables
+}
+
+fn collect(title: String, value: &Value, tables: &mut Vec<Table>) {
+    match value {
+        Value::Object(map) if !map.is_empty() && map.values().all(is_record) => {
+            let entries = map.iter().map(|(k, v)| {
+                let mut row = vec![("name".to_string(), k.clone())];
+                flatten(String::new(), v, &mut row);
+                row
+            });
+            tables.push(Table::from_rows(title, entries.collect()));
+        }
+        Value::Object(map) => {
+            let pairs: Vec<Vec<String>> = map
+                .iter()
+                .filter(|(_, v)| is_scalar(v) && !v.is_null())
+                .map(|(k, v)| vec![k.clone(), cell(v)])
+                .collect();
+            if !pairs.is_empty() {
+                tables.push(Table {
+                    title: title.clone(),
+                    header: None,
+                    rows: pairs,
+                });
+            }
+            for (key, value) in map.iter().filter(|(_, v)| !is_scalar(v)) {
+                collect(join(&title, key), value, tables);
+            }
+        }
+        // empty lists have nothing to lay out
+        Value::Array(items) if items.is_empty() => {}
+        Value::Array(items) => {
+            let mut rows = Vec::new();
+            for (i, item) in items.iter().enumerate() {
+                match item {
+                    // groups, such as duplicates, list a row per member
+                    Value::Array(group) => rows.extend(group.iter().map(|member| {
+                        let mut row = vec![("group".to_string(), (i + 1).to_string())];
+                        flatten(String::new(), member, &mut row);
+                        row
+                    })),
+                    item => {
+                        let mut row = Vec::new();
+                        flatten(String::new(), item, &mut row);
+                        rows.push(row);
+                    }
+                }
+            }
+            tables.push(Table::from_rows(title, rows));
+        }
+        scalar => tables.push(Table {
+            title,
+            header: None,
+            rows: vec![vec![cell(scalar)]],
+        }),
+    }
+}
+
+impl Table {
+    /// A table with a column for every key of the rows, in the order they first appear
+    fn from_rows(title: String, rows: Vec<Vec<(String, String)>>) -> Self {
+        let mut columns: Vec<String> = Vec::new();
+        for (key, _) in rows.iter().flatten() {
+            if !columns.contains(key) {
+                columns.push(key.clone());
+            }
+        }
+        let rows = rows
+            .into_iter()
+            .map(|row| {
+                columns
+                    .iter()
+                    .map(|c| {
+                        row.iter()
+                            .find(|(k, _)| k == c)
+                            .map(|(_, v)| v.clone())
+                            .unwrap_or_default()
+                    })
+                    .collect()
+            })
+            .collect();
+        // a list of plain values needs no header
+        let header = (columns != ["value"]).then_some(columns);
+        Self {
+            title,
+            header,
+            rows,
+        }
+    }
+
+    /// The header, or `key, value` and `value` for tables without one
+    pub fn columns(&self) -> Vec<String> {
+        match &self.header {
+            Some(header) => header.clone(),
+            None => match self.rows.first().map(Vec::len) {
+                Some(2) => vec!["key".to_string(), "value".to_string()],
+                _ => vec!["value".to_string()],
+            },
+        }
+    }
+}
+
+/// A map of scalars, listed as a row
+fn is_record(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => map.values().all(|v| is_scalar(v) || is_list(v)),
+        _ => false,
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    !matches!(value, Value::Object(_) | Value::Array(_))
+}
+
+fn is_list(value: &Value) -> bool {
+    matches!(value, Value::Array(items) if items.iter().all(is_scalar))
+}
+
+/// Variants of enums, such as transactions, are maps with a single capitalized key
+fn variant(map: &Map<String, Value>) -> Option<(&String, &Value)> {
+    let (key, value) = map.iter().next()?;
+    let capitalized = key.starts_with(|c: char| c.is_ascii_uppercase());
+    (map.len() == 1 && capitalized).then_some((key, value))
+}
+
+/// The cells of `value`, named by their dotted path below `prefix`
+fn flatten(prefix: String, value: &Value, row: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => match variant(map) {
+            Some((name, value)) => {
+                row.push((join(&prefix, "type"), name.clone()));
+                flatten(prefix, value, row);
+            }
+            None => {
+                for (key, value) in map {
+                    flatten(join(&prefix, key), value, row);
+                }
+            }
+        },
+        Value::Array(items) if items.iter().all(is_scalar) => {
+            let cells: Vec<String> = items.iter().map(cell).collect();
+            row.push((name(prefix), cells.join(", ")));
+        }
+        Value::Array(_) => row.push((name(prefix), value.to_string())),
+        scalar => row.push((name(prefix), cell(scalar))),
+    }
+}
+
+fn name(prefix: String) -> String {
+    match prefix.is_empty() {
+        true => "value".to_string(),
+        false => prefix,
+    }
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    match prefix.is_empty() {
+        true => key.to_string(),
+        false => format!("{prefix}.{key}"),
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+impl Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let indent = match self.title.is_empty() {
+            true => "",
+            false => {
+                writeln!(f, "{}", self.title)?;
+                "  "
+            }
+        };
+        let lines: Vec<&Vec<String>> = self.header.iter().chain(&self.rows).collect();
+        let width = lines.iter().map(|l| l.len()).max().unwrap_or_default();
+        let widths: Vec<usize> = (0..width)
+            .map(|i| {
+                lines
+                    .iter()
+                    .filter_map(|l| l.get(i))
+                    .map(|c| c.chars().count())
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let write_line = |f: &mut std::fmt::Formatter<'_>, line: &[String]| {
+            let cells: Vec<String> = line
+                .iter()
+                .zip(&widths)
+                .map(|(c, w)| format!("{c:w$}"))
+                .collect();
+            writeln!(f, "{indent}{}", cells.join("  ").trim_end())
+        };
+        if let Some(header) = &self.header {
+            write_line(f, header)?;
+            let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+            write_line(f, &rule)?;
+        }
+        for row in &self.rows {
+            write_line(f, row)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a single table as CSV
+pub fn to_csv(table: &Table) -> anyhow::Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(table.columns())?;
+    for row in &table.rows {
+        writer.write_record(row)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(|c| c.to_string()).collect())
+            .collect()
+    }
+
+    fn header(columns: &[&str]) -> Option<Vec<String>> {
+        Some(columns.iter().map(|c| c.to_string()).collect())
+    }
+
+    #[test]
+    fn scalars_are_pairs_and_lists_are_rows() {
+        let results = json!({
+            "songs": 2,
+            "missing": null,
+            "tracks": [
+                {"path": "a.flac", "loudness": {"integrated": -9.5}},
+                {"path": "b.flac", "album": "B", "tags": ["x", "y"]},
+            ],
+            "errors": [],
+        });
+        assert_eq!(
+            tables(&results),
+            [
+                Table {
+                    title: String::new(),
+                    header: None,
+                    rows: rows(&[&["songs", "2"]]),
+                },
+                Table {
+                    title: "tracks".to_string(),
+                    header: header(&["path", "loudness.integrated", "album", "tags"]),
+                    rows: rows(&[&["a.flac", "-9.5", "", ""], &["b.flac", "", "B", "x, y"]]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_of_records_groups_and_variants() {
+        let results = json!({
+            "albums": {"A": {"tracks": 3}, "B": {"tracks": 1}},
+            "duplicates": [["a.flac", "b.flac"], ["c.flac", "d.flac"]],
+            "transactions": [{"Move": {"src": "a", "dest": "b"}}],
+        });
+        let [albums, duplicates, transactions] = &tables(&results)[..] else {
+            panic!("{:?}", tables(&results));
+        };
+        assert_eq!(albums.title, "albums");
+        assert_eq!(albums.header, header(&["name", "tracks"]));
+        assert_eq!(albums.rows, rows(&[&["A", "3"], &["B", "1"]]));
+        assert_eq!(duplicates.header, header(&["group", "value"]));
+        assert_eq!(duplicates.rows[2], ["2", "c.flac"]);
+        assert_eq!(transactions.header, header(&["type", "src", "dest"]));
+        assert_eq!(transactions.rows, rows(&[&["Move", "a", "b"]]));
+    }
+
+    #[test]
+    fn plain_lists_have_no_header() {
+        let [table] = &tables(&json!(["a", "b"]))[..] else {
+            panic!();
+        };
+        assert_eq!(table.header, None);
+        assert_eq!(table.columns(), ["value"]);
+        assert_eq!(table.to_string(), "a\nb\n");
+    }
+
+    #[test]
+    fn columns_are_aligned_and_csv_quoted() {
+        let table = Table {
+            title: "tracks".to_string(),
+            header: header(&["path", "n"]),
+            rows: rows(&[&["Sigur Rós.flac", "1"], &["a, b.flac", "10"]]),
+        };
+        assert_eq!(
+            table.to_string(),
+            "tracks\n  path            n\n  --------------  --\n  Sigur Rós.flac  1\n  a, b.flac       10\n"
+        );
+        assert_eq!(
+            to_csv(&table).unwrap(),
+            "path,n\nSigur Rós.flac,1\n\"a, b.flac\",10\n"
+        );
+    }
+}
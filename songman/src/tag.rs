@@ -0,0 +1,144 @@
+use std::{fs::File, io::BufWriter, path::Path, process::Command};
+
+use anyhow::bail;
+use music_manager::{
+    edit::TagDocument,
+    infer::infer_transactions,
+    normalize::{Transform, normalize_diffs},
+    sheet::{
+        SheetFormat, TagDiff, apply_diffs, export_rows, import_diffs, read_sheet, write_sheet,
+    },
+};
+
+use crate::{
+    cli::{self, TagCommand},
+    config::get_songs,
+    output::Output,
+    sort::run_transactions,
+};
+
+pub fn tag(args: cli::Tag, out: &Output) -> anyhow::Result<()> {
+    match args.command {
+        TagCommand::Infer(i) => infer(i, out),
+        TagCommand::Export(e) => export(e),
+        TagCommand::Import(i) => import(i, out),
+        TagCommand::Edit(e) => edit(e, out),
+        TagCommand::Normalize(n) => normalize(n, out),
+    }
+}
+
+fn infer(args: cli::Infer, out: &Output) -> anyhow::Result<()> {
+    let songs = get_songs(args.root.clone())?;
+    let transactions = infer_transactions(&songs, &args.patterns, args.overwrite)?;
+    run_transactions(transactions, args.apply, out)
+}
+
+fn export(args: cli::Export) -> anyhow::Result<()> {
+    let songs = get_songs(args.root.clone())?;
+    let rows = export_rows(&songs, &args.filter.unwrap_or_default())?;
+    let format = args
+        .format
+        .or_else(|| args.output.as_deref().and_then(SheetFormat::from_path))
+        .unwrap_or_default();
+    match &args.output {
+        Some(output) => write_sheet(&rows, format, BufWriter::new(File::create(output)?))?,
+        None => write_sheet(&rows, format, std::io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+fn import(args: cli::Import, out: &Output) -> anyhow::Result<()> {
+    let songs = get_songs(args.root.clone())?;
+    let format = args
+        .format
+        .or_else(|| SheetFormat::from_path(&args.sheet))
+        .unwrap_or_default();
+    let rows = read_sheet(format, File::open(&args.sheet)?)?;
+    let diffs = import_diffs(&rows, &songs)?;
+    run_diffs(&diffs, args.apply, out)
+}
+
+fn normalize(args: cli::Normalize, out: &Output) -> anyhow::Result<()> {
+    let songs = get_songs(args.root.clone())?;
+    let transforms = match args.rules.is_empty() {
+        true => Transform::ALL,
+        false => &args.rules,
+    };
+    let diffs = normalize_diffs(&songs, transforms, args.language)?;
+    run_diffs(&diffs, args.apply, out)
+}
+
+/// Print each diff, applying them all if asked to
+pub fn run_diffs(diffs: &[TagDiff], apply: bool, out: &Output) -> anyhow::Result<()> {
+    out.section("diffs");
+    for diff in diffs {
+        out.item("diffs", diff)?;
+    }
+    if apply {
+        apply_diffs(diffs)?;
+        out.status("Success");
+    }
+    Ok(())
+}
+
+/// Prefix of the comments explaining why an edit was rejected
+const ERROR_PREFIX: &str = "# error: ";
+
+fn edit(args: cli::Edit, out: &Output) -> anyhow::Result<()> {
+    let mut songs = Vec::new();
+    for path in args.paths {
+        songs.append(&mut get_songs(path)?);
+    }
+    let document = TagDocument::read(&songs)?;
+    // removed when dropped, created only readable by us under a name no one else can guess
+    let file = tempfile::Builder::new()
+        .prefix("songman-edit-")
+        .suffix(".toml")
+        .tempfile()?
+        .into_temp_path();
+    let mut text = document.to_toml();
+    // reopen the editor with the error on top until the document is valid or emptied
+    let transactions = loop {
+        std::fs::write(&file, &text)?;
+        let edited = run_editor(&file).and_then(|_| Ok(std::fs::read_to_string(&file)?));
+        let changes = edited.map(|edited| (document.changes(&edited), edited));
+        match changes {
+            Ok((Ok(Some(transactions)), _)) => break transactions,
+            Ok((Ok(None), _)) => {
+                out.status("Empty document, nothing changed");
+                return Ok(());
+            }
+            Ok((Err(e), edited)) => {
+                let errors: String = e
+                    .to_string()
+                    .lines()
+                    .map(|l| format!("{ERROR_PREFIX}{l}\n"))
+                    .collect();
+                let edited: Vec<&str> = edited
+                    .lines()
+                    .skip_while(|l| l.starts_with(ERROR_PREFIX))
+                    .collect();
+                text = format!("{errors}{}\n", edited.join("\n"));
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    file.close()?;
+    run_transactions(transactions, true, out)
+}
+
+fn run_editor(file: &Path) -> anyhow::Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // editors are often configured with arguments, such as `code --wait`
+    let mut words = editor.split_whitespace();
+    let Some(program) = words.next() else {
+        bail!("No editor set in $VISUAL or $EDITOR");
+    };
+    let status = Command::new(program).args(words).arg(file).status()?;
+    if !status.success() {
+        bail!("Editor '{editor}' exited with {status}");
+    }
+    Ok(())
+}
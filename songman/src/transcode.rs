@@ -0,0 +1,37 @@
+use music_manager::transcode::{Encoder, transcode_all, transcode_jobs};
+use tracing::error;
+
+use crate::{cli, config::get_songs, output::Output};
+
+pub fn transcode(args: cli::Transcode, out: &Output) -> anyhow::Result<()> {
+    let songs = get_songs(args.root.clone())?;
+    let jobs = transcode_jobs(
+        &args.dest,
+        &songs,
+        args.format,
+        &args.template,
+        &args.sanitize.into(),
+    )?;
+    let encoder = Encoder {
+        format: args.format,
+        bitrate: args.bitrate,
+        program: args.encoder,
+    };
+    let mut failed = 0;
+    out.section("jobs");
+    for (job, result) in transcode_all(&jobs, &encoder) {
+        out.item("jobs", job)?;
+        match result {
+            Ok(()) => out.status("Success"),
+            Err(e) => {
+                error!("{e}");
+                out.error(format!("{}: {e}", job.src.to_string_lossy()));
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} songs failed to transcode", jobs.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,153 @@
+//! Checks the JSON output of commands against the schema `songman schema` prints for them
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde_json::Value;
+
+/// A library of two tracks split from a CUE image, with its config kept to itself
+struct Library {
+    dir: tempfile::TempDir,
+}
+
+impl Library {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let library = Self { dir };
+        std::fs::create_dir(library.root()).unwrap();
+        write_wav(&library.root().join("image.wav"), 4);
+        std::fs::write(
+            library.root().join("image.cue"),
+            "PERFORMER \"Artist\"\nTITLE \"Album\"\nREM DATE 1999\n\
+             FILE \"image.wav\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"one\"\n    INDEX 01 00:00:00\n\
+             \x20 TRACK 02 AUDIO\n    TITLE \"two feat. Guest\"\n    INDEX 01 00:02:00\n",
+        )
+        .unwrap();
+        let image = library.root().join("image.cue");
+        library.check("cue split", &[path(&image)]);
+        library.songman(&["cue", "split", "--apply", path(&image)]);
+        // a copy for the duplicates to find
+        std::fs::copy(library.song(), library.root().join("copy.flac")).unwrap();
+        library
+    }
+
+    fn root(&self) -> PathBuf {
+        self.dir.path().join("library")
+    }
+
+    fn song(&self) -> PathBuf {
+        self.root().join("01 - one.flac")
+    }
+
+    fn songman(&self, args: &[&str]) -> Vec<u8> {
+        let output = Command::new(env!("CARGO_BIN_EXE_songman"))
+            .args(args)
+            .env("XDG_CONFIG_HOME", self.dir.path().join("config"))
+            .env("HOME", self.dir.path())
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "songman {args:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output.stdout
+    }
+
+    /// Run `command` with `args` in JSON and NDJSON, checking each against its schema
+    fn check(&self, command: &str, args: &[&str]) {
+        let command: Vec<&str> = command.split(' ').collect();
+        let args = [&command[..], args].concat();
+
+        let schema = self.schema(&command, false);
+        let document = self.songman(&[&["--json"], &args[..]].concat());
+        let document: Value = serde_json::from_slice(&document)
+            .unwrap_or_else(|e| panic!("{args:?}: {e}: {}", String::from_utf8_lossy(&document)));
+        assert_valid(&schema, &document, &args);
+        assert!(!document["results"].is_null(), "{args:?}: {document}");
+
+        let schema = self.schema(&command, true);
+        let records = self.songman(&[&["--ndjson"], &args[..]].concat());
+        for line in String::from_utf8(records).unwrap().lines() {
+            assert_valid(&schema, &serde_json::from_str(line).unwrap(), &args);
+        }
+    }
+
+    fn schema(&self, command: &[&str], ndjson: bool) -> jsonschema::Validator {
+        let mut args = vec!["schema"];
+        args.extend(ndjson.then_some("--ndjson"));
+        args.extend(command);
+        let schema: Value = serde_json::from_slice(&self.songman(&args)).unwrap();
+        jsonschema::validator_for(&schema).unwrap()
+    }
+}
+
+fn assert_valid(schema: &jsonschema::Validator, instance: &Value, args: &[&str]) {
+    let errors: Vec<String> = schema
+        .iter_errors(instance)
+        .map(|e| format!("{e} at {}", e.instance_path()))
+        .collect();
+    assert!(
+        errors.is_empty(),
+        "songman {args:?}: {errors:#?}\n{instance:#}"
+    );
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+/// Write `seconds` of a stereo tone as 16 bit WAV
+fn write_wav(path: &Path, seconds: u32) {
+    let (channels, rate) = (2u16, 44100u32);
+    let frames = seconds * rate;
+    let data_len = frames * u32::from(channels) * 2;
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&rate.to_le_bytes());
+    wav.extend_from_slice(&(rate * u32::from(channels) * 2).to_le_bytes());
+    wav.extend_from_slice(&(channels * 2).to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for i in 0..frames {
+        let phase = f64::from(i) * 440.0 * std::f64::consts::TAU / f64::from(rate);
+        let sample = (phase.sin() * 8000.0) as i16;
+        for _ in 0..channels {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+    }
+    std::fs::write(path, wav).unwrap();
+}
+
+#[test]
+fn outputs_match_their_schemas() {
+    let library = Library::new();
+    let (root, song) = (library.root(), library.song());
+    let (root, song) = (path(&root), path(&song));
+    let device = library.dir.path().join("device");
+    let dest = library.dir.path().join("sorted");
+    for (command, args) in [
+        ("stats", vec![root]),
+        ("info", vec![song]),
+        ("hash", vec![song]),
+        ("ls", vec![root]),
+        ("detect-dupe", vec!["--stream", "--metadata", root]),
+        ("sort", vec!["--skip-existing", "--dest", path(&dest), root]),
+        ("lint", vec![root]),
+        ("loudness", vec!["--write", "replaygain", root]),
+        ("analyze", vec![root]),
+        ("tag normalize", vec![root]),
+        ("sync", vec![root, path(&device)]),
+        ("config show", vec![root]),
+    ] {
+        library.check(command, &args);
+    }
+}